@@ -94,8 +94,8 @@ impl Game for Demo {
     // resolution is passed in as arguments.width and arguments.height if you would like to use
     // those. State can't be changed in here.
     fn draw(&self, _arguments: &DrawArguments) -> Image {
-        // Create a new image:
-        let mut display = Image::new(self.width, self.height, Color::new(0.2, 0.2, 0.2, 1.0));
+        // Get an image to draw on, romy::image() reuses the memory of previous frames:
+        let mut display = image(self.width, self.height, Color::new(0.2, 0.2, 0.2, 1.0));
 
         // Display our hero:
         display.set_pixel(self.x, self.y, Color::new(1.0, 1.0, 1.0, 1.0));
@@ -106,6 +106,8 @@ impl Game for Demo {
     // we are expected to supply enough audio for the duration of a step, state can't be changed 
     // in here.
     fn render_audio(&self, _arguments: &RenderAudioArguments) -> Sound {
-        self.sound.clone()
+        // Like romy::image(), romy::sound_copy() reuses the memory of sounds from previous steps
+        // instead of allocating a new one every time:
+        sound_copy(&self.sound)
     }
 }
\ No newline at end of file
@@ -1,3 +1,4 @@
+use super::math::Vec2;
 use super::*;
 use serde_derive::{Deserialize, Serialize};
 
@@ -71,6 +72,17 @@ impl InputCombine for InputDevice {
     }
 }
 
+impl InputDevice {
+    /// Position of this type of device in the canonical ordering used by InputCollection
+    fn order(&self) -> u8 {
+        match self {
+            InputDevice::Nes(_) => 0,
+            InputDevice::Controller(_) => 1,
+            InputDevice::Keyboard(_) => 2,
+        }
+    }
+}
+
 impl InputConvert for InputDevice {
     fn convert(&self, device_type: InputDeviceType) -> Option<InputDevice> {
         match self {
@@ -92,15 +104,25 @@ impl InputConvert for InputDevice {
     }
 }
 
+/// An input device along with the id of the physical device it came from, if known
+type IdentifiedInput = (InputDevice, Option<u32>);
+
 /// Collection of many inputs
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub struct InputCollection {
     inputs: Vec<InputDevice>,
+    // Same length as inputs:
+    device_ids: Vec<Option<u32>>,
+    devices_changed: bool,
 }
 
 impl InputCollection {
     pub fn new() -> Self {
-        Self { inputs: Vec::new() }
+        Self {
+            inputs: Vec::new(),
+            device_ids: Vec::new(),
+            devices_changed: false,
+        }
     }
 
     /// Add a new input to the collection
@@ -108,42 +130,88 @@ impl InputCollection {
     /// # Arguments
     /// * `device` - Device to add
     pub fn add_input(&mut self, device: InputDevice) {
-        self.inputs.push(device)
+        self.inputs.push(device);
+        self.device_ids.push(None);
+    }
+
+    /// Add a new input to the collection along with an id for the physical device it came from,
+    /// the id is passed on to the game via PlayerInputArguments::device_id()
+    ///
+    /// # Arguments
+    /// * `device` - Device to add
+    /// * `device_id` - Id that stays the same while the device is connected, like SDL's
+    ///   controller instance id or a browser gamepad index
+    pub fn add_input_with_id(&mut self, device: InputDevice, device_id: u32) {
+        self.inputs.push(device);
+        self.device_ids.push(Some(device_id));
     }
 
-    /// Distribute all of the inputs in the collection amongst all of the players mentioned in the
-    /// info argument and return a InputArgument suitable for passing to Game::Step()
+    /// Mark that an input device was connected or disconnected since the last step, this is
+    /// passed on to the game via InputArguments::devices_changed()
+    ///
+    /// # Arguments
+    /// * `devices_changed` - If a device was connected or disconnected
+    pub fn set_devices_changed(&mut self, devices_changed: bool) {
+        self.devices_changed = devices_changed;
+    }
+
+    /// Forgets the key events of every keyboard in the collection, call this after a step so when
+    /// several steps run in one frame only the first sees each event
+    pub fn clear_key_events(&mut self) {
+        for input in &mut self.inputs {
+            if let InputDevice::Keyboard(keyboard) = input {
+                keyboard.clear_events();
+            }
+        }
+    }
+
+    /// Distribute all of the inputs in the collection amongst the players mentioned in the info
+    /// argument and return a InputArgument suitable for passing to Game::Step()
+    ///
+    /// There is a player for each device in the collection, but at least Info::min_players() and
+    /// at most Info::max_players(), players past the number of devices are None.
+    ///
+    /// Devices are put into a canonical order by type and then by device id before being
+    /// distributed, so the result doesn't depend on the order they were added in. Only devices of
+    /// the same type added without an id keep the order they were added in, runtimes should add
+    /// those in a stable order such as the order they connected.
     ///
     /// # Arguments
     /// * `info` - The game info
     pub fn get_input_arguments(&self, info: &Info) -> InputArguments {
+        let number_of_players = (self.inputs.len() as i32)
+            .max(info.min_players())
+            .min(info.max_players());
         let devices: Vec<InputDeviceType> = info
             .players
             .iter()
+            .take(number_of_players as usize)
             .map(|player| player.input.clone())
             .collect();
 
-        let (dist, mut remaining) = self.split(&devices);
+        // sort_by_key is stable, so devices without an id stay in the order they were added:
+        let mut order: Vec<usize> = (0..self.inputs.len()).collect();
+        order.sort_by_key(|&index| (self.inputs[index].order(), self.device_ids[index]));
+
+        let (dist, mut remaining) = self.split(&order, &devices);
         let mut result: Vec<Option<PlayerInputArguments>> = dist
-            .iter()
+            .into_iter()
             .map(|input| match input {
-                Some(input) => Some(PlayerInputArguments {
-                    input: input.clone(),
-                }),
+                Some((input, device_id)) => Some(PlayerInputArguments { input, device_id }),
                 None => None,
             })
             .collect();
 
         //TODO: HORRID LOOP TO COMBINE ALL POSSIBLE INPUTS:
         loop {
-            let (new_dist, new_remaining) = remaining.split(&devices);
-            if new_remaining.inputs.len() == remaining.inputs.len() {
+            let (new_dist, new_remaining) = self.split(&remaining, &devices);
+            if new_remaining.len() == remaining.len() {
                 break;
             }
 
             for (result_index, result_player) in result.iter_mut().enumerate() {
                 if let Some(player) = result_player {
-                    if let Some(device) = &new_dist[result_index] {
+                    if let Some((device, _)) = &new_dist[result_index] {
                         player.input = player.input.combine(device);
                     }
                 }
@@ -152,25 +220,50 @@ impl InputCollection {
             remaining = new_remaining;
         }
 
-        InputArguments::new(result)
+        InputArguments::new(result, self.devices_changed)
     }
 
-    /// Splits this collection up into into separate inputs
+    /// Splits some of the inputs in this collection up into separate inputs
     ///
     /// # Arguments
+    /// * `indices` - Indices of the inputs to split, in the order they are handed out
     /// * `into` - a slice of inputs type to split into.
-    /// Returns a tuple with the split inputs and a collection of remaining ones.
-    fn split(&self, into: &[InputDeviceType]) -> (Vec<Option<InputDevice>>, InputCollection) {
-        let mut remaining = self.inputs.clone();
-        let mut found = Vec::new();
+    ///
+    /// Returns a tuple with the split inputs along with their device ids and the indices of the
+    /// remaining ones.
+    fn split(
+        &self,
+        indices: &[usize],
+        into: &[InputDeviceType],
+    ) -> (Vec<Option<IdentifiedInput>>, Vec<usize>) {
+        let mut remaining = indices.to_vec();
+        let mut found: Vec<Option<IdentifiedInput>> = into.iter().map(|_| None).collect();
+
+        // First give every slot a device that is natively the right type if there is one, so a
+        // real device isn't taken by an earlier slot that could have used a converted one:
+        for (slot, input_type) in into.iter().enumerate() {
+            let native = remaining
+                .iter()
+                .position(|&index| self.inputs[index].affinity(input_type.clone()) == Some(0));
+            if let Some(position) = native {
+                let index = remaining.remove(position);
+                found[slot] = self.inputs[index]
+                    .convert(input_type.clone())
+                    .map(|input| (input, self.device_ids[index]));
+            }
+        }
 
-        //TODO: PREFER DEVICES THAT ARE THE RIGHT TYPE:
+        // Then fill any empty slots with the closest match from what's left:
+        for (slot, input_type) in into.iter().enumerate() {
+            if found[slot].is_some() {
+                continue;
+            }
 
-        for input_type in into {
-            let mut found_index = None;
+            let mut found_position = None;
             let mut found_affinity = None;
             let mut found_for = None;
-            for (index, input) in remaining.iter().enumerate() {
+            for (position, &index) in remaining.iter().enumerate() {
+                let input = &self.inputs[index];
                 let affinity = input.affinity(input_type.clone());
                 match affinity {
                     Some(affinity) => {
@@ -183,7 +276,7 @@ impl InputCollection {
                         let found_new = input.convert(input_type.clone());
                         if let Some(found_new) = found_new {
                             found_affinity = Some(affinity);
-                            found_index = Some(index);
+                            found_position = Some(position);
                             found_for = Some(found_new);
                         }
                     }
@@ -191,14 +284,55 @@ impl InputCollection {
                 }
             }
 
-            found.push(found_for);
-
-            if let Some(index) = found_index {
-                remaining.remove(index);
+            if let (Some(position), Some(input)) = (found_position, found_for) {
+                let index = remaining.remove(position);
+                found[slot] = Some((input, self.device_ids[index]));
             }
         }
 
-        (found, InputCollection { inputs: remaining })
+        (found, remaining)
+    }
+}
+
+/// Remembers which players have had an input device, so InputArguments can tell games when a
+/// player's device was disconnected rather than never being there. Runtimes should keep one of
+/// these for as long as a game is running.
+#[derive(Default, Clone)]
+pub struct PlayerPresence {
+    seen: Vec<bool>,
+}
+
+impl PlayerPresence {
+    pub fn new() -> Self {
+        Self { seen: Vec::new() }
+    }
+
+    /// Record which players have a device in the arguments and mark any that have lost theirs
+    ///
+    /// # Arguments
+    /// * `arguments` - Arguments built by InputCollection::get_input_arguments()
+    pub fn update(&mut self, mut arguments: InputArguments) -> InputArguments {
+        if self.seen.len() < arguments.players.len() {
+            self.seen.resize(arguments.players.len(), false);
+        }
+        // Players that dropped out when the number of players shrank are still reported:
+        if arguments.players.len() < self.seen.len() {
+            arguments.players.resize_with(self.seen.len(), || None);
+        }
+
+        arguments.disconnected = arguments
+            .players
+            .iter()
+            .zip(self.seen.iter_mut())
+            .map(|(player, seen)| {
+                if player.is_some() {
+                    *seen = true;
+                }
+                player.is_none() && *seen
+            })
+            .collect();
+
+        arguments
     }
 }
 
@@ -244,7 +378,277 @@ impl InputCombine for InputCollection {
     fn combine(&self, with: &Self) -> Self {
         let mut inputs = self.inputs.clone();
         inputs.extend(with.inputs.clone());
-        Self { inputs }
+        let mut device_ids = self.device_ids.clone();
+        device_ids.extend(with.device_ids.clone());
+        Self {
+            inputs,
+            device_ids,
+            devices_changed: self.devices_changed || with.devices_changed,
+        }
+    }
+}
+
+/// A digital button on a Nes or Controller
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    A,
+    B,
+    X,
+    Y,
+    Up,
+    Down,
+    Left,
+    Right,
+    Start,
+    Select,
+    Guide,
+    LeftShoulder,
+    RightShoulder,
+    LeftStick,
+    RightStick,
+}
+
+impl Button {
+    /// Every button, in order
+    pub const ALL: [Button; 15] = [
+        Button::A,
+        Button::B,
+        Button::X,
+        Button::Y,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+        Button::Start,
+        Button::Select,
+        Button::Guide,
+        Button::LeftShoulder,
+        Button::RightShoulder,
+        Button::LeftStick,
+        Button::RightStick,
+    ];
+
+    /// Gets the name of the button in snake case, for example "left_shoulder"
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::A => "a",
+            Button::B => "b",
+            Button::X => "x",
+            Button::Y => "y",
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::Left => "left",
+            Button::Right => "right",
+            Button::Start => "start",
+            Button::Select => "select",
+            Button::Guide => "guide",
+            Button::LeftShoulder => "left_shoulder",
+            Button::RightShoulder => "right_shoulder",
+            Button::LeftStick => "left_stick",
+            Button::RightStick => "right_stick",
+        }
+    }
+
+    /// Gets a button from its name, as returned by name()
+    ///
+    /// # Arguments
+    /// * `name` - The name of the button
+    pub fn from_name(name: &str) -> Option<Button> {
+        Button::ALL.iter().cloned().find(|button| button.name() == name)
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// Which physical gamepad button is read for each Controller button, so players can fix gamepads
+/// the runtime maps wrongly or move buttons around to suit them. Physical buttons are numbered by
+/// the runtime, which also supplies the default table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonRemap {
+    physical: [u32; 15],
+}
+
+impl ButtonRemap {
+    /// Create a remap table
+    ///
+    /// # Arguments
+    /// * `physical` - The physical button read for each button, in the order of Button::ALL
+    pub fn new(physical: [u32; 15]) -> Self {
+        Self { physical }
+    }
+
+    /// Gets the physical button read for a button
+    ///
+    /// # Arguments
+    /// * `button` - The Controller button
+    pub fn physical(&self, button: Button) -> u32 {
+        self.physical[button as usize]
+    }
+
+    /// Changes the physical button read for a button
+    ///
+    /// # Arguments
+    /// * `button` - The Controller button
+    /// * `physical` - The physical button to read for it
+    pub fn set(&mut self, button: Button, physical: u32) {
+        self.physical[button as usize] = physical;
+    }
+
+    /// Changes the table from a config written as button=physical pairs separated by commas or
+    /// new lines, for example "a=1, b=0". Button names are as returned by Button::name(), buttons
+    /// that aren't listed are left as they are. Nothing is changed if the config has a mistake.
+    ///
+    /// # Arguments
+    /// * `config` - The pairs to apply
+    pub fn apply(&mut self, config: &str) -> Result<(), String> {
+        let mut remap = *self;
+        let pairs = config
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty());
+        for pair in pairs {
+            let mut parts = pair.splitn(2, '=').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let button =
+                Button::from_name(name).ok_or_else(|| format!("unknown button: {}", name))?;
+            let physical = parts
+                .next()
+                .and_then(|physical| physical.parse().ok())
+                .ok_or_else(|| format!("expected button=number: {}", pair))?;
+            remap.set(button, physical);
+        }
+
+        *self = remap;
+        Ok(())
+    }
+}
+
+/// Which analog stick axes are negated before they reach the game, for players that prefer an
+/// inverted Y axis or gamepads that report an axis backwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AxisInversion {
+    pub left_stick_x: bool,
+    pub left_stick_y: bool,
+    pub right_stick_x: bool,
+    pub right_stick_y: bool,
+}
+
+impl AxisInversion {
+    /// Reads a config listing the axes to invert, separated by commas, spaces or new lines, for
+    /// example "left_stick_y, right_stick_y". Axes are named like the Controller methods that read
+    /// them.
+    ///
+    /// # Arguments
+    /// * `config` - The axis names
+    pub fn parse(config: &str) -> Result<Self, String> {
+        let mut inversion = Self::default();
+        let names = config
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|name| !name.is_empty());
+        for name in names {
+            match name {
+                "left_stick_x" => inversion.left_stick_x = true,
+                "left_stick_y" => inversion.left_stick_y = true,
+                "right_stick_x" => inversion.right_stick_x = true,
+                "right_stick_y" => inversion.right_stick_y = true,
+                _ => return Err(format!("unknown axis: {}", name)),
+            }
+        }
+
+        Ok(inversion)
+    }
+
+    /// Negates the inverted axes of a controller before it is created
+    ///
+    /// # Arguments
+    /// * `init` - The controller state read from the gamepad
+    pub fn apply(&self, init: &mut ControllerInit) {
+        let invert = |value: &mut f32, inverted| {
+            if inverted {
+                *value = -*value;
+            }
+        };
+        invert(&mut init.left_stick_x, self.left_stick_x);
+        invert(&mut init.left_stick_y, self.left_stick_y);
+        invert(&mut init.right_stick_x, self.right_stick_x);
+        invert(&mut init.right_stick_y, self.right_stick_y);
+    }
+}
+
+/// A set of buttons, stored as bit flags
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ButtonSet {
+    bits: u32,
+}
+
+impl ButtonSet {
+    /// Create an empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a button to the set
+    ///
+    /// # Arguments
+    /// * `button` - The button to add
+    pub fn insert(&mut self, button: Button) {
+        self.bits |= button.bit();
+    }
+
+    /// Removes a button from the set
+    ///
+    /// # Arguments
+    /// * `button` - The button to remove
+    pub fn remove(&mut self, button: Button) {
+        self.bits &= !button.bit();
+    }
+
+    /// Is a button in the set
+    ///
+    /// # Arguments
+    /// * `button` - The button to look for
+    pub fn contains(&self, button: Button) -> bool {
+        self.bits & button.bit() != 0
+    }
+
+    /// Is the set empty
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Gets the number of buttons in the set
+    pub fn len(&self) -> usize {
+        self.bits.count_ones() as usize
+    }
+
+    /// Gets the buttons that are in both this set and another one
+    ///
+    /// # Arguments
+    /// * `other` - The other set
+    pub fn intersection(&self, other: &ButtonSet) -> ButtonSet {
+        ButtonSet {
+            bits: self.bits & other.bits,
+        }
+    }
+
+    /// Gets an iterator over all of the buttons in the set
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = Button> + 'a {
+        Button::ALL
+            .iter()
+            .cloned()
+            .filter(move |button| self.contains(*button))
+    }
+
+    fn from_states(states: &[(Button, bool)]) -> Self {
+        let mut set = Self::new();
+        for (button, down) in states {
+            if *down {
+                set.insert(*button);
+            }
+        }
+        set
     }
 }
 
@@ -303,6 +707,72 @@ impl Nes {
         self.select
     }
 
+    /// Gets the set of buttons that are down
+    pub fn buttons(&self) -> ButtonSet {
+        ButtonSet::from_states(&[
+            (Button::A, self.a),
+            (Button::B, self.b),
+            (Button::Up, self.up),
+            (Button::Down, self.down),
+            (Button::Left, self.left),
+            (Button::Right, self.right),
+            (Button::Start, self.start),
+            (Button::Select, self.select),
+        ])
+    }
+
+    /// Gets the set of buttons that have been pressed or released since a previous state,
+    /// intersect this with buttons() to get only the newly pressed ones.
+    ///
+    /// # Arguments
+    /// * `previous` - The earlier state to compare against
+    pub fn changed_buttons(&self, previous: &Nes) -> ButtonSet {
+        ButtonSet {
+            bits: self.buttons().bits ^ previous.buttons().bits,
+        }
+    }
+
+    /// Is any button down, for "press any button" prompts
+    pub fn any_pressed(&self) -> bool {
+        !self.buttons().is_empty()
+    }
+
+    /// Packs the buttons into a byte, for recording input or sending it over a network. From the
+    /// lowest bit up they are a, b, up, down, left, right, start and select.
+    pub fn to_bits(&self) -> u8 {
+        [
+            self.a,
+            self.b,
+            self.up,
+            self.down,
+            self.left,
+            self.right,
+            self.start,
+            self.select,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (bit, down)| bits | (u8::from(*down) << bit))
+    }
+
+    /// Unpacks buttons packed by to_bits()
+    ///
+    /// # Arguments
+    /// * `bits` - The packed buttons
+    pub fn from_bits(bits: u8) -> Self {
+        let down = |bit: u8| bits & (1 << bit) != 0;
+        Self {
+            a: down(0),
+            b: down(1),
+            up: down(2),
+            down: down(3),
+            left: down(4),
+            right: down(5),
+            start: down(6),
+            select: down(7),
+        }
+    }
+
     /// Sets the state of the a button
     pub fn set_a(&mut self, value: bool) {
         self.a = value;
@@ -374,6 +844,11 @@ impl InputCombine for Nes {
     }
 }
 
+/// How far a trigger needs to be pressed, from 0 to 1, before it counts as a button press. Used by
+/// Controller::left_trigger_pressed() and right_trigger_pressed() so games get the same feel on
+/// every runtime.
+pub const TRIGGER_THRESHOLD: f32 = 0.5;
+
 /// A standard controller, similar to one used for a XBox 360
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub struct Controller {
@@ -548,16 +1023,140 @@ impl Controller {
         self.right_stick_y
     }
 
-    /// Left trigger position [0 - +1] +1 fully down
+    /// Left stick position as a vector, see left_stick_x() and left_stick_y()
+    pub fn left_stick_position(&self) -> Vec2 {
+        Vec2::new(self.left_stick_x, self.left_stick_y)
+    }
+
+    /// Right stick position as a vector, see right_stick_x() and right_stick_y()
+    pub fn right_stick_position(&self) -> Vec2 {
+        Vec2::new(self.right_stick_x, self.right_stick_y)
+    }
+
+    /// Left trigger position [0 - +1] +1 fully down. Runtimes report analog pressure where the
+    /// hardware has it, digital triggers are always either 0 or +1.
     pub fn left_trigger(&self) -> f32 {
         self.left_trigger
     }
 
-    /// Right trigger position [0 - +1] +1 fully down
+    /// Right trigger position [0 - +1] +1 fully down. Runtimes report analog pressure where the
+    /// hardware has it, digital triggers are always either 0 or +1.
     pub fn right_trigger(&self) -> f32 {
         self.right_trigger
     }
 
+    /// Is the left trigger pressed further than TRIGGER_THRESHOLD, for treating it as a button
+    pub fn left_trigger_pressed(&self) -> bool {
+        self.left_trigger >= TRIGGER_THRESHOLD
+    }
+
+    /// Is the right trigger pressed further than TRIGGER_THRESHOLD, for treating it as a button
+    pub fn right_trigger_pressed(&self) -> bool {
+        self.right_trigger >= TRIGGER_THRESHOLD
+    }
+
+    /// Gets the set of buttons that are down, analog sticks and triggers are not included
+    pub fn buttons(&self) -> ButtonSet {
+        ButtonSet::from_states(&[
+            (Button::A, self.a),
+            (Button::B, self.b),
+            (Button::X, self.x),
+            (Button::Y, self.y),
+            (Button::Up, self.up),
+            (Button::Down, self.down),
+            (Button::Left, self.left),
+            (Button::Right, self.right),
+            (Button::Start, self.start),
+            (Button::Select, self.select),
+            (Button::Guide, self.guide),
+            (Button::LeftShoulder, self.left_shoulder),
+            (Button::RightShoulder, self.right_shoulder),
+            (Button::LeftStick, self.left_stick),
+            (Button::RightStick, self.right_stick),
+        ])
+    }
+
+    /// Gets the set of buttons that have been pressed or released since a previous state,
+    /// intersect this with buttons() to get only the newly pressed ones.
+    ///
+    /// # Arguments
+    /// * `previous` - The earlier state to compare against
+    pub fn changed_buttons(&self, previous: &Controller) -> ButtonSet {
+        ButtonSet {
+            bits: self.buttons().bits ^ previous.buttons().bits,
+        }
+    }
+
+    /// Is any button down, or any stick or trigger pushed past a threshold, for "press any button"
+    /// prompts
+    ///
+    /// # Arguments
+    /// * `threshold` - How far, from 0 to 1, a stick or trigger needs to move to count, some
+    ///   leeway stops worn sticks that don't quite center from counting
+    pub fn any_pressed(&self, threshold: f32) -> bool {
+        let axes = [
+            self.left_stick_x,
+            self.left_stick_y,
+            self.right_stick_x,
+            self.right_stick_y,
+            self.left_trigger,
+            self.right_trigger,
+        ];
+        !self.buttons().is_empty() || axes.iter().any(|axis| axis.abs() > threshold)
+    }
+
+    /// Packs the controller into 8 bytes, for recording input or sending it over a network. The
+    /// buttons are kept exactly but sticks and triggers are rounded to 255 steps.
+    pub fn to_packed(&self) -> PackedController {
+        let stick = |value: f32| (value.clamp(-1.0, 1.0) * 127.0).round() as i8;
+        let trigger = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        PackedController {
+            buttons: self.buttons().bits as u16,
+            sticks: [
+                stick(self.left_stick_x),
+                stick(self.left_stick_y),
+                stick(self.right_stick_x),
+                stick(self.right_stick_y),
+            ],
+            triggers: [trigger(self.left_trigger), trigger(self.right_trigger)],
+        }
+    }
+
+    /// Unpacks a controller packed by to_packed()
+    ///
+    /// # Arguments
+    /// * `packed` - The packed controller
+    pub fn from_packed(packed: &PackedController) -> Self {
+        let buttons = ButtonSet {
+            bits: u32::from(packed.buttons),
+        };
+        let stick = |value: i8| f32::from(value) / 127.0;
+        let trigger = |value: u8| f32::from(value) / 255.0;
+        Self::new(ControllerInit {
+            a: buttons.contains(Button::A),
+            b: buttons.contains(Button::B),
+            x: buttons.contains(Button::X),
+            y: buttons.contains(Button::Y),
+            up: buttons.contains(Button::Up),
+            down: buttons.contains(Button::Down),
+            left: buttons.contains(Button::Left),
+            right: buttons.contains(Button::Right),
+            start: buttons.contains(Button::Start),
+            select: buttons.contains(Button::Select),
+            guide: buttons.contains(Button::Guide),
+            left_shoulder: buttons.contains(Button::LeftShoulder),
+            right_shoulder: buttons.contains(Button::RightShoulder),
+            left_stick: buttons.contains(Button::LeftStick),
+            right_stick: buttons.contains(Button::RightStick),
+            left_stick_x: stick(packed.sticks[0]),
+            left_stick_y: stick(packed.sticks[1]),
+            right_stick_x: stick(packed.sticks[2]),
+            right_stick_y: stick(packed.sticks[3]),
+            left_trigger: trigger(packed.triggers[0]),
+            right_trigger: trigger(packed.triggers[1]),
+        })
+    }
+
     /// Sets the state of the a button
     pub fn set_a(&mut self, value: bool) {
         self.a = value;
@@ -664,6 +1263,18 @@ impl Controller {
     }
 }
 
+/// A Controller packed into 8 bytes by Controller::to_packed(), a fraction of the size of a
+/// serialized Controller
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PackedController {
+    // Bits of the buttons in a ButtonSet:
+    buttons: u16,
+    // Left x, left y, right x and right y, from -127 to 127:
+    sticks: [i8; 4],
+    // Left and right, from 0 to 255:
+    triggers: [u8; 2],
+}
+
 impl InputConvert for Controller {
     fn convert(&self, device_type: InputDeviceType) -> Option<InputDevice> {
         match device_type {
@@ -726,28 +1337,181 @@ impl Controller {
     }
 }
 
+/// Table of which keys map to each button when a Keyboard is converted to a Nes controller, any
+/// key in a buttons list will press that button.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KeyBindings {
+    pub a: Vec<KeyCode>,
+    pub b: Vec<KeyCode>,
+    pub up: Vec<KeyCode>,
+    pub down: Vec<KeyCode>,
+    pub left: Vec<KeyCode>,
+    pub right: Vec<KeyCode>,
+    pub start: Vec<KeyCode>,
+    pub select: Vec<KeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            a: vec![KeyCode::K, KeyCode::X, KeyCode::J],
+            b: vec![KeyCode::J, KeyCode::Z, KeyCode::N],
+            up: vec![KeyCode::W, KeyCode::Up, KeyCode::F],
+            down: vec![KeyCode::S, KeyCode::Down],
+            left: vec![KeyCode::A, KeyCode::Left, KeyCode::R],
+            right: vec![KeyCode::D, KeyCode::Right, KeyCode::T],
+            start: vec![KeyCode::Enter],
+            select: vec![KeyCode::Tab],
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Bindings for the player on the left of a keyboard shared by two players, see
+    /// Keyboard::split(). WASD moves, g is a, f is b, e is start and q is select.
+    pub fn left_hand() -> Self {
+        Self {
+            a: vec![KeyCode::G],
+            b: vec![KeyCode::F],
+            up: vec![KeyCode::W],
+            down: vec![KeyCode::S],
+            left: vec![KeyCode::A],
+            right: vec![KeyCode::D],
+            start: vec![KeyCode::E],
+            select: vec![KeyCode::Q],
+        }
+    }
+
+    /// Bindings for the player on the right of a keyboard shared by two players, see
+    /// Keyboard::split(). The arrow keys move, period is a, comma is b, enter is start and slash is
+    /// select.
+    pub fn right_hand() -> Self {
+        Self {
+            a: vec![KeyCode::Period],
+            b: vec![KeyCode::Comma],
+            up: vec![KeyCode::Up],
+            down: vec![KeyCode::Down],
+            left: vec![KeyCode::Left],
+            right: vec![KeyCode::Right],
+            start: vec![KeyCode::Enter],
+            select: vec![KeyCode::Slash],
+        }
+    }
+
+    /// Changes the bindings from a config written as button=keys pairs separated by commas or new
+    /// lines, with the keys separated by spaces, for example "a=k x, start=enter tab". Buttons
+    /// are named like the Nes methods that read them and keys as in KeyCode::from_name(), buttons
+    /// that aren't listed keep their keys. Nothing is changed if the config has a mistake.
+    ///
+    /// # Arguments
+    /// * `config` - The pairs to apply
+    pub fn apply(&mut self, config: &str) -> Result<(), String> {
+        let mut bindings = self.clone();
+        let pairs = config
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty());
+        for pair in pairs {
+            let mut parts = pair.splitn(2, '=').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let keys = parts
+                .next()
+                .ok_or_else(|| format!("expected button=keys: {}", pair))?
+                .split_whitespace()
+                .map(|key| KeyCode::from_name(key).ok_or_else(|| format!("unknown key: {}", key)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let button = match name {
+                "a" => &mut bindings.a,
+                "b" => &mut bindings.b,
+                "up" => &mut bindings.up,
+                "down" => &mut bindings.down,
+                "left" => &mut bindings.left,
+                "right" => &mut bindings.right,
+                "start" => &mut bindings.start,
+                "select" => &mut bindings.select,
+                _ => return Err(format!("unknown button: {}", name)),
+            };
+            *button = keys;
+        }
+
+        *self = bindings;
+        Ok(())
+    }
+
+    /// Is a key bound to any of the buttons
+    ///
+    /// # Arguments
+    /// * `key` - The scan code of the key
+    pub fn contains(&self, key: KeyCode) -> bool {
+        [
+            &self.a,
+            &self.b,
+            &self.up,
+            &self.down,
+            &self.left,
+            &self.right,
+            &self.start,
+            &self.select,
+        ]
+        .iter()
+        .any(|keys| keys.contains(&key))
+    }
+}
+
 /// A input for a computer keyboard
 #[derive(Serialize, Deserialize, Clone, Default)]
 pub struct Keyboard {
     pressed: Vec<Key>,
+    events: Vec<KeyEvent>,
+    #[serde(skip)]
+    bindings: KeyBindings,
 }
 
 impl Keyboard {
-    /// Add a key down state
+    /// Add a key down state, records a KeyEvent::Pressed unless the key was already down, so
+    /// auto repeat from the OS doesn't show up as extra presses
     ///
     /// # Arguments
     /// * `key` - the key that is down
     pub fn key_down(&mut self, key: Key) {
-        self.key_up(key.scan_code);
+        if !self.is_down_scan(key.scan_code) {
+            self.events.push(KeyEvent::Pressed(key));
+        }
+        self.pressed.retain(|pressed| pressed.scan_code != key.scan_code);
         self.pressed.push(key);
     }
 
-    /// Return a key to the up state
+    /// Return a key to the up state, records a KeyEvent::Released if the key was down
     ///
     /// # Arguments
     /// * `scan_code` - the scan code for the key that is now up
     pub fn key_up(&mut self, scan_code: KeyCode) {
-        self.pressed.retain(|key| key.scan_code != scan_code);
+        if let Some(index) = self.pressed.iter().position(|key| key.scan_code == scan_code) {
+            let key = self.pressed.remove(index);
+            self.events.push(KeyEvent::Released(key));
+        }
+    }
+
+    /// Gets the keys that were pressed or released since the last step, in the order it happened.
+    /// Unlike is_down_scan() a key held down only shows up once, which suits menus and text entry.
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    /// Forgets the events recorded so far, runtimes call this once they have been passed to a step
+    pub fn clear_events(&mut self) {
+        self.events.clear();
+    }
+
+    /// Is any key down, for "press any key" prompts
+    pub fn any_pressed(&self) -> bool {
+        !self.pressed.is_empty()
+    }
+
+    /// Gets every key that is currently down, in the order they were pressed, for things like
+    /// "press the key you want to bind" screens
+    pub fn held_keys(&self) -> &[Key] {
+        &self.pressed
     }
 
     /// Get the pressed state of a key via its scan code, this is not effected by the set locale
@@ -778,26 +1542,65 @@ impl Keyboard {
         false
     }
 
-    fn to_nes(&self) -> Nes {
+    /// Gets the bindings used when converting this keyboard to a Nes controller
+    pub fn bindings(&self) -> &KeyBindings {
+        &self.bindings
+    }
+
+    /// Sets the bindings used when converting this keyboard to a Nes controller
+    ///
+    /// # Arguments
+    /// * `bindings` - the new bindings
+    pub fn set_bindings(&mut self, bindings: KeyBindings) {
+        self.bindings = bindings;
+    }
+
+    /// Splits this keyboard into one for each set of bindings, so several players can share it.
+    /// Each keyboard only has the keys and events from its own bindings, and uses those bindings
+    /// when converted to a Nes controller.
+    ///
+    /// # Arguments
+    /// * `bindings` - The bindings of each player, like KeyBindings::left_hand() and
+    ///   KeyBindings::right_hand()
+    pub fn split(&self, bindings: &[KeyBindings]) -> Vec<Keyboard> {
+        bindings
+            .iter()
+            .map(|bindings| Keyboard {
+                pressed: self
+                    .pressed
+                    .iter()
+                    .filter(|key| bindings.contains(key.scan_code))
+                    .cloned()
+                    .collect(),
+                events: self
+                    .events
+                    .iter()
+                    .filter(|event| match event {
+                        KeyEvent::Pressed(key) | KeyEvent::Released(key) => {
+                            bindings.contains(key.scan_code)
+                        }
+                    })
+                    .cloned()
+                    .collect(),
+                bindings: bindings.clone(),
+            })
+            .collect()
+    }
+
+    /// Converts this keyboard into a Nes controller using its key bindings
+    pub fn to_nes(&self) -> Nes {
+        let bindings = &self.bindings;
+        let any_down = |keys: &[KeyCode]| keys.iter().any(|key| self.is_down_scan(*key));
+
         Nes {
-            a: self.is_down_scan(KeyCode::K)
-                || self.is_down_scan(KeyCode::X)
-                || self.is_down_scan(KeyCode::J),
-            b: self.is_down_scan(KeyCode::J)
-                || self.is_down_scan(KeyCode::Z)
-                || self.is_down_scan(KeyCode::N),
-            up: self.is_down_scan(KeyCode::W)
-                || self.is_down_scan(KeyCode::Up)
-                || self.is_down_scan(KeyCode::F),
-            down: self.is_down_scan(KeyCode::S) || self.is_down_scan(KeyCode::Down),
-            left: self.is_down_scan(KeyCode::A)
-                || self.is_down_scan(KeyCode::Left)
-                || self.is_down_scan(KeyCode::R),
-            right: self.is_down_scan(KeyCode::D)
-                || self.is_down_scan(KeyCode::Right)
-                || self.is_down_scan(KeyCode::T),
-            start: self.is_down_scan(KeyCode::Enter),
-            select: self.is_down_scan(KeyCode::Tab),
+            a: any_down(&bindings.a),
+            b: any_down(&bindings.b),
+            up: any_down(&bindings.up),
+            down: any_down(&bindings.down),
+            left: any_down(&bindings.left),
+            right: any_down(&bindings.right),
+            start: any_down(&bindings.start),
+            select: any_down(&bindings.select),
         }
     }
 }
@@ -821,20 +1624,20 @@ impl InputConvert for Keyboard {
 
 impl InputCombine for Keyboard {
     fn combine(&self, with: &Self) -> Self {
-        let pressed = self.pressed.clone();
-
-        let mut result = Self { pressed };
+        let mut result = self.clone();
 
         for key in &with.pressed {
-            result.key_down(key.clone());
+            result.pressed.retain(|pressed| pressed.scan_code != key.scan_code);
+            result.pressed.push(*key);
         }
+        result.events.extend(with.events.iter().cloned());
 
         result
     }
 }
 
 /// A key that can be pressed by a Keyboard
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Key {
     scan_code: KeyCode,
     key_code: KeyCode,
@@ -852,10 +1655,29 @@ impl Key {
             key_code,
         }
     }
+
+    /// Gets the scan code of the key, not effected by the locale
+    pub fn scan_code(&self) -> KeyCode {
+        self.scan_code
+    }
+
+    /// Gets the key code of the key, effected by the locale
+    pub fn key_code(&self) -> KeyCode {
+        self.key_code
+    }
+}
+
+/// A key changing state, see Keyboard::events()
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyEvent {
+    /// The key went down
+    Pressed(Key),
+    /// The key came back up
+    Released(Key),
 }
 
 /// Key/scan codes
-#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum KeyCode {
     _1,
     _2,
@@ -908,3 +1730,475 @@ pub enum KeyCode {
     Semicolon,
     Quote,
 }
+
+/// Table of keys that produce characters, as (key, character, character with shift held), based
+/// on a US keyboard layout
+const KEY_CHARACTERS: [(KeyCode, char, char); 46] = [
+    (KeyCode::_1, '1', '!'),
+    (KeyCode::_2, '2', '@'),
+    (KeyCode::_3, '3', '#'),
+    (KeyCode::_4, '4', '$'),
+    (KeyCode::_5, '5', '%'),
+    (KeyCode::_6, '6', '^'),
+    (KeyCode::_7, '7', '&'),
+    (KeyCode::_8, '8', '*'),
+    (KeyCode::_9, '9', '('),
+    (KeyCode::_0, '0', ')'),
+    (KeyCode::A, 'a', 'A'),
+    (KeyCode::B, 'b', 'B'),
+    (KeyCode::C, 'c', 'C'),
+    (KeyCode::D, 'd', 'D'),
+    (KeyCode::E, 'e', 'E'),
+    (KeyCode::F, 'f', 'F'),
+    (KeyCode::G, 'g', 'G'),
+    (KeyCode::H, 'h', 'H'),
+    (KeyCode::I, 'i', 'I'),
+    (KeyCode::J, 'j', 'J'),
+    (KeyCode::K, 'k', 'K'),
+    (KeyCode::L, 'l', 'L'),
+    (KeyCode::M, 'm', 'M'),
+    (KeyCode::N, 'n', 'N'),
+    (KeyCode::O, 'o', 'O'),
+    (KeyCode::P, 'p', 'P'),
+    (KeyCode::Q, 'q', 'Q'),
+    (KeyCode::R, 'r', 'R'),
+    (KeyCode::S, 's', 'S'),
+    (KeyCode::T, 't', 'T'),
+    (KeyCode::U, 'u', 'U'),
+    (KeyCode::V, 'v', 'V'),
+    (KeyCode::W, 'w', 'W'),
+    (KeyCode::X, 'x', 'X'),
+    (KeyCode::Y, 'y', 'Y'),
+    (KeyCode::Z, 'z', 'Z'),
+    (KeyCode::Enter, '\n', '\n'),
+    (KeyCode::Tab, '\t', '\t'),
+    (KeyCode::LeftBracket, '[', '{'),
+    (KeyCode::RightBracket, ']', '}'),
+    (KeyCode::Slash, '/', '?'),
+    (KeyCode::Backslash, '\\', '|'),
+    (KeyCode::Comma, ',', '<'),
+    (KeyCode::Period, '.', '>'),
+    (KeyCode::Semicolon, ';', ':'),
+    (KeyCode::Quote, '\'', '"'),
+];
+
+impl KeyCode {
+    /// Gets the character this key types, based on a US keyboard layout. None for keys that
+    /// don't type anything, like the arrow keys.
+    ///
+    /// # Arguments
+    /// * `shift` - Is shift being held
+    pub fn to_char(self, shift: bool) -> Option<char> {
+        KEY_CHARACTERS
+            .iter()
+            .find(|(key, _, _)| *key == self)
+            .map(|(_, character, shifted)| if shift { *shifted } else { *character })
+    }
+
+    /// Gets a key from its name, letters and numbers are named by what they type, like "k" or
+    /// "1", other keys in snake case, like "up", "enter" or "left_bracket"
+    ///
+    /// # Arguments
+    /// * `name` - The name of the key
+    pub fn from_name(name: &str) -> Option<KeyCode> {
+        let key = match name {
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "enter" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "left_bracket" => KeyCode::LeftBracket,
+            "right_bracket" => KeyCode::RightBracket,
+            "slash" => KeyCode::Slash,
+            "backslash" => KeyCode::Backslash,
+            "comma" => KeyCode::Comma,
+            "period" => KeyCode::Period,
+            "semicolon" => KeyCode::Semicolon,
+            "quote" => KeyCode::Quote,
+            _ => {
+                let mut characters = name.chars();
+                return match (characters.next(), characters.next()) {
+                    (Some(character), None) if character.is_ascii_alphanumeric() => {
+                        KeyCode::from_char(character.to_ascii_lowercase())
+                    }
+                    _ => None,
+                };
+            }
+        };
+        Some(key)
+    }
+
+    /// Gets the key that types a character, based on a US keyboard layout. Shifted characters
+    /// give the key they are on, so both 'a' and 'A' give KeyCode::A.
+    ///
+    /// # Arguments
+    /// * `character` - The character to look up
+    pub fn from_char(character: char) -> Option<KeyCode> {
+        KEY_CHARACTERS
+            .iter()
+            .find(|(_, unshifted, shifted)| *unshifted == character || *shifted == character)
+            .map(|(key, _, _)| *key)
+    }
+}
+
+/// Movement of a two finger gesture on a touch screen since the last step, from
+/// InputArguments::gesture(). Distances are in pixels of the image the game draws, so a pan can be
+/// applied straight to a camera.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Gesture {
+    pinch: f32,
+    pan_x: f32,
+    pan_y: f32,
+}
+
+impl Gesture {
+    /// Create a new gesture
+    ///
+    /// # Arguments
+    /// * `pinch` - How much further apart the two fingers are, negative when they came together
+    /// * `pan_x` - How far the point between the two fingers moved horizontally
+    /// * `pan_y` - How far the point between the two fingers moved vertically
+    pub fn new(pinch: f32, pan_x: f32, pan_y: f32) -> Self {
+        Self {
+            pinch,
+            pan_x,
+            pan_y,
+        }
+    }
+
+    /// How much further apart the two fingers are than at the last step, negative when they were
+    /// pinched together. Spreading the fingers is usually used to zoom in.
+    pub fn pinch(&self) -> f32 {
+        self.pinch
+    }
+
+    /// How far the point between the two fingers moved horizontally since the last step
+    pub fn pan_x(&self) -> f32 {
+        self.pan_x
+    }
+
+    /// How far the point between the two fingers moved vertically since the last step
+    pub fn pan_y(&self) -> f32 {
+        self.pan_y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyboard_pressing(key: KeyCode) -> InputDevice {
+        let mut keyboard = Keyboard::default();
+        keyboard.key_down(Key::new(key, key));
+        InputDevice::Keyboard(keyboard)
+    }
+
+    fn controller_pressing(set: impl Fn(&mut Controller)) -> InputDevice {
+        let mut controller = Controller::default();
+        set(&mut controller);
+        InputDevice::Controller(controller)
+    }
+
+    fn nes_pressing(set: impl Fn(&mut Nes)) -> InputDevice {
+        let mut nes = Nes::default();
+        set(&mut nes);
+        InputDevice::Nes(nes)
+    }
+
+    fn info_for(players: &[InputDeviceType]) -> Info {
+        let mut info = Info::new("test", 60, players.len() as i32, InputDeviceType::Nes);
+        for (player, input) in info.players.iter_mut().zip(players) {
+            player.input = input.clone();
+        }
+        info
+    }
+
+    #[test]
+    fn button_sets_insert_and_remove() {
+        let mut set = ButtonSet::new();
+        assert!(set.is_empty());
+
+        set.insert(Button::A);
+        set.insert(Button::RightStick);
+        set.insert(Button::A);
+        assert!(set.contains(Button::A));
+        assert!(set.contains(Button::RightStick));
+        assert!(!set.contains(Button::B));
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![Button::A, Button::RightStick]);
+
+        set.remove(Button::A);
+        set.remove(Button::B);
+        assert!(!set.contains(Button::A));
+        assert_eq!(set.len(), 1);
+
+        let mut other = ButtonSet::new();
+        other.insert(Button::RightStick);
+        other.insert(Button::Start);
+        assert_eq!(set.intersection(&other), set);
+
+        set.remove(Button::RightStick);
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn keys_give_back_their_codes() {
+        // An AZERTY layout puts the A key where the Q scan code is:
+        let key = Key::new(KeyCode::Q, KeyCode::A);
+        assert_eq!(key.scan_code(), KeyCode::Q);
+        assert_eq!(key.key_code(), KeyCode::A);
+    }
+
+    #[test]
+    fn nes_bits_round_trip() {
+        for bits in 0..=255 {
+            assert_eq!(Nes::from_bits(bits).to_bits(), bits);
+        }
+
+        let mut nes = Nes::default();
+        nes.set_b(true);
+        nes.set_select(true);
+        assert_eq!(nes.to_bits(), 0b1000_0010);
+    }
+
+    #[test]
+    fn packed_controller_round_trips() {
+        let mut controller = Controller::default();
+        controller.set_x(true);
+        controller.set_right_stick(true);
+        controller.set_left_stick_x(-1.0);
+        controller.set_right_stick_y(0.5);
+        controller.set_right_trigger(1.0);
+
+        let packed = controller.to_packed();
+        let unpacked = Controller::from_packed(&packed);
+        assert_eq!(unpacked.buttons(), controller.buttons());
+        assert_eq!(unpacked.left_stick_x(), -1.0);
+        assert!((unpacked.right_stick_y() - 0.5).abs() < 1.0 / 127.0);
+        assert_eq!(unpacked.left_trigger(), 0.0);
+        assert_eq!(unpacked.right_trigger(), 1.0);
+        assert_eq!(unpacked.to_packed(), packed);
+    }
+
+    #[test]
+    fn keyboard_drives_a_single_nes_player() {
+        let mut collection = InputCollection::new();
+        collection.add_input(keyboard_pressing(KeyCode::K));
+
+        let arguments = collection.get_input_arguments(&info_for(&[InputDeviceType::Nes]));
+        let nes = arguments.player(0).and_then(|player| player.nes()).unwrap();
+        assert!(nes.a());
+        assert!(!nes.b());
+        assert!(arguments.player(1).is_none());
+    }
+
+    #[test]
+    fn two_gamepads_go_to_two_players_in_order() {
+        let mut collection = InputCollection::new();
+        collection.add_input_with_id(controller_pressing(|c| c.set_a(true)), 7);
+        collection.add_input_with_id(controller_pressing(|c| c.set_b(true)), 9);
+
+        let info = info_for(&[InputDeviceType::Controller, InputDeviceType::Controller]);
+        let arguments = collection.get_input_arguments(&info);
+
+        let first = arguments.player(0).unwrap();
+        assert_eq!(first.device_id(), Some(7));
+        assert!(first.controller().unwrap().a());
+        assert!(!first.controller().unwrap().b());
+
+        let second = arguments.player(1).unwrap();
+        assert_eq!(second.device_id(), Some(9));
+        assert!(second.controller().unwrap().b());
+        assert!(!second.controller().unwrap().a());
+    }
+
+    #[test]
+    fn extra_devices_combine_into_players() {
+        let mut collection = InputCollection::new();
+        collection.add_input_with_id(controller_pressing(|c| c.set_a(true)), 1);
+        collection.add_input_with_id(controller_pressing(|c| c.set_b(true)), 2);
+
+        let info = info_for(&[InputDeviceType::Controller]);
+        let arguments = collection.get_input_arguments(&info);
+
+        let player = arguments.player(0).unwrap();
+        assert_eq!(player.device_id(), Some(1));
+        let controller = player.controller().unwrap();
+        assert!(controller.a());
+        assert!(controller.b());
+    }
+
+    #[test]
+    fn players_without_a_device_are_none() {
+        let mut collection = InputCollection::new();
+        collection.add_input(keyboard_pressing(KeyCode::Enter));
+
+        let info = info_for(&[InputDeviceType::Nes, InputDeviceType::Nes, InputDeviceType::Nes]);
+        let arguments = collection.get_input_arguments(&info);
+
+        assert!(arguments.player(0).and_then(|player| player.nes()).unwrap().start());
+        assert!(arguments.player(1).is_none());
+        assert!(arguments.player(2).is_none());
+    }
+
+    #[test]
+    fn players_drop_in_with_their_devices() {
+        let mut collection = InputCollection::new();
+        collection.add_input_with_id(controller_pressing(|c| c.set_a(true)), 1);
+
+        let info = info_for(&vec![InputDeviceType::Controller; 4]).with_min_players(1);
+        let arguments = collection.get_input_arguments(&info);
+        assert_eq!(arguments.connected_players(), vec![0]);
+        assert!(arguments.player(1).is_none());
+
+        collection.add_input_with_id(controller_pressing(|c| c.set_b(true)), 2);
+        let arguments = collection.get_input_arguments(&info);
+        assert_eq!(arguments.connected_players(), vec![0, 1]);
+        assert!(arguments.player(1).unwrap().controller().unwrap().b());
+    }
+
+    #[test]
+    fn split_keyboard_drives_two_players() {
+        let mut keyboard = Keyboard::default();
+        keyboard.key_down(Key::new(KeyCode::W, KeyCode::W));
+        keyboard.key_down(Key::new(KeyCode::Period, KeyCode::Period));
+
+        let mut collection = InputCollection::new();
+        for half in keyboard.split(&[KeyBindings::left_hand(), KeyBindings::right_hand()]) {
+            collection.add_input(InputDevice::Keyboard(half));
+        }
+
+        let info = info_for(&[InputDeviceType::Nes, InputDeviceType::Nes]);
+        let arguments = collection.get_input_arguments(&info);
+
+        let first = arguments.player(0).and_then(|player| player.nes()).unwrap();
+        assert!(first.up());
+        assert!(!first.a());
+
+        let second = arguments.player(1).and_then(|player| player.nes()).unwrap();
+        assert!(second.a());
+        assert!(!second.up());
+    }
+
+    #[test]
+    fn key_bindings_apply_a_config() {
+        let mut bindings = KeyBindings::default();
+        // A mistake anywhere leaves every binding alone:
+        assert!(bindings.apply("a=l, start=space").is_err());
+        assert_eq!(bindings.a, KeyBindings::default().a);
+
+        bindings.apply("a = comma l, start=enter p").unwrap();
+        assert_eq!(bindings.a, vec![KeyCode::Comma, KeyCode::L]);
+        assert_eq!(bindings.start, vec![KeyCode::Enter, KeyCode::P]);
+        assert_eq!(bindings.b, KeyBindings::default().b);
+
+        let mut keyboard = Keyboard::default();
+        keyboard.set_bindings(bindings);
+        keyboard.key_down(Key::new(KeyCode::L, KeyCode::L));
+        assert!(keyboard.to_nes().a());
+        assert!(!keyboard.to_nes().b());
+    }
+
+    #[test]
+    fn native_devices_are_preferred_over_converted_ones() {
+        let mut collection = InputCollection::new();
+        collection.add_input_with_id(controller_pressing(|c| c.set_a(true)), 1);
+        collection.add_input_with_id(nes_pressing(|n| n.set_b(true)), 2);
+
+        let info = info_for(&[InputDeviceType::Nes, InputDeviceType::Nes]);
+        let arguments = collection.get_input_arguments(&info);
+
+        // The real Nes goes to the first player even though the controller was added first:
+        let first = arguments.player(0).unwrap();
+        assert_eq!(first.device_id(), Some(2));
+        assert!(first.nes().unwrap().b());
+
+        let second = arguments.player(1).unwrap();
+        assert_eq!(second.device_id(), Some(1));
+        assert!(second.nes().unwrap().a());
+    }
+
+    #[test]
+    fn mixed_devices_are_assigned_by_affinity() {
+        let mut collection = InputCollection::new();
+        collection.add_input(keyboard_pressing(KeyCode::Up));
+        collection.add_input_with_id(controller_pressing(|c| c.set_x(true)), 1);
+        collection.add_input_with_id(nes_pressing(|n| n.set_b(true)), 2);
+
+        let info = info_for(&[InputDeviceType::Nes, InputDeviceType::Controller]);
+        let arguments = collection.get_input_arguments(&info);
+
+        // The keyboard can't become a controller, so it joins the Nes player:
+        let first = arguments.player(0).unwrap();
+        assert_eq!(first.device_id(), Some(2));
+        let nes = first.nes().unwrap();
+        assert!(nes.b());
+        assert!(nes.up());
+
+        let second = arguments.player(1).unwrap();
+        assert_eq!(second.device_id(), Some(1));
+        assert!(second.controller().unwrap().x());
+    }
+
+    #[test]
+    fn device_order_doesnt_change_the_arguments() {
+        let keyboard = || keyboard_pressing(KeyCode::Up);
+        let controller = || controller_pressing(|c| c.set_x(true));
+        let nes = || nes_pressing(|n| n.set_b(true));
+
+        let mut first = InputCollection::new();
+        first.add_input(keyboard());
+        first.add_input_with_id(controller(), 1);
+        first.add_input_with_id(nes(), 2);
+
+        let mut second = InputCollection::new();
+        second.add_input_with_id(nes(), 2);
+        second.add_input_with_id(controller(), 1);
+        second.add_input(keyboard());
+
+        let info = info_for(&[InputDeviceType::Nes, InputDeviceType::Nes]);
+        assert_eq!(
+            serial::encode(&first.get_input_arguments(&info)),
+            serial::encode(&second.get_input_arguments(&info))
+        );
+    }
+
+    #[test]
+    fn controller_order_doesnt_change_the_arguments() {
+        // More controllers than players, so two of them share a player:
+        let controllers = [
+            (controller_pressing(|c| c.set_a(true)), 5),
+            (controller_pressing(|c| c.set_left_stick_x(0.5)), 3),
+            (controller_pressing(|c| c.set_y(true)), 8),
+        ];
+
+        let mut first = InputCollection::new();
+        for (controller, id) in controllers.iter() {
+            first.add_input_with_id(controller.clone(), *id);
+        }
+
+        let mut second = InputCollection::new();
+        for (controller, id) in controllers.iter().rev() {
+            second.add_input_with_id(controller.clone(), *id);
+        }
+
+        let info = info_for(&[InputDeviceType::Controller, InputDeviceType::Controller]);
+        let arguments = first.get_input_arguments(&info);
+        assert_eq!(
+            serial::encode(&arguments),
+            serial::encode(&second.get_input_arguments(&info))
+        );
+
+        // The lowest ids get a player each, the rest combine into them in id order:
+        let player = arguments.player(0).unwrap();
+        assert_eq!(player.device_id(), Some(3));
+        assert!(player.controller().unwrap().y());
+        assert_eq!(player.controller().unwrap().left_stick_x(), 0.5);
+
+        let player = arguments.player(1).unwrap();
+        assert_eq!(player.device_id(), Some(5));
+        assert!(player.controller().unwrap().a());
+        assert!(!player.controller().unwrap().y());
+    }
+}
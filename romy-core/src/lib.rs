@@ -1,27 +1,43 @@
 use serde_derive::{Deserialize, Serialize};
 
 pub mod input;
+pub mod math;
 pub mod output;
+pub mod random;
 pub mod runtime;
 pub mod serial;
 
 use input::*;
 use output::*;
 
-/// Holds information about the Game
+/// The version of the interface between games and runtimes, exported by games as
+/// romy_api_version. It changes whenever a change would break games built against an older
+/// version. Runtimes refuse games that report a different version.
+pub const API_VERSION: i32 = 2;
+
+/// Holds information about the Game. It's encoded as it is returned from a games init(), so
+/// adding or changing fields changes the interface between games and runtimes, see API_VERSION.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Info {
     name: String,
     step_interval: u32,
     players: Vec<Player>,
+    min_players: i32,
+    audio_steps: u32,
+    pixel_aspect_ratio: f32,
 }
 
 impl Info {
+    /// The most players a game can have
+    pub const MAX_PLAYERS: i32 = 16;
+
     /// Create a new structure of info about the game being played
     /// # Arguments
     /// * `name` - The title of the game
-    /// * `steps_per_second` - The number of times Game::Step() should be called per second
-    /// * `number_of_players` - How many players this games should have
+    /// * `steps_per_second` - The number of times Game::Step() should be called per second,
+    ///   clamped to 1 - 1000000000
+    /// * `number_of_players` - How many players this games should have, clamped to 1 -
+    ///   Info::MAX_PLAYERS
     /// * `input` - The device type to use for each player
     pub fn new(
         name: &str,
@@ -31,6 +47,7 @@ impl Info {
     ) -> Self {
         let player = Player { input };
 
+        let number_of_players = number_of_players.clamp(1, Self::MAX_PLAYERS);
         let mut players = Vec::with_capacity(number_of_players as usize);
         for _ in 0..number_of_players {
             players.push(player.clone());
@@ -40,7 +57,41 @@ impl Info {
             name: name.to_string(),
             step_interval: Self::steps_per_second_to_interval(steps_per_second),
             players,
+            min_players: number_of_players,
+            audio_steps: 1,
+            pixel_aspect_ratio: 1.0,
+        }
+    }
+
+    /// Ask for audio to be rendered every few steps rather than after every step, this allows
+    /// larger chunks of audio to be generated with less overhead. Defaults to 1.
+    /// # Arguments
+    /// * `audio_steps` - The number of steps each call to Game::render_audio() should cover
+    pub fn with_audio_steps(mut self, audio_steps: u32) -> Self {
+        self.audio_steps = audio_steps.max(1);
+        self
+    }
+
+    /// Let the game run with fewer players than Info::new() was given, for games where players can
+    /// drop in by connecting a device. Players are then only handed to the game for the devices
+    /// that are connected, but never fewer than this. Defaults to the number of players.
+    /// # Arguments
+    /// * `min_players` - The fewest players the game needs, clamped to 1 - max_players()
+    pub fn with_min_players(mut self, min_players: i32) -> Self {
+        self.min_players = min_players.clamp(1, self.max_players());
+        self
+    }
+
+    /// Ask for the images the game draws to be shown with non-square pixels, like on some classic
+    /// consoles. Runtimes stretch the image by this when scaling it to fit. Defaults to 1.0.
+    /// # Arguments
+    /// * `pixel_aspect_ratio` - The width of a pixel divided by its height, 8.0 / 7.0 for the
+    ///   NES for example. Values that aren't positive are ignored.
+    pub fn with_pixel_aspect_ratio(mut self, pixel_aspect_ratio: f32) -> Self {
+        if pixel_aspect_ratio.is_finite() && pixel_aspect_ratio > 0.0 {
+            self.pixel_aspect_ratio = pixel_aspect_ratio;
         }
+        self
     }
     
     /// Gets the name of the game
@@ -53,9 +104,82 @@ impl Info {
         self.step_interval
     }
 
-    /// Converts from steps per second to a time interval in nanoseconds
+    /// Gets the number of players the game has, the same as max_players()
+    pub fn number_of_players(&self) -> i32 {
+        self.players.len() as i32
+    }
+
+    /// Gets the fewest players the game needs
+    pub fn min_players(&self) -> i32 {
+        self.min_players
+    }
+
+    /// Gets the most players the game supports
+    pub fn max_players(&self) -> i32 {
+        self.players.len() as i32
+    }
+
+    /// Gets the input device type a player asked for, None if there is no such player
+    /// # Arguments
+    /// * `player` - Index of the player
+    pub fn player_input(&self, player: i32) -> Option<&InputDeviceType> {
+        self.players.get(player as usize).map(|player| &player.input)
+    }
+
+    /// Gets the number of steps each call to Game::render_audio() should cover
+    pub fn audio_steps(&self) -> u32 {
+        self.audio_steps
+    }
+
+    /// Gets the number of samples, at RenderAudioArguments::SAMPLE_RATE, that cover audio_steps()
+    /// steps, what a runtime asks for on average
+    pub fn audio_sample_count(&self) -> i32 {
+        let nanos = u64::from(self.step_interval) * u64::from(self.audio_steps);
+        (nanos * RenderAudioArguments::SAMPLE_RATE as u64 / 1_000_000_000) as i32
+    }
+
+    /// Gets the width of each pixel the game draws divided by its height
+    pub fn pixel_aspect_ratio(&self) -> f32 {
+        self.pixel_aspect_ratio
+    }
+
+    /// Converts from steps per second to a time interval in nanoseconds, the rate is clamped to
+    /// 1 - 1000000000 so the interval is never 0
     pub fn steps_per_second_to_interval(steps: i32) -> u32 {
-        1_000_000_000 / steps as u32
+        1_000_000_000 / steps.clamp(1, 1_000_000_000) as u32
+    }
+
+    /// Checks the info makes sense, Info::new() always gives valid info but info decoded from a
+    /// game could contain anything, so runtimes should check it before running the game
+    pub fn validate(&self) -> Result<(), String> {
+        if self.step_interval == 0 {
+            return Err("the step interval is 0".to_string());
+        }
+        if self.players.len() > Self::MAX_PLAYERS as usize {
+            return Err(format!(
+                "{} players asked for, the most allowed is {}",
+                self.players.len(),
+                Self::MAX_PLAYERS
+            ));
+        }
+        if self.min_players < 1 || self.min_players > self.max_players() {
+            return Err(format!(
+                "at least {} players asked for, but there can only be 1 - {}",
+                self.min_players,
+                self.max_players()
+            ));
+        }
+        if self.audio_steps == 0 {
+            return Err("the number of audio steps is 0".to_string());
+        }
+        if !self.pixel_aspect_ratio.is_finite() || self.pixel_aspect_ratio <= 0.0 {
+            return Err(format!(
+                "the pixel aspect ratio {} isn't positive",
+                self.pixel_aspect_ratio
+            ));
+        }
+
+        Ok(())
     }
 }
 
@@ -75,47 +199,187 @@ pub trait Game {
 
     /// Renders an image for Romy to display, can be called many times per step.
     ///
-    /// This function can return any image.
+    /// This function can return any image. A game that can't draw a frame, because something it
+    /// needs isn't ready for example, can return an image with a width or height of 0 rather than
+    /// panicking, the runtime keeps showing the previous frame.
     /// 
     /// # Arguments
     /// * `arguments` - Info, such as the width of the frame Romy is rendering too, to be used in
     /// this step
     fn draw(&self, arguments: &DrawArguments) -> Image;
 
-    /// Renders some audio for Romy to play, called once per step.
+    /// Renders a stack of layers for Romy to composite and display, called instead of draw().
+    ///
+    /// The first layer sets the size of the frame, the rest are drawn over it in order. By default
+    /// this returns the result of draw() as a single layer. Returning no layers skips the frame,
+    /// the same as returning an empty image from draw().
+    ///
+    /// # Arguments
+    /// * `arguments` - The same arguments that would be passed to draw()
+    fn draw_layers(&self, arguments: &DrawArguments) -> Vec<Layer> {
+        vec![Layer::new(self.draw(arguments), 0, 0, BlendMode::Opaque)]
+    }
+
+    /// Renders some audio for Romy to play, called once per step, or once every
+    /// Info::audio_steps() steps if more than 1 was asked for.
     ///
     /// The sound returned currently needs to be at a sample rate of 44100hz, and have enough
-    /// samples to cover the amount of time between calls to step.
+    /// samples to cover the amount of time between calls to render_audio. The exact number the
+    /// runtime needs is given by RenderAudioArguments::sample_count().
     fn render_audio(&self, arguments: &RenderAudioArguments) -> Sound;
+
+    /// Renders audio as a number of separate voices that the runtime will mix together, called
+    /// once per step instead of render_audio().
+    ///
+    /// Each voice follows the same rules as the sound returned by render_audio(). By default this
+    /// returns the result of render_audio() as a single voice named "main".
+    fn render_voices(&self, arguments: &RenderAudioArguments) -> Vec<Voice> {
+        vec![Voice::new("main", 1.0, self.render_audio(arguments))]
+    }
+
+    /// Asks the game if it would like the controllers to rumble, called once after every step.
+    ///
+    /// Returning None leaves any rumble that is already playing alone.
+    fn rumble(&self) -> Option<Rumble> {
+        None
+    }
+
+    /// Asks the game for a title to show in place of its name, for things like the score or the
+    /// current level. Runtimes check this after steps and update the window or page title when it
+    /// changes.
+    ///
+    /// Returning None shows the name from Info.
+    fn title(&self) -> Option<String> {
+        None
+    }
 }
 
 // Input Arguments /////////////////////////////////////////////////////////////////////////////////
 
 /// Arguments passed for each step of the game
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize)]
 pub struct StepArguments {
     input: InputArguments,
+    step_index: u64,
+    step_interval: u32,
+    focused: bool,
+}
+
+impl Default for StepArguments {
+    fn default() -> Self {
+        Self::new(InputArguments::default(), 0, 0)
+    }
 }
 
 impl StepArguments {
-    pub fn new(input: InputArguments) -> Self {
-        Self { input }
+    pub fn new(input: InputArguments, step_index: u64, step_interval: u32) -> Self {
+        Self {
+            input,
+            step_index,
+            step_interval,
+            focused: true,
+        }
+    }
+
+    /// Sets whether the game has focus, see focused()
+    ///
+    /// # Arguments
+    /// * `focused` - False if the window is in the background or the page is hidden
+    pub fn with_focus(mut self, focused: bool) -> Self {
+        self.focused = focused;
+        self
+    }
+
+    /// Does the game have focus, false when the window is in the background or the browser tab is
+    /// hidden. Games may want to pause or go quiet when they lose focus, runtimes can also be set
+    /// up to pause them automatically, in which case no steps happen until focus returns.
+    pub fn focused(&self) -> bool {
+        self.focused
     }
 
     /// Get the input for this step
     pub fn input(&self) -> &InputArguments {
         &self.input
     }
+
+    /// The number of steps that have happened before this one, the first step is 0
+    pub fn step_index(&self) -> u64 {
+        self.step_index
+    }
+
+    /// Gets time between steps of the game, in nanoseconds
+    pub fn step_interval(&self) -> u32 {
+        self.step_interval
+    }
+
+    /// The amount of game time that has passed before this step, in seconds
+    pub fn elapsed_seconds(&self) -> f32 {
+        (self.step_index as f64 * f64::from(self.step_interval) / 1_000_000_000.0) as f32
+    }
 }
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct InputArguments {
     players: Vec<Option<PlayerInputArguments>>,
+    devices_changed: bool,
+    disconnected: Vec<bool>,
+    gesture: Option<Gesture>,
+}
+
+/// Whether a player has an input device, see InputArguments::player_status()
+pub enum PlayerStatus<'a> {
+    /// The player has an input device
+    Present(&'a PlayerInputArguments),
+    /// The player had an input device earlier in the game but it has since been disconnected
+    Disconnected,
+    /// The player has never had an input device, or isn't a player the game asked for
+    Absent,
 }
 
 impl InputArguments {
-    pub fn new(players: Vec<Option<PlayerInputArguments>>) -> Self {
-        Self { players }
+    pub fn new(players: Vec<Option<PlayerInputArguments>>, devices_changed: bool) -> Self {
+        Self {
+            players,
+            devices_changed,
+            disconnected: Vec::new(),
+            gesture: None,
+        }
+    }
+
+    /// Adds a two finger touch gesture, see gesture()
+    ///
+    /// # Arguments
+    /// * `gesture` - The gesture, None if there are not two fingers on the screen
+    pub fn with_gesture(mut self, gesture: Option<Gesture>) -> Self {
+        self.gesture = gesture;
+        self
+    }
+
+    /// Get the number of players that currently have an input device
+    pub fn player_count(&self) -> i32 {
+        self.players.iter().filter(|player| player.is_some()).count() as i32
+    }
+
+    /// Get the indices of all players that currently have an input device
+    pub fn connected_players(&self) -> Vec<i32> {
+        self.players
+            .iter()
+            .enumerate()
+            .filter(|(_, player)| player.is_some())
+            .map(|(index, _)| index as i32)
+            .collect()
+    }
+
+    /// Was an input device connected or disconnected since the last step
+    pub fn devices_changed(&self) -> bool {
+        self.devices_changed
+    }
+
+    /// Get how a two finger touch gesture moved since the last step, will be None if there aren't
+    /// two fingers on the screen or the runtime doesn't track gestures. The web runtime only
+    /// tracks them when its touchGestures option is set.
+    pub fn gesture(&self) -> Option<&Gesture> {
+        self.gesture.as_ref()
     }
 
     /// Get the input for a specific player, will be None if there is no available player
@@ -128,14 +392,39 @@ impl InputArguments {
 
         None
     }
+
+    /// Get the input for a specific player along with whether a missing device was disconnected
+    /// mid-game or was never there at all, useful for pausing when a controller is unplugged
+    ///
+    /// # Arguments
+    /// * `player` - The index of the player
+    pub fn player_status(&self, player: i32) -> PlayerStatus<'_> {
+        if let Some(input) = self.player(player) {
+            return PlayerStatus::Present(input);
+        }
+
+        if player >= 0 && self.disconnected.get(player as usize) == Some(&true) {
+            return PlayerStatus::Disconnected;
+        }
+
+        PlayerStatus::Absent
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct PlayerInputArguments {
     input: InputDevice,
+    device_id: Option<u32>,
 }
 
 impl PlayerInputArguments {
+    /// Get an id for the physical device this player is using, it stays the same while the device
+    /// is connected so it can be used to show which controller belongs to which player. None if
+    /// the runtime didn't give one, the keyboard doesn't have one for example.
+    pub fn device_id(&self) -> Option<u32> {
+        self.device_id
+    }
+
     /// Get the players NES style controller, will be None if there is no suitable input device, or
     /// one wasn't asked for in the supplied game info.
     pub fn nes(&self) -> Option<&Nes> {
@@ -172,14 +461,24 @@ pub struct DrawArguments {
     width: i32,
     height: i32,
     step_offset: f32,
+    scale_factor: f32,
+    elapsed_seconds: f32,
 }
 
 impl DrawArguments {
-    pub fn new(width: i32, height: i32, step_offset: f32) -> Self {
+    pub fn new(
+        width: i32,
+        height: i32,
+        step_offset: f32,
+        scale_factor: f32,
+        elapsed_seconds: f32,
+    ) -> Self {
         Self {
             width,
             height,
             step_offset,
+            scale_factor,
+            elapsed_seconds,
         }
     }
 
@@ -195,13 +494,75 @@ impl DrawArguments {
 
     /// The fraction of time since the last step call in a range of 0.0 - 1.0. 0.0 no time has
     /// passed, 0.5 = half way to the next step, 0.99 = almost all the way to the next step.
+    /// Use it to blend between the last two steps with math::Interpolated or math::lerp().
     pub fn step_offset(&self) -> f32 {
         self.step_offset
     }
+
+    /// The ratio of physical pixels to logical pixels on the display, 1.0 on most displays and
+    /// higher on high DPI ones.
+    pub fn scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+
+    /// The amount of time the game has been running for, in seconds. Unlike step_offset this
+    /// keeps increasing smoothly, so it can drive purely visual animation without touching the
+    /// game state.
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed_seconds
+    }
+
+    /// The width of the display area divided by its height, 0.0 if the height is 0
+    pub fn aspect_ratio(&self) -> f32 {
+        if self.height == 0 {
+            return 0.0;
+        }
+        self.width as f32 / self.height as f32
+    }
 }
 
 // Render audio arguments //////////////////////////////////////////////////////////////////////////
 
 /// Arguments passed for each audio render of the game
 #[derive(Serialize, Deserialize, Debug)]
-pub struct RenderAudioArguments {}
+pub struct RenderAudioArguments {
+    sample_count: i32,
+}
+
+impl RenderAudioArguments {
+    /// The number of samples per second runtimes play audio at
+    pub const SAMPLE_RATE: i32 = 44100;
+
+    /// Create render audio arguments
+    /// # Arguments
+    /// * `sample_count` - The number of samples the runtime needs, at least 0
+    pub fn new(sample_count: i32) -> Self {
+        Self {
+            sample_count: sample_count.max(0),
+        }
+    }
+
+    /// The number of samples, at SAMPLE_RATE, the runtime needs to keep the audio device playing
+    /// without gaps or a growing backlog. It is worked out from how much the device has played
+    /// since the last call, so it varies around Info::audio_sample_count() and can be 0 when the
+    /// device is well ahead. Returning exactly this many keeps the audio locked to the device
+    /// instead of slowly drifting, games that always return a fixed amount still work.
+    pub fn sample_count(&self) -> i32 {
+        self.sample_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_info_is_always_valid() {
+        for &steps in &[i32::MIN, -1, 0, 1, 60, 1_000_000_000, i32::MAX] {
+            for &players in &[i32::MIN, 0, 1, 4, Info::MAX_PLAYERS + 1, i32::MAX] {
+                let info = Info::new("game", steps, players, InputDeviceType::Nes);
+                assert!(info.validate().is_ok(), "{} steps, {} players", steps, players);
+            }
+        }
+    }
+}
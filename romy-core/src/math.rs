@@ -0,0 +1,486 @@
+//! Deterministic math helpers.
+//!
+//! Basic floating point arithmetic gives the same results everywhere, but functions like sin and
+//! cos are implemented differently by different platforms and compilers, so using them can make a
+//! game behave differently on different machines. The functions here are built from lookup tables
+//! and integer math instead, so they give bit identical results everywhere, which keeps things
+//! like generated audio and replays consistent.
+//!
+//! It also has small shared types for positions and movement, Vec2 and Point, along with helpers
+//! for blending between states.
+
+use serde_derive::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+/// One quarter of a sine wave in 256 steps, as 16.16 fixed point values
+const QUARTER_SINE: [i32; 257] = [
+    0, 402, 804, 1206, 1608, 2010, 2412, 2814, 3216, 3617,
+    4019, 4420, 4821, 5222, 5623, 6023, 6424, 6824, 7224, 7623,
+    8022, 8421, 8820, 9218, 9616, 10014, 10411, 10808, 11204, 11600,
+    11996, 12391, 12785, 13180, 13573, 13966, 14359, 14751, 15143, 15534,
+    15924, 16314, 16703, 17091, 17479, 17867, 18253, 18639, 19024, 19409,
+    19792, 20175, 20557, 20939, 21320, 21699, 22078, 22457, 22834, 23210,
+    23586, 23961, 24335, 24708, 25080, 25451, 25821, 26190, 26558, 26925,
+    27291, 27656, 28020, 28383, 28745, 29106, 29466, 29824, 30182, 30538,
+    30893, 31248, 31600, 31952, 32303, 32652, 33000, 33347, 33692, 34037,
+    34380, 34721, 35062, 35401, 35738, 36075, 36410, 36744, 37076, 37407,
+    37736, 38064, 38391, 38716, 39040, 39362, 39683, 40002, 40320, 40636,
+    40951, 41264, 41576, 41886, 42194, 42501, 42806, 43110, 43412, 43713,
+    44011, 44308, 44604, 44898, 45190, 45480, 45769, 46056, 46341, 46624,
+    46906, 47186, 47464, 47741, 48015, 48288, 48559, 48828, 49095, 49361,
+    49624, 49886, 50146, 50404, 50660, 50914, 51166, 51417, 51665, 51911,
+    52156, 52398, 52639, 52878, 53114, 53349, 53581, 53812, 54040, 54267,
+    54491, 54714, 54934, 55152, 55368, 55582, 55794, 56004, 56212, 56418,
+    56621, 56823, 57022, 57219, 57414, 57607, 57798, 57986, 58172, 58356,
+    58538, 58718, 58896, 59071, 59244, 59415, 59583, 59750, 59914, 60075,
+    60235, 60392, 60547, 60700, 60851, 60999, 61145, 61288, 61429, 61568,
+    61705, 61839, 61971, 62101, 62228, 62353, 62476, 62596, 62714, 62830,
+    62943, 63054, 63162, 63268, 63372, 63473, 63572, 63668, 63763, 63854,
+    63944, 64031, 64115, 64197, 64277, 64354, 64429, 64501, 64571, 64639,
+    64704, 64766, 64827, 64884, 64940, 64993, 65043, 65091, 65137, 65180,
+    65220, 65259, 65294, 65328, 65358, 65387, 65413, 65436, 65457, 65476,
+    65492, 65505, 65516, 65525, 65531, 65535, 65536,
+];
+
+const QUARTER_TURN: u32 = 1 << 30;
+
+fn quarter_sine(position: u32) -> i32 {
+    let index = (position >> 22) as usize;
+    let fraction = i64::from((position >> 6) & 0xFFFF);
+    let from = i64::from(QUARTER_SINE[index]);
+    let to = i64::from(QUARTER_SINE[(index + 1).min(256)]);
+    (from + (((to - from) * fraction) >> 16)) as i32
+}
+
+/// Deterministic fixed point sine
+///
+/// # Arguments
+/// * `phase` - The angle, where the full range of a u32 is one full turn, so it wraps around
+///   naturally
+///
+/// Returns the sine as a 16.16 fixed point value, from -65536 to 65536
+pub fn det_sin_fixed(phase: u32) -> i32 {
+    let position = phase & (QUARTER_TURN - 1);
+    match phase >> 30 {
+        0 => quarter_sine(position),
+        1 => quarter_sine(QUARTER_TURN - position),
+        2 => -quarter_sine(position),
+        _ => -quarter_sine(QUARTER_TURN - position),
+    }
+}
+
+/// Deterministic fixed point cosine, see det_sin_fixed()
+///
+/// # Arguments
+/// * `phase` - The angle, where the full range of a u32 is one full turn
+pub fn det_cos_fixed(phase: u32) -> i32 {
+    det_sin_fixed(phase.wrapping_add(QUARTER_TURN))
+}
+
+fn turns_to_phase(turns: f32) -> u32 {
+    let fraction = f64::from(turns - turns.floor());
+    (fraction * 4_294_967_296.0) as u64 as u32
+}
+
+/// Deterministic sine
+///
+/// # Arguments
+/// * `turns` - The angle in turns, 1.0 is a full circle (2 * PI radians). This is handy for audio
+///   where phase is usually kept in the range of 0.0 - 1.0
+pub fn det_sin(turns: f32) -> f32 {
+    det_sin_fixed(turns_to_phase(turns)) as f32 / 65536.0
+}
+
+/// Deterministic cosine
+///
+/// # Arguments
+/// * `turns` - The angle in turns, 1.0 is a full circle (2 * PI radians)
+pub fn det_cos(turns: f32) -> f32 {
+    det_cos_fixed(turns_to_phase(turns)) as f32 / 65536.0
+}
+
+/// Values that can be blended smoothly between two states, see lerp()
+pub trait Lerp {
+    /// Blend between this value and another
+    ///
+    /// # Arguments
+    /// * `other` - The value to blend towards
+    /// * `t` - How far to blend, 0.0 gives this value and 1.0 gives other
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for i32 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        (*self as f32).lerp(&(*other as f32), t).round() as i32
+    }
+}
+
+impl<A: Lerp, B: Lerp> Lerp for (A, B) {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        (self.0.lerp(&other.0, t), self.1.lerp(&other.1, t))
+    }
+}
+
+/// Linear interpolation between two values, integers are rounded to the nearest whole number
+///
+/// # Arguments
+/// * `a` - The value at t = 0.0
+/// * `b` - The value at t = 1.0
+/// * `t` - How far from a to b, values outside 0.0 - 1.0 extrapolate
+///
+/// ```
+/// use romy_core::math::lerp;
+///
+/// assert_eq!(lerp(2.0, 4.0, 0.5), 3.0);
+/// assert_eq!(lerp(10, 20, 0.25), 13);
+/// assert_eq!(lerp((0.0, 10), (1.0, 20), 1.0), (1.0, 20));
+/// ```
+pub fn lerp<T: Lerp>(a: T, b: T, t: f32) -> T {
+    a.lerp(&b, t)
+}
+
+/// Keeps the last two simulation states of a value, so drawing can blend between them using
+/// DrawArguments::step_offset(). This keeps movement smooth when the display refreshes at a
+/// different rate to the game steps.
+///
+/// Call set() once per Game::step() and get() from Game::draw():
+///
+/// ```
+/// use romy_core::math::Interpolated;
+///
+/// let mut x = Interpolated::new(0.0);
+/// // In step()
+/// x.set(8.0);
+/// // In draw(), with arguments.step_offset() being 0.25
+/// assert_eq!(x.get(0.25), 2.0);
+/// assert_eq!(*x.current(), 8.0);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Interpolated<T> {
+    previous: T,
+    current: T,
+}
+
+impl<T: Lerp + Clone> Interpolated<T> {
+    /// Create an interpolated value that starts at rest
+    ///
+    /// # Arguments
+    /// * `value` - The starting value, used as both the previous and current state
+    pub fn new(value: T) -> Self {
+        Self {
+            previous: value.clone(),
+            current: value,
+        }
+    }
+
+    /// Record the state at the end of a step, the old current state becomes the previous one
+    ///
+    /// # Arguments
+    /// * `value` - The new current state
+    pub fn set(&mut self, value: T) {
+        self.previous = std::mem::replace(&mut self.current, value);
+    }
+
+    /// Jump straight to a value without blending from the old one, for things like teleports
+    ///
+    /// # Arguments
+    /// * `value` - The new previous and current state
+    pub fn reset(&mut self, value: T) {
+        self.previous = value.clone();
+        self.current = value;
+    }
+
+    /// Gets the state recorded by the last step
+    pub fn current(&self) -> &T {
+        &self.current
+    }
+
+    /// Gets the state recorded by the step before the last one
+    pub fn previous(&self) -> &T {
+        &self.previous
+    }
+
+    /// Gets the state to draw
+    ///
+    /// # Arguments
+    /// * `step_offset` - DrawArguments::step_offset(), how far the display is between the
+    ///   previous and current step
+    pub fn get(&self, step_offset: f32) -> T {
+        self.previous.lerp(&self.current, step_offset)
+    }
+}
+
+/// A 2D vector, used for positions, directions and movement
+///
+/// ```
+/// use romy_core::math::Vec2;
+///
+/// let a = Vec2::new(3.0, 4.0);
+/// assert_eq!(a.length(), 5.0);
+/// assert_eq!(a.normalize(), Vec2::new(0.6, 0.8));
+/// assert_eq!(a + Vec2::new(1.0, 1.0), Vec2::new(4.0, 5.0));
+/// assert_eq!(a * 2.0, Vec2::new(6.0, 8.0));
+/// assert_eq!(a.dot(Vec2::new(1.0, 0.0)), 3.0);
+/// assert_eq!(Vec2::ZERO.normalize(), Vec2::ZERO);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vec2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Vec2 {
+    /// A vector with both components set to 0.0
+    pub const ZERO: Vec2 = Vec2 { x: 0.0, y: 0.0 };
+
+    /// Create a new vector
+    ///
+    /// # Arguments
+    /// * `x` - The horizontal component
+    /// * `y` - The vertical component
+    pub fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+
+    /// Multiply both components by a value
+    ///
+    /// # Arguments
+    /// * `factor` - The value to multiply by
+    pub fn scale(self, factor: f32) -> Self {
+        Self::new(self.x * factor, self.y * factor)
+    }
+
+    /// The dot product of this vector and another
+    ///
+    /// # Arguments
+    /// * `other` - The other vector
+    pub fn dot(self, other: Vec2) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+
+    /// The squared length of the vector, cheaper than length() when only comparing lengths
+    pub fn length_squared(self) -> f32 {
+        self.dot(self)
+    }
+
+    /// The length of the vector
+    pub fn length(self) -> f32 {
+        self.length_squared().sqrt()
+    }
+
+    /// A vector pointing the same way with a length of 1.0, a zero length vector stays zero
+    pub fn normalize(self) -> Self {
+        let length = self.length();
+        if length == 0.0 {
+            return Self::ZERO;
+        }
+
+        self.scale(1.0 / length)
+    }
+
+    /// Rounds both components to the nearest whole number
+    pub fn round(self) -> Point {
+        Point::new(self.x.round() as i32, self.y.round() as i32)
+    }
+}
+
+impl Add for Vec2 {
+    type Output = Vec2;
+
+    fn add(self, other: Vec2) -> Vec2 {
+        Vec2::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl AddAssign for Vec2 {
+    fn add_assign(&mut self, other: Vec2) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Vec2 {
+    type Output = Vec2;
+
+    fn sub(self, other: Vec2) -> Vec2 {
+        Vec2::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl SubAssign for Vec2 {
+    fn sub_assign(&mut self, other: Vec2) {
+        *self = *self - other;
+    }
+}
+
+impl Mul<f32> for Vec2 {
+    type Output = Vec2;
+
+    fn mul(self, factor: f32) -> Vec2 {
+        self.scale(factor)
+    }
+}
+
+impl Neg for Vec2 {
+    type Output = Vec2;
+
+    fn neg(self) -> Vec2 {
+        Vec2::new(-self.x, -self.y)
+    }
+}
+
+impl From<(f32, f32)> for Vec2 {
+    fn from((x, y): (f32, f32)) -> Self {
+        Self::new(x, y)
+    }
+}
+
+impl From<Point> for Vec2 {
+    fn from(point: Point) -> Self {
+        Self::new(point.x as f32, point.y as f32)
+    }
+}
+
+impl Lerp for Vec2 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self::new(self.x.lerp(&other.x, t), self.y.lerp(&other.y, t))
+    }
+}
+
+/// A 2D position in whole pixels
+///
+/// ```
+/// use romy_core::math::{Point, Vec2};
+///
+/// let a = Point::new(2, 3) + Point::new(1, -1);
+/// assert_eq!(a, Point::new(3, 2));
+/// assert_eq!(Vec2::from(a), Vec2::new(3.0, 2.0));
+/// assert_eq!(Vec2::new(1.4, 1.6).round(), Point::new(1, 2));
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Point {
+    /// Create a new point
+    ///
+    /// # Arguments
+    /// * `x` - The horizontal coordinate
+    /// * `y` - The vertical coordinate
+    pub fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+}
+
+impl Add for Point {
+    type Output = Point;
+
+    fn add(self, other: Point) -> Point {
+        Point::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl AddAssign for Point {
+    fn add_assign(&mut self, other: Point) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Point {
+    type Output = Point;
+
+    fn sub(self, other: Point) -> Point {
+        Point::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl SubAssign for Point {
+    fn sub_assign(&mut self, other: Point) {
+        *self = *self - other;
+    }
+}
+
+impl Neg for Point {
+    type Output = Point;
+
+    fn neg(self) -> Point {
+        Point::new(-self.x, -self.y)
+    }
+}
+
+impl From<(i32, i32)> for Point {
+    fn from((x, y): (i32, i32)) -> Self {
+        Self::new(x, y)
+    }
+}
+
+impl Lerp for Point {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self::new(self.x.lerp(&other.x, t), self.y.lerp(&other.y, t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EIGHTH_TURN: u32 = QUARTER_TURN / 2;
+
+    #[test]
+    fn quarter_turns_are_exact() {
+        assert_eq!(det_sin_fixed(0), 0);
+        assert_eq!(det_sin_fixed(QUARTER_TURN), 65536);
+        assert_eq!(det_sin_fixed(QUARTER_TURN * 2), 0);
+        assert_eq!(det_sin_fixed(QUARTER_TURN * 3), -65536);
+
+        assert_eq!(det_sin(0.0), 0.0);
+        assert_eq!(det_sin(0.25), 1.0);
+        assert_eq!(det_sin(0.5), 0.0);
+        assert_eq!(det_sin(0.75), -1.0);
+        assert_eq!(det_cos(0.0), 1.0);
+        assert_eq!(det_cos(0.25), 0.0);
+        assert_eq!(det_cos(0.5), -1.0);
+        assert_eq!(det_cos(0.75), 0.0);
+
+        // Whole turns wrap around:
+        assert_eq!(det_sin(1.25), 1.0);
+        assert_eq!(det_sin(-0.25), -1.0);
+    }
+
+    #[test]
+    fn sine_is_odd() {
+        for phase in (0..=u32::MAX).step_by(12_345_679) {
+            assert_eq!(det_sin_fixed(phase.wrapping_neg()), -det_sin_fixed(phase));
+        }
+    }
+
+    #[test]
+    fn cosine_is_sine_a_quarter_turn_on() {
+        for phase in (0..=u32::MAX).step_by(12_345_679) {
+            assert_eq!(det_cos_fixed(phase), det_sin_fixed(phase.wrapping_add(QUARTER_TURN)));
+        }
+        for step in 0..64 {
+            let turns = step as f32 / 64.0;
+            assert_eq!(det_cos(turns), det_sin(turns + 0.25));
+        }
+    }
+
+    #[test]
+    fn outputs_are_pinned() {
+        // Changing the table or the interpolation changes these, and with them every game that
+        // relies on its results being the same everywhere:
+        assert_eq!(det_sin_fixed(EIGHTH_TURN / 2), 25080);
+        assert_eq!(det_sin_fixed(EIGHTH_TURN), 46341);
+        assert_eq!(det_sin_fixed((1 << 22) + (1 << 21)), 603);
+        assert_eq!(det_sin_fixed(123_456_789), 11772);
+        assert_eq!(det_cos_fixed(3_000_000_000), -20841);
+        assert_eq!(det_sin(1.0 / 12.0), 32767.0 / 65536.0);
+    }
+}
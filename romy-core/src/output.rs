@@ -1,6 +1,9 @@
 use serde_derive::{Deserialize, Serialize};
+use crate::math::Point;
 use byteorder::{LittleEndian, ReadBytesExt};
 
+/// A color with red, green, blue and alpha channels, each in the range of 0.0 - 1.0
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Color {
     red: f32,
     green: f32,
@@ -17,6 +20,103 @@ impl Color {
             alpha,
         }
     }
+    /// Create a color from a packed 32 bit RGBA value, the same format returned by as_rgba()
+    /// and stored in Image
+    pub fn from_rgba(rgba: u32) -> Color {
+        let range = 255.0;
+        Self {
+            red: (rgba & 0xFF) as f32 / range,
+            green: ((rgba >> 8) & 0xFF) as f32 / range,
+            blue: ((rgba >> 16) & 0xFF) as f32 / range,
+            alpha: ((rgba >> 24) & 0xFF) as f32 / range,
+        }
+    }
+    /// Create a color from hue, saturation and value
+    /// # Arguments
+    /// * `hue` - the hue in degrees, 0.0 - 360.0, values outside of this wrap around
+    /// * `saturation` - the saturation, 0.0 - 1.0
+    /// * `value` - the value/brightness, 0.0 - 1.0
+    /// * `alpha` - the alpha, 0.0 - 1.0
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32, alpha: f32) -> Color {
+        let mut hue = hue % 360.0;
+        if hue < 0.0 {
+            hue += 360.0;
+        }
+        let hue = hue / 60.0;
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (red, green, blue) = match hue as i32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = value - chroma;
+
+        Self::new(red + m, green + m, blue + m, alpha)
+    }
+
+    /// Gets the red channel
+    pub fn red(&self) -> f32 {
+        self.red
+    }
+
+    /// Gets the green channel
+    pub fn green(&self) -> f32 {
+        self.green
+    }
+
+    /// Gets the blue channel
+    pub fn blue(&self) -> f32 {
+        self.blue
+    }
+
+    /// Gets the alpha channel
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    /// Converts to hue, saturation and value, returned as a tuple of (hue, saturation, value,
+    /// alpha). Hue is in degrees 0.0 - 360.0, the rest are 0.0 - 1.0
+    pub fn to_hsv(&self) -> (f32, f32, f32, f32) {
+        let max = self.red.max(self.green).max(self.blue);
+        let min = self.red.min(self.green).min(self.blue);
+        let delta = max - min;
+
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == self.red {
+            let hue = 60.0 * ((self.green - self.blue) / delta);
+            if hue < 0.0 {
+                hue + 360.0
+            } else {
+                hue
+            }
+        } else if max == self.green {
+            60.0 * ((self.blue - self.red) / delta + 2.0)
+        } else {
+            60.0 * ((self.red - self.green) / delta + 4.0)
+        };
+        let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+        (hue, saturation, max, self.alpha)
+    }
+
+    /// Linearly interpolates each channel between this color and another
+    /// # Arguments
+    /// * `other` - the color to interpolate towards
+    /// * `t` - how far to go, 0.0 = this color, 1.0 = other color
+    pub fn lerp(&self, other: &Color, t: f32) -> Color {
+        Self {
+            red: self.red + (other.red - self.red) * t,
+            green: self.green + (other.green - self.green) * t,
+            blue: self.blue + (other.blue - self.blue) * t,
+            alpha: self.alpha + (other.alpha - self.alpha) * t,
+        }
+    }
+
     pub fn as_rgba(&self) -> u32 {
         let range = 255.0;
         let red = (range * self.red) as u32;
@@ -29,6 +129,112 @@ impl Color {
         rgba |= red;
         rgba
     }
+
+    /// Converts the red, green and blue channels from sRGB, the way colors are stored in an Image,
+    /// to linear light, where mixing colors gives physically correct results. Alpha is unchanged.
+    pub fn to_linear(&self) -> Color {
+        Self::new(
+            srgb_to_linear(self.red),
+            srgb_to_linear(self.green),
+            srgb_to_linear(self.blue),
+            self.alpha,
+        )
+    }
+
+    /// Converts the red, green and blue channels from linear light back to sRGB, the inverse of
+    /// to_linear(). Alpha is unchanged.
+    pub fn to_srgb(&self) -> Color {
+        Self::new(
+            linear_to_srgb(self.red),
+            linear_to_srgb(self.green),
+            linear_to_srgb(self.blue),
+            self.alpha,
+        )
+    }
+}
+
+/// Converts a single sRGB channel, 0.0 - 1.0, to linear light
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a single linear light channel, 0.0 - 1.0, to sRGB
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// A rectangular area, in pixels
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+impl Rect {
+    /// Create a new rectangle
+    /// # Arguments
+    /// * `x` - horizontal coordinate of the left edge
+    /// * `y` - vertical coordinate of the top edge
+    /// * `width` - horizontal size
+    /// * `height` - vertical size
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Gets the horizontal coordinate of the left edge
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+
+    /// Gets the vertical coordinate of the top edge
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+
+    /// Gets the coordinate of the top left corner
+    pub fn position(&self) -> Point {
+        Point::new(self.x, self.y)
+    }
+
+    /// Gets the horizontal size
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    /// Gets the vertical size
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Checks if a point is inside the rectangle
+    /// # Arguments
+    /// * `x` - horizontal coordinate
+    /// * `y` - vertical coordinate
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// Checks if a point is inside the rectangle, see contains()
+    /// # Arguments
+    /// * `point` - the coordinate to check
+    pub fn contains_point(&self, point: Point) -> bool {
+        self.contains(point.x, point.y)
+    }
 }
 
 /// An image that can be displayed by the runtime.
@@ -39,8 +245,22 @@ pub struct Image {
     width: i32,
     height: i32,
     data: Vec<u32>,
+    // Drawing state only, not sent to the runtime:
+    #[serde(skip)]
+    clip: Option<Rect>,
+    #[serde(skip)]
+    srgb_blending: bool,
 }
 
+// Images are equal when they look the same, the drawing state isn't compared:
+impl PartialEq for Image {
+    fn eq(&self, other: &Self) -> bool {
+        self.width == other.width && self.height == other.height && self.data == other.data
+    }
+}
+
+impl Eq for Image {}
+
 impl Image {
     /// Create a blank image
     /// # Arguments
@@ -58,6 +278,8 @@ impl Image {
             width,
             height,
             data: d,
+            clip: None,
+            srgb_blending: false,
         }
     }
 
@@ -80,19 +302,259 @@ impl Image {
             width,
             height,
             data: d,
+            clip: None,
+            srgb_blending: false,
+        }
+    }
+
+    /// Restricts drawing to a rectangle, pixels that set_pixel(), fill(), fill_rect() and the
+    /// blit functions would draw outside of it are discarded. None, the default, allows drawing
+    /// to the whole image. The raw pixel accessors and for_each_pixel() ignore the clip.
+    /// # Arguments
+    /// * `clip` - area to restrict drawing to
+    pub fn set_clip(&mut self, clip: Option<Rect>) {
+        self.clip = clip;
+    }
+
+    /// Gets the rectangle drawing is currently restricted to, if any
+    pub fn clip(&self) -> Option<Rect> {
+        self.clip
+    }
+
+    /// Blend in linear light instead of directly on the stored sRGB values when drawing into this
+    /// image with blit_with_mode() and over(). This makes fades and gradients look even, but is
+    /// slower, and many pixel art games prefer the look of the cheaper blending, so it is off by
+    /// default. Layers are always composited by the runtime without it.
+    /// # Arguments
+    /// * `srgb_blending` - true to blend in linear light
+    pub fn set_srgb_blending(&mut self, srgb_blending: bool) {
+        self.srgb_blending = srgb_blending;
+    }
+
+    /// Gets if blending into this image happens in linear light, see set_srgb_blending()
+    pub fn srgb_blending(&self) -> bool {
+        self.srgb_blending
+    }
+
+    /// Gets the function pixels are blended with, depending on set_srgb_blending()
+    fn blend_function(&self) -> fn(BlendMode, u32, u32) -> u32 {
+        if self.srgb_blending {
+            BlendMode::blend_srgb
+        } else {
+            BlendMode::blend
+        }
+    }
+
+    /// Checks if a pixel can be drawn to with the current clip
+    fn clip_contains(&self, x: i32, y: i32) -> bool {
+        match self.clip {
+            Some(clip) => clip.contains(x, y),
+            None => true,
+        }
+    }
+
+    /// Gets the left, top, right and bottom edges of the part of a rectangle that is both inside
+    /// the image and inside the current clip, right and bottom are exclusive
+    fn clipped_bounds(&self, x: i32, y: i32, width: i32, height: i32) -> (i32, i32, i32, i32) {
+        let mut bounds = (
+            x.max(0),
+            y.max(0),
+            x.saturating_add(width).min(self.width),
+            y.saturating_add(height).min(self.height),
+        );
+        if let Some(clip) = self.clip {
+            bounds.0 = bounds.0.max(clip.x());
+            bounds.1 = bounds.1.max(clip.y());
+            bounds.2 = bounds.2.min(clip.x().saturating_add(clip.width()));
+            bounds.3 = bounds.3.min(clip.y().saturating_add(clip.height()));
+        }
+        bounds
+    }
+
+    /// Sets every pixel in the image, or in the clip rectangle if there is one, to a specified
+    /// color
+    /// # Arguments
+    /// * `color` - color to set the pixels to
+    pub fn fill(&mut self, color: Color) {
+        if self.clip.is_some() {
+            self.fill_rect(Rect::new(0, 0, self.width, self.height), color);
+            return;
+        }
+
+        let color = color.as_rgba();
+        for pixel in self.pixels_mut() {
+            *pixel = color;
+        }
+    }
+
+    /// Sets every pixel in a rectangle to a specified color, parts of the rectangle outside the
+    /// image or the clip rectangle are ignored
+    /// # Arguments
+    /// * `rect` - area to fill
+    /// * `color` - color to set the pixels to
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let (left, top, right, bottom) =
+            self.clipped_bounds(rect.x(), rect.y(), rect.width(), rect.height());
+        let color = color.as_rgba();
+        let width = self.width;
+        for y in top..bottom {
+            for x in left..right {
+                self.data[(y * width + x) as usize] = color;
+            }
+        }
+    }
+
+    /// Replaces the connected area of pixels that are the same color as a starting pixel with
+    /// another color, like the bucket tool in a paint program. Only pixels inside the clip
+    /// rectangle are changed. Does nothing if the starting pixel is outside the clip rectangle or
+    /// is already the fill color.
+    /// # Arguments
+    /// * `x` - horizontal coordinate of the starting pixel
+    /// * `y` - vertical coordinate of the starting pixel
+    /// * `fill` - color to replace the area with
+    ///
+    /// ```
+    /// use romy_core::output::{Color, Image, Rect};
+    ///
+    /// let white = Color::new(1.0, 1.0, 1.0, 1.0);
+    /// let black = Color::new(0.0, 0.0, 0.0, 1.0);
+    /// let red = Color::new(1.0, 0.0, 0.0, 1.0);
+    ///
+    /// // A white image split in two by a black line
+    /// let mut image = Image::new(8, 8, white);
+    /// image.fill_rect(Rect::new(4, 0, 1, 8), black);
+    ///
+    /// image.flood_fill(0, 0, red);
+    /// assert_eq!(image.pixels()[3], red.as_rgba());
+    /// assert_eq!(image.pixels()[4], black.as_rgba());
+    /// assert_eq!(image.pixels()[5], white.as_rgba());
+    /// assert_eq!(image.pixels()[7 * 8 + 3], red.as_rgba());
+    /// ```
+    pub fn flood_fill(&mut self, x: i32, y: i32, fill: Color) {
+        self.flood_fill_with_tolerance(x, y, fill, 0.0);
+    }
+
+    /// Flood fills like flood_fill(), but also spreads into pixels that are close to the color of
+    /// the starting pixel
+    /// # Arguments
+    /// * `x` - horizontal coordinate of the starting pixel
+    /// * `y` - vertical coordinate of the starting pixel
+    /// * `fill` - color to replace the area with
+    /// * `tolerance` - the biggest difference in any one channel that still counts as the same
+    ///   color, 0.0 - 1.0
+    ///
+    /// ```
+    /// use romy_core::output::{Color, Image};
+    ///
+    /// let mut image = Image::new(4, 1, Color::new(0.5, 0.5, 0.5, 1.0));
+    /// image.set_pixel(1, 0, Color::new(0.52, 0.5, 0.5, 1.0));
+    /// image.set_pixel(2, 0, Color::new(0.8, 0.5, 0.5, 1.0));
+    ///
+    /// let red = Color::new(1.0, 0.0, 0.0, 1.0);
+    /// image.flood_fill_with_tolerance(0, 0, red, 0.05);
+    /// assert_eq!(image.pixels()[1], red.as_rgba());
+    /// assert_ne!(image.pixels()[2], red.as_rgba());
+    /// assert_ne!(image.pixels()[3], red.as_rgba());
+    /// ```
+    pub fn flood_fill_with_tolerance(&mut self, x: i32, y: i32, fill: Color, tolerance: f32) {
+        let (left, top, right, bottom) = self.clipped_bounds(0, 0, self.width, self.height);
+        if x < left || x >= right || y < top || y >= bottom {
+            return;
+        }
+
+        let width = self.width;
+        let fill = fill.as_rgba();
+        let target = self.data[(y * width + x) as usize];
+        if target == fill {
+            return;
+        }
+
+        // Tolerant matches can include the fill color, so filled pixels are tracked separately:
+        let tolerance = (tolerance.max(0.0) * 255.0).round() as u32;
+        let mut filled = vec![false; self.data.len()];
+        let data = &mut self.data;
+        let fillable = |data: &[u32], filled: &[bool], x: i32, y: i32| {
+            let index = (y * width + x) as usize;
+            !filled[index] && channel_difference(data[index], target) <= tolerance
+        };
+
+        // Each entry is a seed pixel, the whole horizontal run of matching pixels around it is
+        // filled and the rows above and below are checked for more seeds:
+        let mut stack = vec![(x, y)];
+        while let Some((x, y)) = stack.pop() {
+            if !fillable(data, &filled, x, y) {
+                continue;
+            }
+
+            let mut start = x;
+            while start > left && fillable(data, &filled, start - 1, y) {
+                start -= 1;
+            }
+            let mut end = x + 1;
+            while end < right && fillable(data, &filled, end, y) {
+                end += 1;
+            }
+
+            for x in start..end {
+                let index = (y * width + x) as usize;
+                data[index] = fill;
+                filled[index] = true;
+            }
+
+            for &next_y in &[y - 1, y + 1] {
+                if next_y < top || next_y >= bottom {
+                    continue;
+                }
+
+                let mut in_run = false;
+                for x in start..end {
+                    let matches = fillable(data, &filled, x, next_y);
+                    if matches && !in_run {
+                        stack.push((x, next_y));
+                    }
+                    in_run = matches;
+                }
+            }
         }
     }
 
-    /// Sets a pixel in the image to a specified color
+    /// Sets a pixel in the image to a specified color, does nothing if the pixel is outside the
+    /// clip rectangle
     /// # Arguments
     /// * `x` - horizontal coordinate
     /// * `y` - vertical coordinate
     /// * `color` - color to set the pixel to
     pub fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if !self.clip_contains(x, y) {
+            return;
+        }
+
         let width = self.width();
         self.pixels_mut()[(y * width + x) as usize] = color.as_rgba();
     }
 
+    /// Gets an iterator over every pixel in the image along with its coordinates, goes row by row
+    /// from the top left.
+    pub fn pixels_with_coords<'a>(&'a self) -> impl Iterator<Item = (i32, i32, Color)> + 'a {
+        let width = self.width;
+        self.data.iter().enumerate().map(move |(index, pixel)| {
+            let index = index as i32;
+            (index % width, index / width, Color::from_rgba(*pixel))
+        })
+    }
+
+    /// Rewrites every pixel in the image with a color returned from a function
+    /// # Arguments
+    /// * `f` - function that takes the horizontal and vertical coordinate of a pixel and returns
+    ///   its new color
+    pub fn for_each_pixel(&mut self, mut f: impl FnMut(i32, i32) -> Color) {
+        let width = self.width;
+        for (index, pixel) in self.data.iter_mut().enumerate() {
+            let index = index as i32;
+            *pixel = f(index % width, index / width).as_rgba();
+        }
+    }
+
     /// Gets the number of horizontal pixels
     pub fn width(&self) -> i32 {
         self.width
@@ -103,6 +565,11 @@ impl Image {
         self.height
     }
 
+    /// Gets the number of bytes the pixels of the image take up
+    pub fn byte_size(&self) -> usize {
+        self.data.len() * std::mem::size_of::<u32>()
+    }
+
     /// Gets a reference to the raw pixel buffer
     pub fn pixels(&self) -> &[u32] {
         &self.data
@@ -127,8 +594,67 @@ impl Image {
         }
     }
 
+    /// Finds the smallest rectangle that contains every pixel that differs between this image and
+    /// another one, None if they are identical. If the images are different sizes the whole of
+    /// this image is returned.
+    /// # Arguments
+    /// * `previous` - image to compare against, usually the previous frame
+    pub fn changed_bounds(&self, previous: &Image) -> Option<Rect> {
+        if self.width != previous.width || self.height != previous.height {
+            return Some(Rect::new(0, 0, self.width, self.height));
+        }
+
+        let mut left = self.width;
+        let mut right = -1;
+        let mut top = self.height;
+        let mut bottom = -1;
+        for (index, (current, previous)) in self.data.iter().zip(&previous.data).enumerate() {
+            if current == previous {
+                continue;
+            }
+
+            let x = index as i32 % self.width;
+            let y = index as i32 / self.width;
+            left = left.min(x);
+            right = right.max(x);
+            top = top.min(y);
+            bottom = bottom.max(y);
+        }
+
+        if right < left {
+            return None;
+        }
+
+        Some(Rect::new(left, top, right - left + 1, bottom - top + 1))
+    }
+
+    /// Gets a hash of the dimensions and pixels of this image, useful for cheaply comparing a
+    /// rendered frame against a known good one.
+    ///
+    /// Uses 64 bit FNV-1a so the value is stable across platforms and builds and can be stored.
+    pub fn content_hash(&self) -> u64 {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let mut hash = OFFSET_BASIS;
+        let mut write = |value: u32| {
+            for byte in &value.to_le_bytes() {
+                hash ^= u64::from(*byte);
+                hash = hash.wrapping_mul(PRIME);
+            }
+        };
+
+        write(self.width as u32);
+        write(self.height as u32);
+        for pixel in &self.data {
+            write(*pixel);
+        }
+
+        hash
+    }
+
     /// Draws an image into a section of this one, will ignore fully transparent pixels, but does
-    /// not blend semi-transparent ones.
+    /// not blend semi-transparent ones. Pixels outside the clip rectangle are left untouched.
     /// # Arguments
     /// * `source' - Image to take data from
     /// * `x` - horizontal coordinate to draw at in this image
@@ -136,46 +662,507 @@ impl Image {
     /// * `width` - horizontal pixel span to draw into on this image
     /// * `height` - vertical pixel span to draw into on this image
     pub fn blit(&mut self, source: &Image, x: i32, y: i32, width: i32, height: i32) {
+        self.blit_with_mode(source, x, y, width, height, BlendMode::Opaque);
+    }
+
+    /// Draws an image into a section of this one, combining each pixel with what is already
+    /// there using a blend mode. Pixels outside the clip rectangle are left untouched.
+    /// # Arguments
+    /// * `source' - Image to take data from
+    /// * `x` - horizontal coordinate to draw at in this image
+    /// * `y` - vertical coordinate to draw at in this image
+    /// * `width` - horizontal pixel span to draw into on this image
+    /// * `height` - vertical pixel span to draw into on this image
+    /// * `mode` - how to combine source pixels with the pixels in this image
+    pub fn blit_with_mode(
+        &mut self,
+        source: &Image,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        mode: BlendMode,
+    ) {
         let input_width = source.width();
         let input_height = source.height();
         let output_width = self.width();
+        let output_height = self.height();
         let draw_at_x = x;
         let draw_at_y = y;
 
         let x_ratio = input_width as f32 / width as f32;
         let y_ratio = input_height as f32 / height as f32;
         let pixels = source.pixels();
+        let blend = self.blend_function();
 
         for y in 0..height {
             for x in 0..width {
+                let (out_x, out_y) = (x + draw_at_x, y + draw_at_y);
+                // Checked separately so pixels off the left or right edge don't wrap onto
+                // another row:
+                if out_x < 0 || out_x >= output_width || out_y < 0 || out_y >= output_height {
+                    continue;
+                }
+                if !self.clip_contains(out_x, out_y) {
+                    continue;
+                }
+
                 let sample_x = (x as f32 * x_ratio) as i32;
                 let sample_y = (y as f32 * y_ratio) as i32;
 
                 let output = self.pixels_mut();
 
-                let o = ((y + draw_at_y) * output_width + x + draw_at_x) as usize;
-                if o >= output.len() {
-                    continue;
-                }
+                let o = (out_y * output_width + out_x) as usize;
                 let i = (sample_y * input_width + sample_x) as usize;
                 if i >= pixels.len() {
                     continue;
                 }
 
-                if pixels[i] & 0xFF_00_00_00 != 0xFF_00_00_00 {
+                output[o] = blend(mode, pixels[i], output[o]);
+            }
+        }
+    }
+
+    /// Draws a grid of tiles taken from an atlas in one go, much faster than calling blit() for
+    /// each tile of a large map. Tiles are drawn at their original size, ignoring fully
+    /// transparent pixels like blit(), and tiles that fall outside the image or the clip rectangle
+    /// are skipped without being looked at.
+    /// # Arguments
+    /// * `atlas` - image holding every tile, numbered row by row from the top left
+    /// * `tile_width` - the number of horizontal pixels in each tile
+    /// * `tile_height` - the number of vertical pixels in each tile
+    /// * `tiles` - the atlas tile number for each grid cell, row by row, negative numbers and
+    ///   numbers past the end of the atlas are left empty
+    /// * `columns` - the number of grid cells in each row
+    /// * `scroll` - coordinate of the grid that appears at the top left corner of this image
+    ///
+    /// ```
+    /// use romy_core::math::Point;
+    /// use romy_core::output::{Color, Image};
+    ///
+    /// let red = Color::new(1.0, 0.0, 0.0, 1.0);
+    /// let blue = Color::new(0.0, 0.0, 1.0, 1.0);
+    ///
+    /// // An atlas of two 2x2 tiles, red then blue
+    /// let mut atlas = Image::new(4, 2, red);
+    /// atlas.fill_rect(romy_core::output::Rect::new(2, 0, 2, 2), blue);
+    ///
+    /// // A map of blue, red, empty, scrolled one pixel to the right
+    /// let mut image = Image::new(4, 2, Color::new(0.0, 0.0, 0.0, 1.0));
+    /// image.blit_tiles(&atlas, 2, 2, &[1, 0, -1], 3, Point::new(1, 0));
+    /// assert_eq!(image.pixels()[0], blue.as_rgba());
+    /// assert_eq!(image.pixels()[1], red.as_rgba());
+    /// assert_eq!(image.pixels()[2], red.as_rgba());
+    /// assert_eq!(image.pixels()[3], Color::new(0.0, 0.0, 0.0, 1.0).as_rgba());
+    /// ```
+    pub fn blit_tiles(
+        &mut self,
+        atlas: &Image,
+        tile_width: i32,
+        tile_height: i32,
+        tiles: &[i32],
+        columns: i32,
+        scroll: Point,
+    ) {
+        let (left, top, right, bottom) = self.clipped_bounds(0, 0, self.width, self.height);
+        if tile_width <= 0 || tile_height <= 0 || columns <= 0 || left >= right || top >= bottom {
+            return;
+        }
+
+        let atlas_columns = atlas.width / tile_width;
+        let atlas_tiles = atlas_columns * (atlas.height / tile_height);
+        if atlas_tiles <= 0 {
+            return;
+        }
+
+        // Only the cells that overlap the drawable area are visited:
+        let rows = (tiles.len() as i32 + columns - 1) / columns;
+        let first_column = floor_div(left + scroll.x, tile_width).max(0);
+        let last_column = floor_div(right - 1 + scroll.x, tile_width).min(columns - 1);
+        let first_row = floor_div(top + scroll.y, tile_height).max(0);
+        let last_row = floor_div(bottom - 1 + scroll.y, tile_height).min(rows - 1);
+
+        let blend = self.blend_function();
+        let width = self.width;
+        for row in first_row..=last_row {
+            for column in first_column..=last_column {
+                let tile = match tiles.get((row * columns + column) as usize) {
+                    Some(&tile) if tile >= 0 && tile < atlas_tiles => tile,
+                    _ => continue,
+                };
+
+                let source_x = tile % atlas_columns * tile_width;
+                let source_y = tile / atlas_columns * tile_height;
+                let tile_x = column * tile_width - scroll.x;
+                let tile_y = row * tile_height - scroll.y;
+                let start_x = tile_x.max(left);
+                let end_x = (tile_x + tile_width).min(right);
+                let start_y = tile_y.max(top);
+                let end_y = (tile_y + tile_height).min(bottom);
+
+                for y in start_y..end_y {
+                    let input = (source_y + y - tile_y) * atlas.width + source_x - tile_x;
+                    let output = y * width;
+                    for x in start_x..end_x {
+                        let o = (output + x) as usize;
+                        let i = (input + x) as usize;
+                        self.data[o] = blend(BlendMode::Opaque, atlas.data[i], self.data[o]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Creates a new image with another one alpha blended over this one, the same way as
+    /// BlendMode::Normal, useful for putting a HUD over a scene. Both images are lined up at the
+    /// top left, if they are different sizes only the overlap is blended and the result is the
+    /// size of this image.
+    /// # Arguments
+    /// * `top` - Image to put over this one
+    pub fn over(&self, top: &Image) -> Image {
+        let mut result = Image {
+            width: self.width,
+            height: self.height,
+            data: self.data.clone(),
+            clip: None,
+            srgb_blending: self.srgb_blending,
+        };
+
+        let blend = self.blend_function();
+        let width = self.width.min(top.width);
+        let height = self.height.min(top.height);
+        for y in 0..height {
+            for x in 0..width {
+                let output = &mut result.data[(y * self.width + x) as usize];
+                let source = top.data[(y * top.width + x) as usize];
+                *output = blend(BlendMode::Normal, source, *output);
+            }
+        }
+
+        result
+    }
+
+    /// Draws an image into a section of this one, like blit() but the position and size can be
+    /// fractional and source pixels are bilinearly interpolated, so slow or sub-pixel movement
+    /// (for example scrolling by step_offset) is smooth instead of jumping a whole pixel at a time.
+    ///
+    /// Transparent source pixels don't contribute to the interpolation, output pixels that are
+    /// mostly covered by transparent source pixels are left untouched, as are pixels outside the
+    /// clip rectangle.
+    /// # Arguments
+    /// * `source' - Image to take data from
+    /// * `x` - horizontal coordinate to draw at in this image
+    /// * `y` - vertical coordinate to draw at in this image
+    /// * `width` - horizontal pixel span to draw into on this image
+    /// * `height` - vertical pixel span to draw into on this image
+    pub fn blit_bilinear(&mut self, source: &Image, x: f32, y: f32, width: f32, height: f32) {
+        let input_width = source.width();
+        let input_height = source.height();
+        let output_width = self.width();
+        if input_width <= 0 || input_height <= 0 || width <= 0.0 || height <= 0.0 {
+            return;
+        }
+
+        let x_ratio = input_width as f32 / width;
+        let y_ratio = input_height as f32 / height;
+        let pixels = source.pixels();
+
+        let start_x = x.floor() as i32;
+        let start_y = y.floor() as i32;
+        let (start_x, start_y, end_x, end_y) = self.clipped_bounds(
+            start_x,
+            start_y,
+            (x + width).ceil() as i32 - start_x,
+            (y + height).ceil() as i32 - start_y,
+        );
+
+        for output_y in start_y..end_y {
+            // Position of the pixel center in source space:
+            let sample_y = (output_y as f32 + 0.5 - y) * y_ratio - 0.5;
+            if sample_y < -0.5 || sample_y > input_height as f32 - 0.5 {
+                continue;
+            }
+
+            for output_x in start_x..end_x {
+                let sample_x = (output_x as f32 + 0.5 - x) * x_ratio - 0.5;
+                if sample_x < -0.5 || sample_x > input_width as f32 - 0.5 {
+                    continue;
+                }
+
+                let left = sample_x.floor();
+                let top = sample_y.floor();
+                let fraction_x = sample_x - left;
+                let fraction_y = sample_y - top;
+                let left = left as i32;
+                let top = top as i32;
+
+                let taps = [
+                    (left, top, (1.0 - fraction_x) * (1.0 - fraction_y)),
+                    (left + 1, top, fraction_x * (1.0 - fraction_y)),
+                    (left, top + 1, (1.0 - fraction_x) * fraction_y),
+                    (left + 1, top + 1, fraction_x * fraction_y),
+                ];
+
+                let mut channels = [0.0; 3];
+                let mut coverage = 0.0;
+                for &(tap_x, tap_y, weight) in taps.iter() {
+                    let tap_x = tap_x.max(0).min(input_width - 1);
+                    let tap_y = tap_y.max(0).min(input_height - 1);
+                    let pixel = pixels[(tap_y * input_width + tap_x) as usize];
+                    if pixel & 0xFF_00_00_00 != 0xFF_00_00_00 {
+                        continue;
+                    }
+
+                    for (channel, value) in channels.iter_mut().enumerate() {
+                        *value += ((pixel >> (channel * 8)) & 0xFF) as f32 * weight;
+                    }
+                    coverage += weight;
+                }
+
+                if coverage < 0.5 {
                     continue;
                 }
 
-                output[o] = pixels[i];
+                let mut rgba = 0xFF_00_00_00;
+                for (channel, value) in channels.iter().enumerate() {
+                    let value = (value / coverage).round().clamp(0.0, 255.0) as u32;
+                    rgba |= value << (channel * 8);
+                }
+
+                self.pixels_mut()[(output_y * output_width + output_x) as usize] = rgba;
             }
         }
     }
 }
 
+/// Keeps hold of images that are finished with so their memory can be used again.
+///
+/// Creating an image allocates width * height * 4 bytes, for a 320x240 frame that is 300KiB every
+/// time draw() is called, 60 or more times a second. Taking images from a pool instead means once
+/// the pool has warmed up a frame only costs a fill. Two images are kept, enough to double buffer.
+#[derive(Default)]
+pub struct ImagePool {
+    images: Vec<Image>,
+}
+
+impl ImagePool {
+    /// The most images held on to for reuse
+    const CAPACITY: usize = 2;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets an image filled with a color, reusing a recycled image of the same size if there is
+    /// one, otherwise a new image is created
+    /// # Arguments
+    /// * `width` - the number of horizontal pixels
+    /// * `height` - the number of vertical pixels.
+    /// * `color` - the color of all pixels in the image.
+    pub fn take(&mut self, width: i32, height: i32, color: Color) -> Image {
+        let index = self
+            .images
+            .iter()
+            .position(|image| image.width() == width && image.height() == height);
+        match index {
+            Some(index) => {
+                let mut image = self.images.swap_remove(index);
+                image.set_clip(None);
+                image.set_srgb_blending(false);
+                image.fill(color);
+                image
+            }
+            None => Image::new(width, height, color),
+        }
+    }
+
+    /// Gives an image back to the pool so that take() can reuse it
+    /// # Arguments
+    /// * `image` - the image that is no longer needed
+    pub fn recycle(&mut self, image: Image) {
+        if self.images.len() >= Self::CAPACITY {
+            self.images.remove(0);
+        }
+        self.images.push(image);
+    }
+}
+
+/// Ways of combining a pixel being drawn with the pixel already in an image
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    /// Fully opaque source pixels replace the destination, anything with transparency is skipped.
+    /// This is what Image::blit() does.
+    Opaque,
+    /// Standard alpha blending, semi-transparent source pixels are mixed with the destination
+    Normal,
+    /// Source channels are added to the destination, brightening it, good for glows and light
+    Additive,
+    /// Source channels are multiplied with the destination, darkening it, good for shadows
+    Multiply,
+    /// The inverse of multiplying the inverted channels, brightens without blowing out as quickly
+    /// as additive
+    Screen,
+}
+
+impl BlendMode {
+    /// Combines a source pixel with a destination pixel, both packed 32 bit RGBA values. For all
+    /// modes except Opaque the source alpha controls how strongly the effect is applied.
+    /// # Arguments
+    /// * `source` - the pixel being drawn
+    /// * `destination` - the pixel already in the image
+    pub fn blend(self, source: u32, destination: u32) -> u32 {
+        if let Some(result) = self.blend_trivial(source, destination) {
+            return result;
+        }
+
+        let alpha = source >> 24;
+        let inverse = 0xFF - alpha;
+        let mut result = 0;
+        for shift in &[0, 8, 16] {
+            let source = (source >> shift) & 0xFF;
+            let destination = (destination >> shift) & 0xFF;
+            let blended = match self {
+                BlendMode::Additive => (source + destination).min(0xFF),
+                BlendMode::Multiply => source * destination / 0xFF,
+                BlendMode::Screen => 0xFF - (0xFF - source) * (0xFF - destination) / 0xFF,
+                _ => source,
+            };
+            result |= ((blended * alpha + destination * inverse) / 0xFF) << shift;
+        }
+
+        let destination_alpha = destination >> 24;
+        result | ((alpha + destination_alpha * inverse / 0xFF) << 24)
+    }
+
+    /// Combines a source pixel with a destination pixel like blend(), but converts the colors to
+    /// linear light first and back to sRGB after, so gradients and fades blend evenly. Slower
+    /// than blend().
+    /// # Arguments
+    /// * `source` - the pixel being drawn
+    /// * `destination` - the pixel already in the image
+    pub fn blend_srgb(self, source: u32, destination: u32) -> u32 {
+        if let Some(result) = self.blend_trivial(source, destination) {
+            return result;
+        }
+
+        let source_color = Color::from_rgba(source).to_linear();
+        let destination_color = Color::from_rgba(destination).to_linear();
+        let channel = |source: f32, destination: f32| {
+            let blended = match self {
+                BlendMode::Additive => (source + destination).min(1.0),
+                BlendMode::Multiply => source * destination,
+                BlendMode::Screen => 1.0 - (1.0 - source) * (1.0 - destination),
+                _ => source,
+            };
+            blended * source_color.alpha() + destination * (1.0 - source_color.alpha())
+        };
+        let linear = Color::new(
+            channel(source_color.red(), destination_color.red()),
+            channel(source_color.green(), destination_color.green()),
+            channel(source_color.blue(), destination_color.blue()),
+            0.0,
+        );
+
+        let alpha = source >> 24;
+        let destination_alpha = destination >> 24;
+        let rgb = linear.to_srgb().as_rgba() & 0x00FF_FFFF;
+        rgb | ((alpha + destination_alpha * (0xFF - alpha) / 0xFF) << 24)
+    }
+
+    /// Handles the cases where no mixing is needed, fully opaque or fully transparent sources
+    fn blend_trivial(self, source: u32, destination: u32) -> Option<u32> {
+        let alpha = source >> 24;
+        match self {
+            BlendMode::Opaque => Some(if alpha == 0xFF { source } else { destination }),
+            BlendMode::Normal if alpha == 0xFF => Some(source),
+            _ if alpha == 0 => Some(destination),
+            _ => None,
+        }
+    }
+}
+
+/// Divides, rounding towards negative infinity rather than zero
+fn floor_div(value: i32, divisor: i32) -> i32 {
+    let quotient = value / divisor;
+    if value % divisor != 0 && (value < 0) != (divisor < 0) {
+        quotient - 1
+    } else {
+        quotient
+    }
+}
+
+/// Gets the biggest difference between any channel of two packed pixels
+fn channel_difference(a: u32, b: u32) -> u32 {
+    (0..4)
+        .map(|channel| {
+            let shift = channel * 8;
+            ((a >> shift) & 0xFF).abs_diff((b >> shift) & 0xFF)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// An image positioned within a frame, games can return a stack of these from
+/// Game::draw_layers() and the runtime will composite them together.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Layer {
+    image: Image,
+    x: i32,
+    y: i32,
+    blend_mode: BlendMode,
+}
+
+impl Layer {
+    /// Create a new layer
+    /// # Arguments
+    /// * `image` - the image to draw
+    /// * `x` - horizontal coordinate to draw the image at
+    /// * `y` - vertical coordinate to draw the image at
+    /// * `blend_mode` - how to combine this layer with the ones below it
+    pub fn new(image: Image, x: i32, y: i32, blend_mode: BlendMode) -> Self {
+        Self {
+            image,
+            x,
+            y,
+            blend_mode,
+        }
+    }
+
+    /// Gets the image for this layer
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    /// Takes the image out of this layer
+    pub fn into_image(self) -> Image {
+        self.image
+    }
+
+    /// Gets the horizontal coordinate to draw the image at
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+
+    /// Gets the vertical coordinate to draw the image at
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+
+    /// Gets the coordinate to draw the image at
+    pub fn position(&self) -> Point {
+        Point::new(self.x, self.y)
+    }
+
+    /// Gets how to combine this layer with the ones below it
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+}
+
 /// A sound that can be played by the runtime.
 ///
 /// Internally stores data as an array of 32 bit floating point values that range from -1.0 to 1.0
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Sound {
     sample_rate: i32,
     samples: Vec<f32>,
@@ -193,6 +1180,42 @@ impl Sound {
         }
     }
     
+    /// Create a mono sound by averaging several channels together, useful for playing the
+    /// separate channels returned by a decoder on a mono runtime. An empty slice gives an empty
+    /// sound.
+    ///
+    /// Panics if the channels have different sample rates or lengths.
+    /// # Arguments
+    /// * `channels` - the channels to mix, each as its own sound
+    pub fn mix_channels(channels: &[Sound]) -> Self {
+        let first = match channels.first() {
+            Some(first) => first,
+            None => return Self::from_data(44100, &[]),
+        };
+
+        for channel in channels {
+            assert_eq!(
+                channel.sample_rate, first.sample_rate,
+                "channels must have the same sample rate"
+            );
+            assert_eq!(
+                channel.samples.len(),
+                first.samples.len(),
+                "channels must have the same length"
+            );
+        }
+
+        let scale = 1.0 / channels.len() as f32;
+        let mut mixed = Self::with_buffer_size(first.sample_rate, first.sample_count());
+        for channel in channels {
+            for (mixed, sample) in mixed.samples_mut().iter_mut().zip(channel.samples()) {
+                *mixed += sample * scale;
+            }
+        }
+
+        mixed
+    }
+
     /// Create a blank/silent sound
     /// # Arguments
     /// * `sample_rate` - the number of samples per second
@@ -230,6 +1253,19 @@ impl Sound {
         self.samples.len() as i32
     }
 
+    /// Gets the length of the sound in samples, the same as sample_count()
+    pub fn duration_samples(&self) -> i32 {
+        self.sample_count()
+    }
+
+    /// Gets the length of the sound in seconds
+    pub fn duration_seconds(&self) -> f32 {
+        if self.sample_rate <= 0 {
+            return 0.0;
+        }
+        (self.samples.len() as f64 / f64::from(self.sample_rate)) as f32
+    }
+
     /// Gets a reference to the raw sample data
     pub fn samples(&self) -> &[f32] {
         &self.samples
@@ -240,6 +1276,59 @@ impl Sound {
         &mut self.samples
     }
 
+    /// Hard limits all samples into the range of -1.0 to 1.0
+    pub fn clamp(&mut self) {
+        for sample in self.samples_mut() {
+            *sample = sample.clamp(-1.0, 1.0);
+        }
+    }
+
+    /// Smoothly limits all samples into the range of -1.0 to 1.0 using a tanh curve, this rounds
+    /// off peaks rather than cutting them, which sounds much less harsh than clamp()
+    pub fn soft_clip(&mut self) {
+        for sample in self.samples_mut() {
+            *sample = sample.tanh();
+        }
+    }
+
+    /// Scales all samples so the loudest one has a magnitude of 1.0, silent sounds are left
+    /// unchanged
+    pub fn normalize_peak(&mut self) {
+        let peak = self
+            .samples
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        if peak == 0.0 {
+            return;
+        }
+
+        for sample in self.samples_mut() {
+            *sample /= peak;
+        }
+    }
+
+    /// Scales all samples so the root mean square (average loudness) of the sound matches a
+    /// target, silent sounds are left unchanged. This can push samples outside of -1.0 to 1.0, so
+    /// may need to be followed by clamp() or soft_clip()
+    /// # Arguments
+    /// * `target_rms` - the root mean square to scale to
+    pub fn normalize_rms(&mut self, target_rms: f32) {
+        if self.samples.is_empty() {
+            return;
+        }
+
+        let sum: f32 = self.samples.iter().map(|sample| sample * sample).sum();
+        let rms = (sum / self.samples.len() as f32).sqrt();
+        if rms == 0.0 {
+            return;
+        }
+
+        let scale = target_rms / rms;
+        for sample in self.samples_mut() {
+            *sample *= scale;
+        }
+    }
+
     /// Creates a new sound by sampling a section of this one
     /// # Arguments
     /// * `start' sample start index
@@ -250,4 +1339,554 @@ impl Sound {
             samples: self.samples[(start as usize)..((start + length) as usize)].to_vec(),
         }
     }
-}
\ No newline at end of file
+
+    /// Removes leading and trailing samples that are quieter than a threshold, useful for decoded
+    /// sound effects that would otherwise waste memory and start late
+    /// # Arguments
+    /// * `threshold` - samples with an absolute value below this are treated as silence
+    pub fn trim_silence(&mut self, threshold: f32) {
+        let loud = |sample: &f32| sample.abs() >= threshold;
+        match self.samples.iter().rposition(loud) {
+            Some(end) => {
+                let start = self.samples.iter().position(loud).unwrap();
+                self.samples.truncate(end + 1);
+                self.samples.drain(..start);
+            }
+            None => self.samples.clear(),
+        }
+    }
+
+    /// Creates a copy of this sound with leading and trailing silence removed, see trim_silence()
+    /// # Arguments
+    /// * `threshold` - samples with an absolute value below this are treated as silence
+    pub fn trimmed_silence(&self, threshold: f32) -> Self {
+        let mut result = self.clone();
+        result.trim_silence(threshold);
+        result
+    }
+
+    /// Checks if every sample is quieter than a threshold, an empty sound is silent
+    /// # Arguments
+    /// * `threshold` - samples with an absolute value below this are treated as silence
+    pub fn is_silent(&self, threshold: f32) -> bool {
+        self.samples.iter().all(|sample| sample.abs() < threshold)
+    }
+
+    /// Creates a copy of this sound that plays backwards, good for reversed cymbals and swooshes
+    ///
+    /// ```
+    /// # use romy_core::output::Sound;
+    /// let sound = Sound::from_data(44100, &[0.1, 0.2, 0.3]);
+    /// assert_eq!(sound.reverse().reverse(), sound);
+    /// ```
+    pub fn reverse(&self) -> Self {
+        Self {
+            sample_rate: self.sample_rate,
+            samples: self.samples.iter().rev().cloned().collect(),
+        }
+    }
+
+    /// Creates a copy of this sound played faster or slower, which raises or lowers its pitch. The
+    /// length changes too, shifting up an octave halves it, which is fine for short effects like
+    /// footsteps or coin pickups that just need a little variety.
+    ///
+    /// ```
+    /// # use romy_core::output::Sound;
+    /// let sound = Sound::with_buffer_size(44100, 1000);
+    /// assert_eq!(sound.pitch_shifted(12.0).sample_count(), 500);
+    /// assert_eq!(sound.pitch_shifted(-12.0).sample_count(), 2000);
+    /// ```
+    /// # Arguments
+    /// * `semitones` - how far to shift the pitch, 12.0 is an octave up, negative values go down
+    pub fn pitch_shifted(&self, semitones: f32) -> Self {
+        self.resampled_by(2f64.powf(f64::from(semitones) / 12.0))
+    }
+
+    /// Creates a copy of this sound played at a different speed, linearly interpolating between
+    /// samples
+    fn resampled_by(&self, speed: f64) -> Self {
+        if self.samples.is_empty() || !speed.is_finite() || speed <= 0.0 {
+            return self.clone();
+        }
+
+        let length = (self.samples.len() as f64 / speed).round() as usize;
+        let last = self.samples.len() - 1;
+        let samples = (0..length)
+            .map(|index| {
+                let position = index as f64 * speed;
+                let before = (position as usize).min(last);
+                let after = (before + 1).min(last);
+                let fraction = (position - before as f64) as f32;
+                let (before, after) = (self.samples[before], self.samples[after]);
+                before + (after - before) * fraction
+            })
+            .collect();
+
+        Self {
+            sample_rate: self.sample_rate,
+            samples,
+        }
+    }
+
+    /// Creates a copy of this sound with a repeating echo. The copy is longer than this sound so
+    /// the echoes can ring out until they are around 60dB quieter than the first one.
+    /// # Arguments
+    /// * `delay_samples` - the number of samples between each echo, 0 or less gives an unchanged
+    ///   copy
+    /// * `feedback` - how much quieter each echo is than the last, 0.0 for a single echo, this is
+    ///   limited to 0.95 so the echoes always die out
+    /// * `mix` - how loud the first echo is compared to the original sound
+    pub fn with_echo(&self, delay_samples: i32, feedback: f32, mix: f32) -> Self {
+        const MAX_FEEDBACK: f32 = 0.95;
+        const TAIL_LEVEL: f32 = 0.001;
+
+        if delay_samples <= 0 {
+            return self.clone();
+        }
+
+        let delay = delay_samples as usize;
+        let feedback = feedback.clamp(0.0, MAX_FEEDBACK);
+        let echoes = if feedback > 0.0 {
+            (TAIL_LEVEL.ln() / feedback.ln()).ceil() as usize + 1
+        } else {
+            1
+        };
+        let length = self.samples.len() + delay * echoes;
+
+        // What has gone into the delay line so far, the original plus fed back echoes:
+        let mut line = vec![0.0; length];
+        let mut samples = Vec::with_capacity(length);
+        for index in 0..length {
+            let dry = self.samples.get(index).cloned().unwrap_or(0.0);
+            let delayed = if index >= delay { line[index - delay] } else { 0.0 };
+            line[index] = dry + delayed * feedback;
+            samples.push(dry + delayed * mix);
+        }
+
+        Self {
+            sample_rate: self.sample_rate,
+            samples,
+        }
+    }
+
+    /// Shapes the loudness of this sound with an envelope, see Envelope. The release ends at the
+    /// end of the sound, on silence. Sounds shorter than the attack, decay and release together
+    /// have all three shortened to fit, so they still rise to full volume and fade out.
+    /// # Arguments
+    /// * `envelope` - the envelope to apply
+    pub fn apply_envelope(&mut self, envelope: &Envelope) {
+        let length = self.samples.len();
+        let envelope = envelope.fit(length);
+        let release = envelope.release as usize;
+        let release_start = length - release;
+        let release_from = envelope.level(release_start);
+        for (index, sample) in self.samples.iter_mut().enumerate() {
+            let level = if index < release_start {
+                envelope.level(index)
+            } else {
+                release_from * (length - 1 - index) as f32 / release as f32
+            };
+            *sample *= level;
+        }
+    }
+}
+
+/// An attack, decay, sustain and release envelope, for giving synthesized sounds a shape like a
+/// plucked string or a soft pad. The sound rises from silence to full volume over the attack, falls
+/// to the sustain level over the decay and holds there, then fades out over the release at the
+/// end of the sound.
+///
+/// ```
+/// use romy_core::output::{Envelope, Sound};
+///
+/// let mut sound = Sound::from_data(44100, &[1.0; 100]);
+/// sound.apply_envelope(&Envelope::new(10, 10, 0.5, 20));
+/// assert_eq!(sound.samples()[10], 1.0);
+/// assert_eq!(sound.samples()[50], 0.5);
+/// assert_eq!(sound.samples()[99], 0.0);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    attack: u32,
+    decay: u32,
+    sustain: f32,
+    release: u32,
+}
+
+impl Envelope {
+    /// Create an envelope
+    /// # Arguments
+    /// * `attack` - the number of samples to rise from silence to full volume over
+    /// * `decay` - the number of samples to fall from full volume to the sustain level over
+    /// * `sustain` - the volume held after the decay, 0.0 - 1.0
+    /// * `release` - the number of samples at the end of the sound to fade out over
+    pub fn new(attack: u32, decay: u32, sustain: f32, release: u32) -> Self {
+        Self {
+            attack,
+            decay,
+            sustain: sustain.clamp(0.0, 1.0),
+            release,
+        }
+    }
+
+    /// Gets the number of samples in the attack
+    pub fn attack(&self) -> u32 {
+        self.attack
+    }
+
+    /// Gets the number of samples in the decay
+    pub fn decay(&self) -> u32 {
+        self.decay
+    }
+
+    /// Gets the volume held after the decay
+    pub fn sustain(&self) -> f32 {
+        self.sustain
+    }
+
+    /// Gets the number of samples in the release
+    pub fn release(&self) -> u32 {
+        self.release
+    }
+
+    /// Shortens the attack, decay and release in proportion so they fit in a sound, the release
+    /// always ends up no longer than the sound
+    fn fit(&self, length: usize) -> Self {
+        let (attack, decay, release) = (
+            u64::from(self.attack),
+            u64::from(self.decay),
+            u64::from(self.release),
+        );
+        let (total, length) = (attack + decay + release, length as u64);
+        if total <= length {
+            return *self;
+        }
+
+        let attack = attack * length / total;
+        let decay = decay * length / total;
+        let release = if release > 0 { length - attack - decay } else { 0 };
+        Self::new(attack as u32, decay as u32, self.sustain, release as u32)
+    }
+
+    /// Gets the volume at a sample before the release starts
+    fn level(&self, index: usize) -> f32 {
+        let (attack, decay) = (self.attack as usize, self.decay as usize);
+        if index < attack {
+            index as f32 / attack as f32
+        } else if index < attack + decay {
+            let through = (index - attack) as f32 / decay as f32;
+            1.0 - (1.0 - self.sustain) * through
+        } else {
+            self.sustain
+        }
+    }
+}
+
+/// A named sound with its own gain, games can return many of these from Game::render_voices() and
+/// the runtime will mix them together.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Voice {
+    name: String,
+    gain: f32,
+    sound: Sound,
+}
+
+impl Voice {
+    /// Create a new voice
+    /// # Arguments
+    /// * `name` - name of the voice, for example "music" or "sfx", runtimes can use this to
+    ///   change the volume of specific voices
+    /// * `gain` - the volume the sound should be played at, 1.0 = unchanged
+    /// * `sound` - a steps worth of sound for this voice
+    pub fn new(name: &str, gain: f32, sound: Sound) -> Self {
+        Self {
+            name: name.to_string(),
+            gain,
+            sound,
+        }
+    }
+
+    /// Gets the name of the voice
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the volume the sound should be played at
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Gets the sound for this voice
+    pub fn sound(&self) -> &Sound {
+        &self.sound
+    }
+
+    /// Takes the sound out of this voice
+    pub fn into_sound(self) -> Sound {
+        self.sound
+    }
+}
+
+/// Keeps hold of sounds that are finished with so their memory can be used again, the audio
+/// version of ImagePool.
+///
+/// A game stepping 60 times a second at 44100hz renders 735 samples each step, so building a new
+/// sound, or cloning a prepared one, allocates about 3KiB every step for every voice. Taking sounds
+/// from a pool instead means once the pool has warmed up a step only costs filling in the samples.
+#[derive(Default)]
+pub struct SoundPool {
+    sounds: Vec<Sound>,
+}
+
+impl SoundPool {
+    /// The most sounds held on to for reuse, enough for a few voices
+    const CAPACITY: usize = 8;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets a silent sound, reusing the memory of a recycled sound if there is one, otherwise a
+    /// new sound is created
+    /// # Arguments
+    /// * `sample_rate` - the number of samples per second
+    /// * `sample_count` - the number of samples
+    pub fn take(&mut self, sample_rate: i32, sample_count: i32) -> Sound {
+        let sample_count = sample_count.max(0) as usize;
+        let index = self
+            .sounds
+            .iter()
+            .position(|sound| sound.samples.capacity() >= sample_count);
+        match index {
+            Some(index) => {
+                let mut sound = self.sounds.swap_remove(index);
+                sound.sample_rate = sample_rate;
+                sound.samples.clear();
+                sound.samples.resize(sample_count, 0.0);
+                sound
+            }
+            None => Sound::with_buffer_size(sample_rate, sample_count as i32),
+        }
+    }
+
+    /// Gets a copy of a sound, reusing the memory of a recycled sound if there is one
+    /// # Arguments
+    /// * `source` - the sound to copy
+    pub fn take_copy(&mut self, source: &Sound) -> Sound {
+        let mut sound = self.take(source.sample_rate(), source.sample_count());
+        sound.samples.copy_from_slice(source.samples());
+        sound
+    }
+
+    /// Gives a sound back to the pool so that take() can reuse it
+    /// # Arguments
+    /// * `sound` - the sound that is no longer needed
+    pub fn recycle(&mut self, sound: Sound) {
+        if self.sounds.len() >= Self::CAPACITY {
+            self.sounds.remove(0);
+        }
+        self.sounds.push(sound);
+    }
+}
+
+/// Plays back a sound over many steps, useful for background music or any other sound that is
+/// longer than a single step.
+///
+/// Keeps track of a playback position and hands out the next chunk of samples each time it's
+/// asked, optionally looping back to the start when it reaches the end.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SoundPlayer {
+    sound: Sound,
+    position: i32,
+    looping: bool,
+}
+
+impl SoundPlayer {
+    /// Create a new player that starts at the beginning of a sound and does not loop
+    /// # Arguments
+    /// * `sound` - the sound to play
+    pub fn new(sound: Sound) -> Self {
+        Self {
+            sound,
+            position: 0,
+            looping: false,
+        }
+    }
+
+    /// Gets the sound being played
+    pub fn sound(&self) -> &Sound {
+        &self.sound
+    }
+
+    /// Gets the index of the next sample that will be played
+    pub fn position(&self) -> i32 {
+        self.position
+    }
+
+    /// Moves playback to a specific sample, clamped to the length of the sound
+    /// # Arguments
+    /// * `position` - sample index to play from next
+    pub fn set_position(&mut self, position: i32) {
+        self.position = position.max(0).min(self.sound.sample_count());
+    }
+
+    /// Does playback go back to the start when it reaches the end
+    pub fn looping(&self) -> bool {
+        self.looping
+    }
+
+    /// Sets if playback goes back to the start when it reaches the end
+    /// # Arguments
+    /// * `looping` - true to loop
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    /// Has playback reached the end of the sound, never true for a looping player
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.position >= self.sound.sample_count()
+    }
+
+    /// Takes the next set of samples from the sound and moves the playback position along,
+    /// wrapping around to the start if looping. Once a non looping player has finished any
+    /// remaining samples are silent.
+    /// # Arguments
+    /// * `sample_count` - the number of samples to take
+    pub fn next_samples(&mut self, sample_count: i32) -> Sound {
+        let mut output = Sound::with_buffer_size(self.sound.sample_rate(), sample_count);
+        let length = self.sound.sample_count();
+        if length == 0 {
+            return output;
+        }
+
+        let samples = self.sound.samples();
+        for sample in output.samples_mut() {
+            if self.position >= length {
+                if !self.looping {
+                    break;
+                }
+                self.position = 0;
+            }
+
+            *sample = samples[self.position as usize];
+            self.position += 1;
+        }
+
+        output
+    }
+
+    /// Takes enough samples to cover a single step, see next_samples()
+    /// # Arguments
+    /// * `steps_per_second` - the number of steps per second, at least 1
+    pub fn next_step(&mut self, steps_per_second: i32) -> Sound {
+        self.next_samples(self.sound.sample_rate() / steps_per_second.max(1))
+    }
+}
+
+/// A request to vibrate the rumble motors of any connected controllers.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rumble {
+    low_frequency: f32,
+    high_frequency: f32,
+    duration: u32,
+}
+
+impl Rumble {
+    /// Create a new rumble request
+    /// # Arguments
+    /// * `low_frequency` - intensity of the low frequency (left) motor, 0.0 - 1.0
+    /// * `high_frequency` - intensity of the high frequency (right) motor, 0.0 - 1.0
+    /// * `duration` - how long to rumble for, in milliseconds
+    pub fn new(low_frequency: f32, high_frequency: f32, duration: u32) -> Self {
+        Self {
+            low_frequency: low_frequency.clamp(0.0, 1.0),
+            high_frequency: high_frequency.clamp(0.0, 1.0),
+            duration,
+        }
+    }
+
+    /// Gets the intensity of the low frequency motor, 0.0 - 1.0
+    pub fn low_frequency(&self) -> f32 {
+        self.low_frequency
+    }
+
+    /// Gets the intensity of the high frequency motor, 0.0 - 1.0
+    pub fn high_frequency(&self) -> f32 {
+        self.high_frequency
+    }
+
+    /// Gets how long to rumble for, in milliseconds
+    pub fn duration(&self) -> u32 {
+        self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Color, b: Color) {
+        let channels = |color: Color| [color.red(), color.green(), color.blue(), color.alpha()];
+        for (a_channel, b_channel) in channels(a).iter().zip(channels(b).iter()) {
+            assert!((a_channel - b_channel).abs() < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn hsv_round_trips() {
+        let colors = [
+            Color::new(1.0, 0.0, 0.0, 1.0),
+            Color::new(0.2, 0.8, 0.4, 0.5),
+            Color::new(0.1, 0.3, 0.9, 1.0),
+            Color::new(0.7, 0.2, 0.6, 0.0),
+            Color::new(0.5, 0.5, 0.5, 1.0),
+            Color::new(0.0, 0.0, 0.0, 1.0),
+        ];
+        for color in colors.iter() {
+            let (hue, saturation, value, alpha) = color.to_hsv();
+            assert_close(Color::from_hsv(hue, saturation, value, alpha), *color);
+        }
+
+        // Hues outside of 0 - 360 wrap around:
+        assert_close(Color::from_hsv(-120.0, 1.0, 1.0, 1.0), Color::from_hsv(240.0, 1.0, 1.0, 1.0));
+        assert_close(Color::from_hsv(480.0, 1.0, 1.0, 1.0), Color::from_hsv(120.0, 1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn lerp_reaches_both_ends() {
+        let from = Color::new(0.1, 0.3, 0.9, 1.0);
+        let to = Color::new(0.7, 0.2, 0.6, 0.0);
+
+        assert_close(from.lerp(&to, 0.0), from);
+        assert_close(from.lerp(&to, 1.0), to);
+        assert_close(from.lerp(&to, 0.5), Color::new(0.4, 0.25, 0.75, 0.5));
+    }
+
+    fn enveloped(length: usize, envelope: Envelope) -> Vec<f32> {
+        let mut sound = Sound::from_data(44100, &vec![1.0; length]);
+        sound.apply_envelope(&envelope);
+        sound.samples().to_vec()
+    }
+
+    #[test]
+    fn envelope_peaks_then_sustains() {
+        let samples = enveloped(100, Envelope::new(10, 10, 0.5, 20));
+        assert_eq!(samples[0], 0.0);
+        assert_eq!(samples[5], 0.5);
+        assert_eq!(samples[10], 1.0);
+        assert_eq!(samples[15], 0.75);
+        assert!(samples[20..80].iter().all(|&sample| sample == 0.5));
+        assert!(samples[80] < 0.5);
+        assert_eq!(samples[99], 0.0);
+    }
+
+    #[test]
+    fn short_sounds_still_rise_and_fall() {
+        // 15 samples is too short for the 40 the envelope wants, so it's squeezed to fit:
+        let samples = enveloped(15, Envelope::new(10, 10, 0.5, 20));
+        let peak = samples.iter().cloned().fold(0.0, f32::max);
+        assert_eq!(peak, 1.0);
+        assert_eq!(samples[0], 0.0);
+        assert_eq!(samples[14], 0.0);
+    }
+}
@@ -0,0 +1,89 @@
+//! Deterministic random number generation.
+//!
+//! Romy replays games by re-stepping them from snapshots, so any randomness must come from state
+//! that is part of the game itself. Games should store an Rng in their state and only advance it
+//! inside Game::step(), never seed it from external entropy such as the system clock.
+
+use serde_derive::{Deserialize, Serialize};
+
+const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+const INCREMENT: u64 = 1_442_695_040_888_963_407;
+
+/// A small, fast and deterministic pseudo random number generator (PCG32).
+///
+/// The same seed will always produce the same sequence of numbers on every platform.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a new generator
+    /// # Arguments
+    /// * `seed` - the starting point of the sequence
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    /// Gets the next random u32 in the sequence
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(INCREMENT);
+        let xor_shifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rotation = (old >> 59) as u32;
+        xor_shifted.rotate_right(rotation)
+    }
+
+    /// Gets the next random u64 in the sequence
+    pub fn next_u64(&mut self) -> u64 {
+        (u64::from(self.next_u32()) << 32) | u64::from(self.next_u32())
+    }
+
+    /// Gets the next random f32 in the range of 0.0 - 1.0, 1.0 is never returned
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Gets the next random bool
+    pub fn next_bool(&mut self) -> bool {
+        self.next_u32() & 1 == 1
+    }
+
+    /// Gets a random i32 in a range, the end of the range is excluded
+    /// # Arguments
+    /// * `start` - the lowest value that can be returned
+    /// * `end` - one more than the highest value that can be returned
+    pub fn range(&mut self, start: i32, end: i32) -> i32 {
+        if end <= start {
+            return start;
+        }
+        let span = (i64::from(end) - i64::from(start)) as u64;
+        (i64::from(start) + (u64::from(self.next_u32()) % span) as i64) as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence(seed: u64) -> Vec<u32> {
+        let mut rng = Rng::new(seed);
+        (0..16).map(|_| rng.next_u32()).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_sequence() {
+        assert_eq!(sequence(42), sequence(42));
+        assert_eq!(sequence(0), sequence(0));
+    }
+
+    #[test]
+    fn different_seeds_give_different_sequences() {
+        assert_ne!(sequence(42), sequence(43));
+        assert_ne!(sequence(0), sequence(1));
+    }
+}
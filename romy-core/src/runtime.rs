@@ -2,21 +2,59 @@
 //! platforms, its not intended to be used for other purposes. 
  
 use super::*;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 /// A version of the Game trait with mutable draw/render_audio. Some implementations need this.
 pub trait GameMut {
     fn step(&mut self, arguments: &StepArguments);
     fn draw(&mut self, arguments: &DrawArguments) -> Image;
+    fn draw_layers(&mut self, arguments: &DrawArguments) -> Vec<Layer> {
+        vec![Layer::new(self.draw(arguments), 0, 0, BlendMode::Opaque)]
+    }
     fn render_audio(&mut self, arguments: &RenderAudioArguments) -> Sound;
+    fn render_voices(&mut self, arguments: &RenderAudioArguments) -> Vec<Voice> {
+        vec![Voice::new("main", 1.0, self.render_audio(arguments))]
+    }
+    fn rumble(&mut self) -> Option<Rumble> {
+        None
+    }
+    fn title(&mut self) -> Option<String> {
+        None
+    }
+    /// Gets how many bytes of memory the game is using, None if it can't be measured, like for
+    /// games compiled into the runtime
+    fn memory_usage(&mut self) -> Option<usize> {
+        None
+    }
+    /// Copies out the games memory so it can be moved into a rebuilt version of the game with
+    /// load_memory(), None if the memory can't be copied
+    fn save_memory(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+    /// Replaces the games memory with memory from save_memory(), returns false if it couldn't be
+    /// replaced, leaving the game as it was
+    fn load_memory(&mut self, _memory: &[u8]) -> bool {
+        false
+    }
+    /// Gets a fingerprint of how the games memory is laid out, memory from save_memory() only
+    /// makes sense to a build with the same fingerprint. None if it isn't known
+    fn memory_layout(&mut self) -> Option<u64> {
+        None
+    }
 }
 
 /// A wrapper to convert a immutable Game to a mutable one
 pub struct GameMutMap {
-    game: Box<Game>,
+    game: Box<dyn Game>,
 }
 
 impl GameMutMap {
-    pub fn new(game: Box<Game>) -> Self {
+    pub fn new(game: Box<dyn Game>) -> Self {
         Self { game }
     }
 }
@@ -28,19 +66,1160 @@ impl GameMut for GameMutMap {
     fn draw(&mut self, arguments: &DrawArguments) -> Image {
         self.game.draw(arguments)
     }
+    fn draw_layers(&mut self, arguments: &DrawArguments) -> Vec<Layer> {
+        self.game.draw_layers(arguments)
+    }
     fn render_audio(&mut self, arguments: &RenderAudioArguments) -> Sound {
         self.game.render_audio(arguments)
     }
+    fn render_voices(&mut self, arguments: &RenderAudioArguments) -> Vec<Voice> {
+        self.game.render_voices(arguments)
+    }
+    fn rumble(&mut self) -> Option<Rumble> {
+        self.game.rumble()
+    }
+    fn title(&mut self) -> Option<String> {
+        self.game.title()
+    }
+}
+
+/// What a runtime does when a game uses more memory than its MemoryLimit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryLimitAction {
+    /// Print a warning the first time it happens and keep running
+    Warn,
+    /// Stop the game
+    Halt,
+}
+
+/// A limit on how much memory a game can use, checked by runtimes once a frame. A game that leaks
+/// memory would otherwise grow until the machine runs out, with nothing saying why.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryLimit {
+    pub max_bytes: usize,
+    pub action: MemoryLimitAction,
+}
+
+impl Default for MemoryLimit {
+    fn default() -> Self {
+        Self {
+            max_bytes: 512 * 1024 * 1024,
+            action: MemoryLimitAction::Warn,
+        }
+    }
+}
+
+impl MemoryLimit {
+    /// Checks how much memory a game is using, giving a message to report if it's over the limit
+    ///
+    /// # Arguments
+    /// * `used` - The number of bytes of memory the game is using, see GameMut::memory_usage()
+    pub fn check(&self, used: usize) -> Option<String> {
+        if used <= self.max_bytes {
+            return None;
+        }
+        Some(format!(
+            "game is using {} bytes of memory, over the limit of {}",
+            used, self.max_bytes
+        ))
+    }
+}
+
+/// Limits on the size of images a game can hand to a runtime. Images come from untrusted games, so
+/// need checking before a runtime allocates or uploads anything based on their claimed size.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageLimits {
+    pub max_width: i32,
+    pub max_height: i32,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        Self {
+            max_width: 4096,
+            max_height: 4096,
+        }
+    }
+}
+
+impl ImageLimits {
+    /// Checks that an image is within the limits, and that it holds as many pixels as it claims
+    /// to.
+    ///
+    /// # Arguments
+    /// * `image` - The image to check
+    pub fn check(&self, image: &Image) -> Result<(), String> {
+        let (width, height) = (image.width(), image.height());
+        if width <= 0 || height <= 0 || width > self.max_width || height > self.max_height {
+            return Err(format!(
+                "image size {}x{} is outside of the allowed 1x1 - {}x{}",
+                width, height, self.max_width, self.max_height
+            ));
+        }
+
+        if image.pixels().len() as i64 != i64::from(width) * i64::from(height) {
+            return Err(format!(
+                "image claims to be {}x{} but has {} pixels",
+                width,
+                height,
+                image.pixels().len()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks every layer in a stack, see check(). Layers with a width or height of 0 are allowed,
+    /// they have nothing to draw and a game can return one to skip a frame.
+    ///
+    /// # Arguments
+    /// * `layers` - The layers to check
+    pub fn check_layers(&self, layers: &[Layer]) -> Result<(), String> {
+        layers
+            .iter()
+            .map(Layer::image)
+            .filter(|image| image.width() != 0 && image.height() != 0)
+            .try_for_each(|image| self.check(image))
+    }
+}
+
+/// Checks if a game chose not to draw a frame, by returning no layers or an empty first layer.
+/// Runtimes should keep showing the previous frame instead of compositing these.
+///
+/// # Arguments
+/// * `layers` - The layers returned from the game
+pub fn is_skipped_frame(layers: &[Layer]) -> bool {
+    match layers.first() {
+        Some(base) => base.image().width() <= 0 || base.image().height() <= 0,
+        None => true,
+    }
+}
+
+/// Composites a stack of layers returned from a game into a single image ready for display. The
+/// first layer sets the size of the frame, the rest are drawn over it in order using their blend
+/// modes.
+///
+/// # Arguments
+/// * `layers` - The layers to composite
+pub fn composite(layers: Vec<Layer>) -> Image {
+    let mut layers = layers.into_iter();
+    let base = match layers.next() {
+        Some(base) => base,
+        None => return Image::new(0, 0, Color::new(0.0, 0.0, 0.0, 0.0)),
+    };
+
+    let mut layers = layers.peekable();
+    if base.x() == 0 && base.y() == 0 && layers.peek().is_none() {
+        // Common case of a plain draw(), no need to copy anything:
+        return base.into_image();
+    }
+
+    let mut frame = Image::new(
+        base.image().width(),
+        base.image().height(),
+        Color::new(0.0, 0.0, 0.0, 0.0),
+    );
+    for layer in std::iter::once(base).chain(layers) {
+        let image = layer.image();
+        frame.blit_with_mode(
+            image,
+            layer.x(),
+            layer.y(),
+            image.width(),
+            image.height(),
+            layer.blend_mode(),
+        );
+    }
+
+    frame
+}
+
+/// Works out the arguments for a call to Game::render_audio(), asking for enough samples to bring
+/// the queue waiting for the audio device back up to a steps worth plus two device buffers. The
+/// device drains the queue at its own rate, so this asks for what it played since the last call
+/// and the game stays locked to it.
+///
+/// # Arguments
+/// * `info` - The info of the game being run
+/// * `queued` - The number of samples waiting to be played
+/// * `device_buffer` - The number of samples the audio device takes at a time
+pub fn render_audio_arguments(
+    info: &Info,
+    queued: usize,
+    device_buffer: usize,
+) -> RenderAudioArguments {
+    let target = info.audio_sample_count() as usize + device_buffer * 2;
+    RenderAudioArguments::new(target.saturating_sub(queued) as i32)
+}
+
+/// How full a runtime's queue of samples waiting for the audio device is, for tools and debugging
+/// that want to see how much latency the audio has or if it is running dry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioQueueStatus {
+    queued: usize,
+    device_buffer: usize,
+}
+
+impl AudioQueueStatus {
+    /// Create a status
+    ///
+    /// # Arguments
+    /// * `queued` - The number of samples waiting to be played
+    /// * `device_buffer` - The number of samples the audio device takes at a time
+    pub fn new(queued: usize, device_buffer: usize) -> Self {
+        Self {
+            queued,
+            device_buffer,
+        }
+    }
+
+    /// The number of samples waiting to be played
+    pub fn queued_samples(&self) -> usize {
+        self.queued
+    }
+
+    /// The number of samples the audio device takes at a time
+    pub fn device_buffer(&self) -> usize {
+        self.device_buffer
+    }
+
+    /// How long the queued samples take to play, roughly how far behind the game the audio is
+    pub fn latency(&self) -> Duration {
+        let nanos = self.queued as u64 * 1_000_000_000;
+        Duration::from_nanos(nanos / RenderAudioArguments::SAMPLE_RATE as u64)
+    }
+
+    /// Is there less queued than the device takes at a time, runtimes play silence rather than
+    /// part of a buffer so the next callback will be a gap in the audio
+    pub fn is_starved(&self) -> bool {
+        self.queued < self.device_buffer
+    }
+}
+
+/// Converts a pointer position from window coordinates into a pixel position within the games
+/// rendered image, returning None if the pointer is outside of the image.
+///
+/// Pointer events are in window coordinates, but with high DPI enabled the image is drawn in
+/// drawable coordinates, which are bigger by the displays scale factor. For a 640x480 window on a
+/// 2x display the drawable is 1280x960; a 320x240 game filling it is drawn to the viewport
+/// (0, 0, 1280, 960), and a pointer at (320, 240) in the window is first scaled to (640, 480) in
+/// the drawable and then to (160, 120) in the image. Skipping the first step would give (80, 60).
+///
+/// # Arguments
+/// * `x` - Horizontal pointer position in window coordinates
+/// * `y` - Vertical pointer position in window coordinates
+/// * `window_size` - Size of the window, in window coordinates
+/// * `drawable_size` - Size of the drawable area of the window, in physical pixels
+/// * `viewport` - The area of the drawable the image is drawn to, in physical pixels
+/// * `render_size` - Size of the image the game drew
+pub fn pointer_to_render(
+    x: f32,
+    y: f32,
+    window_size: (u32, u32),
+    drawable_size: (u32, u32),
+    viewport: Rect,
+    render_size: (i32, i32),
+) -> Option<(f32, f32)> {
+    let (width, height) = (viewport.width() as f32, viewport.height() as f32);
+    if window_size.0 == 0 || window_size.1 == 0 || width <= 0.0 || height <= 0.0 {
+        return None;
+    }
+
+    let x = x * drawable_size.0 as f32 / window_size.0 as f32 - viewport.x() as f32;
+    let y = y * drawable_size.1 as f32 / window_size.1 as f32 - viewport.y() as f32;
+    if x < 0.0 || y < 0.0 || x >= width || y >= height {
+        return None;
+    }
+
+    Some((x * render_size.0 as f32 / width, y * render_size.1 as f32 / height))
+}
+
+/// Converts a pixel position within the games rendered image back to the center of that pixel in
+/// the viewport, the reverse of pointer_to_render() without the window scaling. Runtimes use this
+/// to draw debugging markers over the pixel under the pointer.
+///
+/// # Arguments
+/// * `x` - Horizontal position in the image, as returned from pointer_to_render()
+/// * `y` - Vertical position in the image, as returned from pointer_to_render()
+/// * `viewport` - The area of the drawable the image is drawn to, in physical pixels
+/// * `render_size` - Size of the image the game drew
+pub fn render_pixel_center(x: f32, y: f32, viewport: Rect, render_size: (i32, i32)) -> (f32, f32) {
+    let pixel_width = viewport.width() as f32 / render_size.0.max(1) as f32;
+    let pixel_height = viewport.height() as f32 / render_size.1.max(1) as f32;
+    (
+        viewport.x() as f32 + (x.floor() + 0.5) * pixel_width,
+        viewport.y() as f32 + (y.floor() + 0.5) * pixel_height,
+    )
+}
+
+/// Mixes the voices returned from a game into a single sound, ready to be sent to an audio device.
+///
+/// Each voice is scaled by its own gain, and then by a gain set on the mixer for voices with that
+/// name, allowing runtimes to offer volume controls for things like music.
+#[derive(Default, Clone)]
+pub struct Mixer {
+    gains: HashMap<String, f32>,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the gain applied to voices with a specific name, 1.0 if one hasn't been set
+    ///
+    /// # Arguments
+    /// * `name` - Name of the voice
+    pub fn gain(&self, name: &str) -> f32 {
+        *self.gains.get(name).unwrap_or(&1.0)
+    }
+
+    /// Sets the gain applied to voices with a specific name
+    ///
+    /// # Arguments
+    /// * `name` - Name of the voice
+    /// * `gain` - The gain, 1.0 = unchanged, 0.0 = silent
+    pub fn set_gain(&mut self, name: &str, gain: f32) {
+        self.gains.insert(name.to_string(), gain);
+    }
+
+    /// Sums a set of voices into one sound, the result is as long as the longest voice and is
+    /// clamped into the range of -1.0 to 1.0
+    ///
+    /// # Arguments
+    /// * `voices` - The voices to mix
+    pub fn mix(&self, voices: &[Voice]) -> Sound {
+        let sample_rate = voices
+            .first()
+            .map(|voice| voice.sound().sample_rate())
+            .unwrap_or(44100);
+        let sample_count = voices
+            .iter()
+            .map(|voice| voice.sound().sample_count())
+            .max()
+            .unwrap_or(0);
+
+        let mut output = Sound::with_buffer_size(sample_rate, sample_count);
+        for voice in voices {
+            let gain = voice.gain() * self.gain(voice.name());
+            if gain == 0.0 || voice.sound().is_silent(f32::EPSILON) {
+                continue;
+            }
+            for (mixed, sample) in output.samples_mut().iter_mut().zip(voice.sound().samples()) {
+                *mixed += sample * gain;
+            }
+        }
+
+        output.clamp();
+        output
+    }
+}
+
+/// Where a runtime gets the current time from, so anything that drives a game by time can be run
+/// with a clock that only moves when told to, see ManualClock
+pub trait Clock {
+    /// Gets the time passed since some fixed point, like the clock being created. Only the
+    /// difference between two readings means anything, and readings never go backwards
+    fn now(&self) -> Duration;
+}
+
+impl<C: Clock + ?Sized> Clock for Rc<C> {
+    fn now(&self) -> Duration {
+        (**self).now()
+    }
+}
+
+/// Clock that follows real time, measured from when it was created
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Clock that starts at zero and only moves forward when advance() is called, for tests and
+/// headless runners. Share it with an Rc to keep advancing it after handing it to a runtime.
+#[derive(Default)]
+pub struct ManualClock {
+    now: Cell<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the clock forward
+    ///
+    /// # Arguments
+    /// * `by` - How much time passes
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
+
+/// What a StepScheduler does when more steps are due than it is allowed to run in one frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CatchUpPolicy {
+    /// Throw away the time that couldn't be run, the game skips ahead but stays responsive
+    DropTime,
+    /// Keep the missing time and work through it a frames worth of steps at a time, the game runs
+    /// slow until it has caught up
+    RunSlow,
+}
+
+/// How a StepScheduler reads the time passed each frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FramePacing {
+    /// Use the time as it is given
+    Exact,
+    /// Move on by the average time between the last few frames, staying within half a frame of
+    /// the time given. Evens out jitter in when frames are presented, which otherwise makes the
+    /// step offset wobble and motion stutter, while steps still follow the time that has passed.
+    Smoothed,
+}
+
+/// Works out which steps of a game need running each frame, given how much time has passed.
+///
+/// After a stall, like a breakpoint or a hidden browser tab, a runtime can find itself many steps
+/// behind. Running them all at once can take longer than real time, leaving it even further
+/// behind, so the scheduler never hands out more than max_steps_per_frame steps at a time.
+pub struct StepScheduler {
+    step: Duration,
+    max_steps_per_frame: u32,
+    policy: CatchUpPolicy,
+    steps: u64,
+    dropped_time: Duration,
+    dropped_steps: u64,
+    // Steps and game time at the last change of step interval, steps are counted on from here:
+    base_steps: u64,
+    base_time: Duration,
+    pacing: FramePacing,
+    frame_times: VecDeque<Duration>,
+    last_elapsed: Option<Duration>,
+    paced: Duration,
+}
+
+impl StepScheduler {
+    /// The number of frames averaged with FramePacing::Smoothed
+    const PACING_FRAMES: usize = 8;
+
+    /// Create a scheduler that starts at step 0
+    ///
+    /// # Arguments
+    /// * `step_interval` - Time between steps in nanoseconds, as in Info::step_interval()
+    /// * `max_steps_per_frame` - The most steps to run in one call to update(), at least 1
+    /// * `policy` - What to do with the time that can't be run
+    pub fn new(step_interval: u32, max_steps_per_frame: u32, policy: CatchUpPolicy) -> Self {
+        Self {
+            step: Duration::from_nanos(u64::from(step_interval.max(1))),
+            max_steps_per_frame: max_steps_per_frame.max(1),
+            policy,
+            steps: 0,
+            dropped_time: Duration::from_secs(0),
+            dropped_steps: 0,
+            base_steps: 0,
+            base_time: Duration::from_secs(0),
+            pacing: FramePacing::Exact,
+            frame_times: VecDeque::with_capacity(Self::PACING_FRAMES),
+            last_elapsed: None,
+            paced: Duration::from_secs(0),
+        }
+    }
+
+    /// Changes how the time given to update() is read, defaults to FramePacing::Exact
+    ///
+    /// # Arguments
+    /// * `pacing` - The frame pacing to use
+    pub fn with_frame_pacing(mut self, pacing: FramePacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Changes the time between steps from now on, for slowing a game down or speeding it up while
+    /// debugging. Counting restarts from the current step, so the game doesn't jump forwards or
+    /// backwards.
+    ///
+    /// # Arguments
+    /// * `step_interval` - Time between steps in nanoseconds, as in Info::step_interval()
+    /// * `elapsed` - The time since the game started, as given to update()
+    pub fn set_step_interval(&mut self, step_interval: u32, elapsed: Duration) {
+        self.base_steps = self.steps;
+        self.base_time += self.game_time(elapsed);
+        self.step = Duration::from_nanos(u64::from(step_interval.max(1)));
+    }
+
+    /// Gets the indices of the steps that should be run now, in order
+    ///
+    /// # Arguments
+    /// * `elapsed` - The time since the game started, excluding any time it was paused
+    pub fn update(&mut self, elapsed: Duration) -> Range<u64> {
+        let elapsed = self.pace(elapsed);
+        let elapsed = self.game_time(elapsed);
+        let expected_steps = self.base_steps + (elapsed.as_nanos() / self.step.as_nanos()) as u64;
+        let due = expected_steps.saturating_sub(self.steps);
+        let max = u64::from(self.max_steps_per_frame);
+
+        self.dropped_steps = 0;
+        let run = if due > max {
+            if self.policy == CatchUpPolicy::DropTime {
+                self.dropped_steps = due - max;
+                self.dropped_time +=
+                    Duration::from_nanos(self.step.as_nanos() as u64 * self.dropped_steps);
+            }
+            max
+        } else {
+            due
+        };
+
+        let first = self.steps;
+        self.steps += run;
+        first..self.steps
+    }
+
+    /// How far through the current step the game is, in the range of 0 - 1, for passing to
+    /// DrawArguments
+    ///
+    /// # Arguments
+    /// * `elapsed` - The time since the game started, as given to update()
+    pub fn step_offset(&self, elapsed: Duration) -> f32 {
+        let elapsed = match self.last_elapsed {
+            Some(last_elapsed) if last_elapsed == elapsed => self.paced,
+            _ => elapsed,
+        };
+        let elapsed = self.game_time(elapsed);
+        (elapsed.as_nanos() % self.step.as_nanos()) as f32 / self.step.as_nanos() as f32
+    }
+
+    /// The number of steps that have been handed out so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The number of steps thrown away by the last update(), always 0 with RunSlow
+    pub fn dropped_steps(&self) -> u64 {
+        self.dropped_steps
+    }
+
+    /// Gets the time between steps
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// Applies the frame pacing to the time given to update(), remembering the result for
+    /// step_offset()
+    fn pace(&mut self, elapsed: Duration) -> Duration {
+        let last_elapsed = self.last_elapsed.replace(elapsed);
+        self.paced = match (self.pacing, last_elapsed) {
+            (FramePacing::Smoothed, Some(last_elapsed)) => {
+                if self.frame_times.len() == Self::PACING_FRAMES {
+                    self.frame_times.pop_front();
+                }
+                self.frame_times
+                    .push_back(elapsed.checked_sub(last_elapsed).unwrap_or_default());
+                let average =
+                    self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32;
+
+                let paced = self.paced + average;
+                let slack = average / 2;
+                if paced > elapsed + slack {
+                    elapsed + slack
+                } else if paced + slack < elapsed {
+                    elapsed - slack
+                } else {
+                    paced
+                }
+            }
+            _ => elapsed,
+        };
+        self.paced
+    }
+
+    /// Time the game has spent at the current step interval, not counting dropped time
+    fn game_time(&self, elapsed: Duration) -> Duration {
+        elapsed
+            .checked_sub(self.dropped_time + self.base_time)
+            .unwrap_or_else(|| Duration::from_secs(0))
+    }
+}
+
+/// Where a game's saved data lives, runtimes back the storage imports with one of these for each
+/// game. Keys come from untrusted games, so keys that aren't safe to use as file names are
+/// refused, see Storage::check_key().
+pub enum Storage {
+    /// Kept in memory and lost when the game is closed
+    Memory(HashMap<String, Vec<u8>>),
+    /// Kept on disk, one file per key in the directory
+    Directory(PathBuf),
+}
+
+impl Storage {
+    /// The largest amount of data a game can save under one key, in bytes
+    pub const MAX_SIZE: usize = 1024 * 1024;
+
+    /// The longest key a game can save under, in characters
+    pub const MAX_KEY_LENGTH: usize = 64;
+
+    /// Create storage that isn't persisted anywhere
+    pub fn memory() -> Self {
+        Storage::Memory(HashMap::new())
+    }
+
+    /// Create storage on disk for a game, in a directory named after it under ~/.romy/saves, or
+    /// ./romy-saves if there is no home directory
+    ///
+    /// # Arguments
+    /// * `name` - Name identifying the game, usually the file name of the game without extension
+    pub fn for_game(name: &str) -> Self {
+        let home = std::env::var_os("HOME").or_else(|| std::env::var_os("APPDATA"));
+        let saves = match home {
+            Some(home) => PathBuf::from(home).join(".romy").join("saves"),
+            None => PathBuf::from("romy-saves"),
+        };
+        Storage::Directory(saves.join(Self::sanitize(name)))
+    }
+
+    /// Checks a key can be saved under, keys are 1 - MAX_KEY_LENGTH letters, numbers, dashes and
+    /// underscores. Every runtime refuses other keys rather than changing them, so two keys can
+    /// never end up sharing data.
+    ///
+    /// # Arguments
+    /// * `key` - The key to check
+    pub fn check_key(key: &str) -> Result<(), String> {
+        if key.is_empty() {
+            return Err("the key is empty".to_string());
+        }
+        if key.len() > Self::MAX_KEY_LENGTH {
+            return Err(format!(
+                "the key is longer than {} characters",
+                Self::MAX_KEY_LENGTH
+            ));
+        }
+        if let Some(c) = key.chars().find(|&c| !Self::is_key_char(c)) {
+            return Err(format!(
+                "{:?} can't be used in a key, only letters, numbers, dashes and underscores can",
+                c
+            ));
+        }
+        Ok(())
+    }
+
+    /// Loads the data saved under a key, None if nothing has been saved or the key isn't valid
+    ///
+    /// # Arguments
+    /// * `key` - The key the data was saved under
+    pub fn load(&self, key: &str) -> Option<Vec<u8>> {
+        Self::check_key(key).ok()?;
+        match self {
+            Storage::Memory(map) => map.get(key).cloned(),
+            Storage::Directory(directory) => std::fs::read(directory.join(key)).ok(),
+        }
+    }
+
+    /// Saves some data under a key, replacing anything already saved there
+    ///
+    /// # Arguments
+    /// * `key` - The key to save under, see check_key()
+    /// * `data` - The data to save, at most MAX_SIZE bytes
+    pub fn save(&mut self, key: &str, data: &[u8]) -> Result<(), String> {
+        Self::check_key(key)?;
+        if data.len() > Self::MAX_SIZE {
+            return Err(format!(
+                "{} bytes is more than the {} that can be saved under one key",
+                data.len(),
+                Self::MAX_SIZE
+            ));
+        }
+
+        match self {
+            Storage::Memory(map) => {
+                map.insert(key.to_string(), data.to_vec());
+                Ok(())
+            }
+            Storage::Directory(directory) => {
+                std::fs::create_dir_all(&directory).map_err(|error| error.to_string())?;
+                std::fs::write(directory.join(key), data)
+                    .map_err(|error| error.to_string())
+            }
+        }
+    }
+
+    fn is_key_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '-' || c == '_'
+    }
+
+    /// Makes a game name safe to use as a directory name, anything other than letters, numbers,
+    /// dashes and underscores is replaced
+    fn sanitize(name: &str) -> String {
+        let name: String = name
+            .chars()
+            .take(Self::MAX_KEY_LENGTH)
+            .map(|c| if Self::is_key_char(c) { c } else { '_' })
+            .collect();
+        if name.is_empty() {
+            "_".to_string()
+        } else {
+            name
+        }
+    }
 }
 
 /// A structure for holding a game and its info struct together
 pub struct RunBundle {
-    pub game: Box<GameMut>,
+    pub game: Box<dyn GameMut>,
     pub info: Info,
 }
 
 impl RunBundle {
-    pub fn new(game: Box<GameMut>, info: Info) -> Self {
+    pub fn new(game: Box<dyn GameMut>, info: Info) -> Self {
         Self { game, info }
     }
 }
+
+/// A saved copy of a games memory along with what it was saved from, so it can be written to a
+/// file and safely restored later, possibly on another machine. Restoring refuses saves from a
+/// different game or API version, as their memory wouldn't make sense.
+///
+/// ```
+/// use romy_core::input::InputDeviceType;
+/// use romy_core::runtime::SaveBundle;
+/// use romy_core::{Info, API_VERSION};
+///
+/// let info = Info::new("Game", 60, 1, InputDeviceType::Nes);
+/// let save = SaveBundle::new(info.clone(), API_VERSION, vec![1, 2, 3]);
+/// let loaded = SaveBundle::decode(&save.encode()).unwrap();
+/// assert_eq!(loaded.memory(), &[1, 2, 3]);
+/// assert!(loaded.check(&info, API_VERSION).is_ok());
+///
+/// // Saves from other games are refused:
+/// let other = Info::new("Other Game", 60, 1, InputDeviceType::Nes);
+/// assert!(loaded.check(&other, API_VERSION).is_err());
+///
+/// // As are saves from other API versions:
+/// assert!(loaded.check(&info, API_VERSION + 1).is_err());
+///
+/// // And data that isn't a save at all:
+/// assert!(SaveBundle::decode(b"not a save").is_err());
+/// let mut truncated = save.encode();
+/// truncated.truncate(truncated.len() - 1);
+/// assert!(SaveBundle::decode(&truncated).is_err());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SaveBundle {
+    info: Info,
+    api_version: i32,
+    memory: Vec<u8>,
+}
+
+impl SaveBundle {
+    /// Bytes at the start of every encoded save
+    const MAGIC: &'static [u8; 8] = b"ROMYSAVE";
+    /// Version of the encoded layout, bumped when SaveBundle changes
+    const FORMAT_VERSION: u32 = 1;
+
+    /// Create a save from memory that has already been copied out of a game
+    ///
+    /// # Arguments
+    /// * `info` - Info of the game the memory came from
+    /// * `api_version` - The API version the game was built against
+    /// * `memory` - The games memory, from GameMut::save_memory()
+    pub fn new(info: Info, api_version: i32, memory: Vec<u8>) -> Self {
+        Self {
+            info,
+            api_version,
+            memory,
+        }
+    }
+
+    /// Saves a running game, None if its memory can't be copied
+    ///
+    /// # Arguments
+    /// * `bundle` - The game to save
+    /// * `api_version` - The API version the game was built against
+    pub fn capture(bundle: &mut RunBundle, api_version: i32) -> Option<Self> {
+        let memory = bundle.game.save_memory()?;
+        Some(Self::new(bundle.info.clone(), api_version, memory))
+    }
+
+    /// Gets the info of the game that was saved
+    pub fn info(&self) -> &Info {
+        &self.info
+    }
+
+    /// Gets the API version of the game that was saved
+    pub fn api_version(&self) -> i32 {
+        self.api_version
+    }
+
+    /// Gets the saved memory
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Encodes the save as bytes for writing to a file
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Self::MAGIC.to_vec();
+        data.extend(Self::FORMAT_VERSION.to_le_bytes().iter());
+        data.extend(serial::encode_with_size(self));
+        data
+    }
+
+    /// Decodes a save from bytes made by encode(), returning why if they aren't a save this
+    /// version of Romy can read
+    ///
+    /// # Arguments
+    /// * `data` - The encoded save
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        let header = Self::MAGIC.len() + 4;
+        if data.len() < header || &data[..Self::MAGIC.len()] != Self::MAGIC {
+            return Err("not a Romy save".to_string());
+        }
+
+        let mut format_version = [0; 4];
+        format_version.copy_from_slice(&data[Self::MAGIC.len()..header]);
+        let format_version = u32::from_le_bytes(format_version);
+        if format_version != Self::FORMAT_VERSION {
+            return Err(format!("unsupported save format version {}", format_version));
+        }
+
+        let body = &data[header..];
+        if body.len() < 8 {
+            return Err("save is truncated".to_string());
+        }
+        let mut size = [0; 8];
+        size.copy_from_slice(&body[..8]);
+        if body.len() as u64 - 8 != u64::from_le_bytes(size) {
+            return Err("save is truncated".to_string());
+        }
+
+        serial::try_decode_with_size(body).ok_or_else(|| "save is corrupt".to_string())
+    }
+
+    /// Checks the save can be restored onto a game, returning why if it can't
+    ///
+    /// # Arguments
+    /// * `info` - Info of the game to restore onto
+    /// * `api_version` - The API version the game was built against
+    pub fn check(&self, info: &Info, api_version: i32) -> Result<(), String> {
+        if self.info.name() != info.name() {
+            return Err(format!(
+                "save is for \"{}\", not \"{}\"",
+                self.info.name(),
+                info.name()
+            ));
+        }
+        if self.api_version != api_version {
+            return Err(format!(
+                "save is from API version {}, the game uses version {}",
+                self.api_version, api_version
+            ));
+        }
+        Ok(())
+    }
+
+    /// Restores the save onto a running game, after checking it is the same game and API version.
+    /// The game is left as it was if anything goes wrong.
+    ///
+    /// # Arguments
+    /// * `bundle` - The game to restore onto
+    /// * `api_version` - The API version the game was built against
+    pub fn restore(&self, bundle: &mut RunBundle, api_version: i32) -> Result<(), String> {
+        self.check(&bundle.info, api_version)?;
+        if bundle.game.load_memory(&self.memory) {
+            Ok(())
+        } else {
+            Err("the game's memory couldn't be replaced".to_string())
+        }
+    }
+}
+
+/// Drives a game through steps, drawing and audio in process, without a window, audio device or
+/// WebAssembly. Useful for testing game logic:
+///
+/// ```
+/// use romy_core::input::InputDeviceType;
+/// use romy_core::output::{Color, Image, Sound};
+/// use romy_core::runtime::TestHarness;
+/// use romy_core::*;
+///
+/// // Draws a line that grows by a pixel every step:
+/// struct Line {
+///     length: i32,
+/// }
+///
+/// impl Game for Line {
+///     fn step(&mut self, _arguments: &StepArguments) {
+///         self.length += 1;
+///     }
+///     fn draw(&self, _arguments: &DrawArguments) -> Image {
+///         Image::new(self.length, 1, Color::new(1.0, 1.0, 1.0, 1.0))
+///     }
+///     fn render_audio(&self, arguments: &RenderAudioArguments) -> Sound {
+///         let samples = vec![0.0; arguments.sample_count() as usize];
+///         Sound::from_data(RenderAudioArguments::SAMPLE_RATE, &samples)
+///     }
+/// }
+///
+/// let info = Info::new("line", 60, 1, InputDeviceType::Nes);
+/// let samples = info.audio_sample_count() as usize;
+/// let mut harness = TestHarness::from_game(Box::new(Line { length: 0 }), info);
+/// harness.step_many(3);
+/// assert_eq!(harness.steps(), 3);
+/// assert_eq!(harness.draw(320, 240).width(), 3);
+/// assert_eq!(harness.render_audio().samples().len(), samples);
+/// ```
+pub struct TestHarness {
+    bundle: RunBundle,
+    inputs: VecDeque<InputArguments>,
+    steps: u64,
+    mixer: Mixer,
+    clock: ManualClock,
+    scheduler: StepScheduler,
+}
+
+impl TestHarness {
+    /// Create a harness around a bundle, nothing is run until step() is called
+    ///
+    /// # Arguments
+    /// * `bundle` - The game to drive
+    pub fn new(bundle: RunBundle) -> Self {
+        let scheduler = StepScheduler::new(bundle.info.step_interval(), 8, CatchUpPolicy::DropTime);
+        Self {
+            bundle,
+            inputs: VecDeque::new(),
+            steps: 0,
+            mixer: Mixer::new(),
+            clock: ManualClock::new(),
+            scheduler,
+        }
+    }
+
+    /// Create a harness around a plain Game
+    ///
+    /// # Arguments
+    /// * `game` - The game to drive
+    /// * `info` - The info the game would be started with
+    pub fn from_game(game: Box<dyn Game>, info: Info) -> Self {
+        Self::new(RunBundle::new(Box::new(GameMutMap::new(game)), info))
+    }
+
+    /// Queues up the input for a future step, each step takes one input from the queue in order,
+    /// steps run with an empty queue get default input with nothing pressed
+    ///
+    /// # Arguments
+    /// * `input` - The input to queue
+    pub fn push_input(&mut self, input: InputArguments) {
+        self.inputs.push_back(input);
+    }
+
+    /// Steps the game once with the next queued input
+    pub fn step(&mut self) {
+        let input = self.inputs.pop_front().unwrap_or_default();
+        let arguments = StepArguments::new(input, self.steps, self.bundle.info.step_interval());
+        self.bundle.game.step(&arguments);
+        self.steps += 1;
+    }
+
+    /// Steps the game a number of times, see step()
+    ///
+    /// # Arguments
+    /// * `count` - The number of steps to run
+    pub fn step_many(&mut self, count: u64) {
+        for _ in 0..count {
+            self.step();
+        }
+    }
+
+    /// Moves the harness clock forward and runs the steps a runtime would in a frame after that
+    /// much time, including the catch up limits, see set_catch_up(). Returns the number of steps
+    /// run.
+    ///
+    /// # Arguments
+    /// * `by` - How much time passes
+    pub fn advance_time(&mut self, by: Duration) -> u64 {
+        self.clock.advance(by);
+        let steps = self.scheduler.update(self.clock.now());
+        let count = steps.end - steps.start;
+        self.step_many(count);
+        count
+    }
+
+    /// Changes how advance_time() catches up when more steps are due than fit in a frame, by
+    /// default at most 8 steps are run and the rest of the time is dropped. Resets the clock.
+    ///
+    /// # Arguments
+    /// * `max_steps_per_frame` - The most steps to run in one call to advance_time()
+    /// * `policy` - What to do with the time that can't be run
+    pub fn set_catch_up(&mut self, max_steps_per_frame: u32, policy: CatchUpPolicy) {
+        let step_interval = self.bundle.info.step_interval();
+        self.scheduler = StepScheduler::new(step_interval, max_steps_per_frame, policy);
+        self.clock = ManualClock::new();
+    }
+
+    /// The scheduler used by advance_time(), for checking how many steps were dropped
+    pub fn scheduler(&self) -> &StepScheduler {
+        &self.scheduler
+    }
+
+    /// Draws the game and composites its layers the way a runtime would
+    ///
+    /// # Arguments
+    /// * `width` - The width of the area the game is drawn into
+    /// * `height` - The height of the area the game is drawn into
+    pub fn draw(&mut self, width: i32, height: i32) -> Image {
+        let elapsed = self.steps as f64 * f64::from(self.bundle.info.step_interval()) / 1e9;
+        let arguments = DrawArguments::new(width, height, 0.0, 1.0, elapsed as f32);
+        composite(self.bundle.game.draw_layers(&arguments))
+    }
+
+    /// Renders the games audio and mixes its voices the way a runtime would
+    pub fn render_audio(&mut self) -> Sound {
+        let arguments = RenderAudioArguments::new(self.bundle.info.audio_sample_count());
+        self.mixer.mix(&self.bundle.game.render_voices(&arguments))
+    }
+
+    /// The number of steps run so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The info the game was started with
+    pub fn info(&self) -> &Info {
+        &self.bundle.info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 10ms steps, at most 4 in a frame:
+    fn scheduler(policy: CatchUpPolicy) -> StepScheduler {
+        StepScheduler::new(10_000_000, 4, policy)
+    }
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn steps_follow_the_elapsed_time() {
+        let mut scheduler = scheduler(CatchUpPolicy::DropTime);
+        assert_eq!(scheduler.update(millis(0)), 0..0);
+        assert_eq!(scheduler.update(millis(35)), 0..3);
+        assert_eq!(scheduler.update(millis(50)), 3..5);
+        assert_eq!(scheduler.dropped_steps(), 0);
+        assert_eq!(scheduler.steps(), 5);
+    }
+
+    #[test]
+    fn drop_time_skips_what_it_cant_run() {
+        let mut scheduler = scheduler(CatchUpPolicy::DropTime);
+        assert_eq!(scheduler.update(millis(100)), 0..4);
+        assert_eq!(scheduler.dropped_steps(), 6);
+
+        // The dropped time is gone for good, only new time is run:
+        assert_eq!(scheduler.update(millis(100)), 4..4);
+        assert_eq!(scheduler.dropped_steps(), 0);
+        assert_eq!(scheduler.update(millis(110)), 4..5);
+    }
+
+    #[test]
+    fn run_slow_catches_up_over_several_frames() {
+        let mut scheduler = scheduler(CatchUpPolicy::RunSlow);
+        assert_eq!(scheduler.update(millis(100)), 0..4);
+        assert_eq!(scheduler.dropped_steps(), 0);
+        assert_eq!(scheduler.update(millis(100)), 4..8);
+        assert_eq!(scheduler.update(millis(100)), 8..10);
+        assert_eq!(scheduler.update(millis(100)), 10..10);
+    }
+
+    #[test]
+    fn step_offset_skips_dropped_time() {
+        let mut scheduler = scheduler(CatchUpPolicy::DropTime);
+        assert_eq!(scheduler.update(millis(105)), 0..4);
+
+        // 60ms were dropped, leaving the game half way through its fifth step:
+        assert_eq!(scheduler.step_offset(millis(105)), 0.5);
+        assert_eq!(scheduler.update(millis(115)), 4..5);
+        assert_eq!(scheduler.step_offset(millis(115)), 0.5);
+    }
+
+    #[test]
+    fn pointer_maps_through_a_scaled_letterboxed_window() {
+        // A 640x480 window on a 2x display, with a 320x180 game letterboxed top and bottom:
+        let (window, drawable) = ((640, 480), (1280, 960));
+        let viewport = Rect::new(0, 120, 1280, 720);
+        let render = (320, 180);
+        let map = |x, y| pointer_to_render(x, y, window, drawable, viewport, render);
+
+        assert_eq!(map(320.0, 240.0), Some((160.0, 90.0)));
+        assert_eq!(map(0.0, 60.0), Some((0.0, 0.0)));
+        assert_eq!(map(639.0, 419.0), Some((319.5, 179.5)));
+
+        // The bars above and below the game:
+        assert_eq!(map(320.0, 59.0), None);
+        assert_eq!(map(320.0, 420.0), None);
+
+        // Mapping back lands in the middle of the game pixel, in drawable pixels:
+        assert_eq!(render_pixel_center(160.0, 90.0, viewport, render), (642.0, 482.0));
+    }
+
+    #[test]
+    fn storage_refuses_keys_instead_of_changing_them() {
+        let mut storage = Storage::memory();
+        assert!(storage.save("high-scores_2", &[1]).is_ok());
+        assert_eq!(storage.load("high-scores_2"), Some(vec![1]));
+
+        // These would all have been saved as "a_b" if keys were sanitized:
+        assert!(storage.save("a_b", &[2]).is_ok());
+        assert!(storage.save("a.b", &[3]).is_err());
+        assert!(storage.save("a b", &[4]).is_err());
+        assert_eq!(storage.load("a_b"), Some(vec![2]));
+        assert_eq!(storage.load("a.b"), None);
+
+        let long = "a".repeat(Storage::MAX_KEY_LENGTH);
+        assert!(storage.save(&long, &[5]).is_ok());
+        assert!(storage.save(&format!("{}b", long), &[6]).is_err());
+        assert!(storage.save("", &[7]).is_err());
+        assert!(storage.save("../escape", &[8]).is_err());
+    }
+
+    #[test]
+    fn layers_off_the_edge_dont_wrap() {
+        let clear = Color::new(0.0, 0.0, 0.0, 0.0);
+        let red = Color::new(1.0, 0.0, 0.0, 1.0);
+        let base = Layer::new(Image::new(4, 2, clear), 0, 0, BlendMode::Opaque);
+
+        // Half of each layer hangs off the right and left edges of the first row:
+        let right = Layer::new(Image::new(2, 1, red), 3, 0, BlendMode::Opaque);
+        let left = Layer::new(Image::new(2, 1, red), -1, 0, BlendMode::Opaque);
+        let frame = composite(vec![base, right, left]);
+
+        let (clear, red) = (clear.as_rgba(), red.as_rgba());
+        assert_eq!(frame.pixels(), &[red, clear, clear, red, clear, clear, clear, clear][..]);
+    }
+}
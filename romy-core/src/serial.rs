@@ -38,6 +38,18 @@ pub fn decode_with_size<'a, T: serde::Deserialize<'a>>(data: &'a [u8]) -> T {
     decode(&data[8..])
 }
 
+/// Decodes an object from a series of bytes that has had a size tacked on the front as a u64,
+/// returning None instead of panicking if the data is malformed, for data that can't be trusted
+/// 
+/// # Arguments
+/// * `data` - the data to decode 
+pub fn try_decode_with_size<'a, T: serde::Deserialize<'a>>(data: &'a [u8]) -> Option<T> {
+    if data.len() < 8 {
+        return None;
+    }
+    bincode::deserialize(&data[8..]).ok()
+}
+
 /// Decodes an object from a series of bytes given as a pointer that has had a size tacked on the
 /// front as a u64
 /// 
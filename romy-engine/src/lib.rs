@@ -1,15 +1,23 @@
 use romy_core::output::*;
 use image::GenericImageView;
 
-/// Decode a .png, returning a Image
+mod sprite;
+pub use sprite::{Animation, SpriteOptions, SpriteSheet};
+
+/// Decode a .png, returning a Image, panics if the data isn't a valid image
 pub fn decode_png(data: &[u8]) -> Image {
-    let image = image::load_from_memory(data).unwrap();
+    try_decode_png(data).unwrap()
+}
+
+/// Decode a .png, returning a Image or why it couldn't be decoded
+pub fn try_decode_png(data: &[u8]) -> Result<Image, String> {
+    let image = image::load_from_memory(data).map_err(|error| error.to_string())?;
     let rah = image.to_rgba().into_raw();
-    Image::from_data(
+    Ok(Image::from_data(
         image.dimensions().0 as i32,
         image.dimensions().1 as i32,
         &rah,
-    )
+    ))
 }
 
 // Decode a .ogg file, retuning a sound for each channel
@@ -0,0 +1,365 @@
+use romy_core::output::*;
+
+/// Extra ways of drawing a frame from a SpriteSheet, see SpriteSheet::draw_frame_with()
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpriteOptions {
+    /// Mirror the frame left to right
+    pub flip_horizontal: bool,
+    /// Mirror the frame top to bottom
+    pub flip_vertical: bool,
+    /// Color each pixel is multiplied by, white leaves the frame unchanged
+    pub tint: Color,
+}
+
+impl Default for SpriteOptions {
+    fn default() -> Self {
+        Self {
+            flip_horizontal: false,
+            flip_vertical: false,
+            tint: Color::new(1.0, 1.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// An atlas image cut up into a grid of equally sized frames, numbered row by row from the top
+/// left
+pub struct SpriteSheet {
+    atlas: Image,
+    frame_width: i32,
+    frame_height: i32,
+}
+
+impl SpriteSheet {
+    /// Create a sprite sheet, any space at the right or bottom of the atlas that doesn't fit a
+    /// whole frame is ignored
+    /// # Arguments
+    /// * `atlas` - image holding every frame
+    /// * `frame_width` - the number of horizontal pixels in each frame
+    /// * `frame_height` - the number of vertical pixels in each frame
+    pub fn new(atlas: Image, frame_width: i32, frame_height: i32) -> Self {
+        Self {
+            atlas,
+            frame_width: frame_width.max(1),
+            frame_height: frame_height.max(1),
+        }
+    }
+
+    /// Gets the image holding every frame
+    pub fn atlas(&self) -> &Image {
+        &self.atlas
+    }
+
+    /// Gets the number of horizontal pixels in each frame
+    pub fn frame_width(&self) -> i32 {
+        self.frame_width
+    }
+
+    /// Gets the number of vertical pixels in each frame
+    pub fn frame_height(&self) -> i32 {
+        self.frame_height
+    }
+
+    /// Gets the number of frames in the sheet
+    pub fn frame_count(&self) -> i32 {
+        self.columns() * (self.atlas.height() / self.frame_height)
+    }
+
+    /// Gets the area of the atlas a frame is taken from, None if there is no such frame
+    /// # Arguments
+    /// * `index` - the frame number
+    pub fn frame_rect(&self, index: i32) -> Option<Rect> {
+        if index < 0 || index >= self.frame_count() {
+            return None;
+        }
+
+        Some(Rect::new(
+            index % self.columns() * self.frame_width,
+            index / self.columns() * self.frame_height,
+            self.frame_width,
+            self.frame_height,
+        ))
+    }
+
+    /// Draws a frame into an image, alpha blended like BlendMode::Normal and respecting the
+    /// images clip rectangle and sRGB blending setting. Frames that don't exist draw nothing.
+    /// # Arguments
+    /// * `target` - image to draw into
+    /// * `index` - the frame number
+    /// * `x` - horizontal coordinate of the left edge of the frame in the target
+    /// * `y` - vertical coordinate of the top edge of the frame in the target
+    pub fn draw_frame(&self, target: &mut Image, index: i32, x: i32, y: i32) {
+        self.draw_frame_with(target, index, x, y, SpriteOptions::default());
+    }
+
+    /// Draws a frame into an image like draw_frame(), flipped and tinted
+    /// # Arguments
+    /// * `target` - image to draw into
+    /// * `index` - the frame number
+    /// * `x` - horizontal coordinate of the left edge of the frame in the target
+    /// * `y` - vertical coordinate of the top edge of the frame in the target
+    /// * `options` - how to flip and tint the frame
+    pub fn draw_frame_with(
+        &self,
+        target: &mut Image,
+        index: i32,
+        x: i32,
+        y: i32,
+        options: SpriteOptions,
+    ) {
+        let source = match self.frame_rect(index) {
+            Some(source) => source,
+            None => return,
+        };
+
+        let tinted = options.tint != SpriteOptions::default().tint;
+        let clip = target
+            .clip()
+            .unwrap_or_else(|| Rect::new(0, 0, target.width(), target.height()));
+        let target_width = target.width();
+        let target_height = target.height();
+        let atlas_width = self.atlas.width();
+        let atlas = self.atlas.pixels();
+        let srgb_blending = target.srgb_blending();
+        let output = target.pixels_mut();
+
+        for row in 0..self.frame_height {
+            let output_y = y + row;
+            if output_y < 0 || output_y >= target_height {
+                continue;
+            }
+
+            let source_y = if options.flip_vertical {
+                source.y() + self.frame_height - 1 - row
+            } else {
+                source.y() + row
+            };
+
+            for column in 0..self.frame_width {
+                let output_x = x + column;
+                if output_x < 0 || output_x >= target_width || !clip.contains(output_x, output_y) {
+                    continue;
+                }
+
+                let source_x = if options.flip_horizontal {
+                    source.x() + self.frame_width - 1 - column
+                } else {
+                    source.x() + column
+                };
+
+                let mut pixel = atlas[(source_y * atlas_width + source_x) as usize];
+                if tinted {
+                    pixel = tint(pixel, options.tint);
+                }
+
+                let o = (output_y * target_width + output_x) as usize;
+                output[o] = if srgb_blending {
+                    BlendMode::Normal.blend_srgb(pixel, output[o])
+                } else {
+                    BlendMode::Normal.blend(pixel, output[o])
+                };
+            }
+        }
+    }
+
+    fn columns(&self) -> i32 {
+        self.atlas.width() / self.frame_width
+    }
+}
+
+/// Multiplies each channel of a packed pixel by a color
+fn tint(pixel: u32, tint: Color) -> u32 {
+    let color = Color::from_rgba(pixel);
+    Color::new(
+        color.red() * tint.red(),
+        color.green() * tint.green(),
+        color.blue() * tint.blue(),
+        color.alpha() * tint.alpha(),
+    )
+    .as_rgba()
+}
+
+/// Works out which frame of an animation to show as a game steps, for use with SpriteSheet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    first_frame: i32,
+    frame_count: i32,
+    steps_per_frame: u32,
+    looping: bool,
+    steps: u64,
+}
+
+impl Animation {
+    /// Create an animation that starts on its first frame and loops
+    /// # Arguments
+    /// * `first_frame` - the sprite sheet frame number the animation starts at
+    /// * `frame_count` - the number of frames in the animation, they follow on from first_frame
+    /// * `steps_per_frame` - how many steps each frame is shown for
+    pub fn new(first_frame: i32, frame_count: i32, steps_per_frame: u32) -> Self {
+        Self {
+            first_frame,
+            frame_count: frame_count.max(1),
+            steps_per_frame: steps_per_frame.max(1),
+            looping: true,
+            steps: 0,
+        }
+    }
+
+    /// Stop on the last frame instead of going back to the first, for one shot animations like
+    /// explosions
+    pub fn once(mut self) -> Self {
+        self.looping = false;
+        self
+    }
+
+    /// Moves the animation on, call this from Game::step()
+    /// # Arguments
+    /// * `steps` - the number of steps that have passed, usually 1
+    pub fn advance(&mut self, steps: u64) {
+        self.steps = self.steps.saturating_add(steps);
+    }
+
+    /// Goes back to the first frame
+    pub fn reset(&mut self) {
+        self.steps = 0;
+    }
+
+    /// Gets the sprite sheet frame number to draw now
+    pub fn frame(&self) -> i32 {
+        let frame = self.steps / u64::from(self.steps_per_frame);
+        let count = self.frame_count as u64;
+        let offset = if self.looping {
+            frame % count
+        } else {
+            frame.min(count - 1)
+        };
+
+        self.first_frame + offset as i32
+    }
+
+    /// Has a one shot animation reached its last frame, looping animations never finish
+    pub fn finished(&self) -> bool {
+        !self.looping && self.steps / u64::from(self.steps_per_frame) >= self.frame_count as u64 - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 5x3 atlas cut into 2x2 frames, the extra column and row don't fit a frame. Every pixel
+    /// is a different opaque color so tests can tell where a pixel came from.
+    fn numbered_sheet() -> SpriteSheet {
+        let mut atlas = Image::new(5, 3, Color::new(0.0, 0.0, 0.0, 1.0));
+        atlas.for_each_pixel(|x, y| Color::new(x as f32 / 4.0, y as f32 / 2.0, 0.5, 1.0));
+        SpriteSheet::new(atlas, 2, 2)
+    }
+
+    fn atlas_pixel(sheet: &SpriteSheet, x: i32, y: i32) -> u32 {
+        sheet.atlas().pixels()[(y * sheet.atlas().width() + x) as usize]
+    }
+
+    fn target_pixel(target: &Image, x: i32, y: i32) -> u32 {
+        target.pixels()[(y * target.width() + x) as usize]
+    }
+
+    #[test]
+    fn frames_are_numbered_row_by_row() {
+        let sheet = numbered_sheet();
+        assert_eq!(sheet.frame_count(), 2);
+        assert_eq!(sheet.frame_rect(0), Some(Rect::new(0, 0, 2, 2)));
+        assert_eq!(sheet.frame_rect(1), Some(Rect::new(2, 0, 2, 2)));
+        assert_eq!(sheet.frame_rect(2), None);
+        assert_eq!(sheet.frame_rect(-1), None);
+
+        let sheet = SpriteSheet::new(Image::new(4, 6, Color::new(0.0, 0.0, 0.0, 1.0)), 2, 3);
+        assert_eq!(sheet.frame_count(), 4);
+        assert_eq!(sheet.frame_rect(3), Some(Rect::new(2, 3, 2, 3)));
+    }
+
+    #[test]
+    fn frames_draw_flipped() {
+        let sheet = numbered_sheet();
+        let black = Color::new(0.0, 0.0, 0.0, 1.0);
+
+        let mut target = Image::new(4, 4, black);
+        sheet.draw_frame(&mut target, 1, 1, 1);
+        assert_eq!(target_pixel(&target, 1, 1), atlas_pixel(&sheet, 2, 0));
+        assert_eq!(target_pixel(&target, 2, 2), atlas_pixel(&sheet, 3, 1));
+        assert_eq!(target_pixel(&target, 0, 0), black.as_rgba());
+
+        let mut target = Image::new(4, 4, black);
+        let options = SpriteOptions {
+            flip_horizontal: true,
+            ..SpriteOptions::default()
+        };
+        sheet.draw_frame_with(&mut target, 1, 1, 1, options);
+        assert_eq!(target_pixel(&target, 1, 1), atlas_pixel(&sheet, 3, 0));
+        assert_eq!(target_pixel(&target, 2, 2), atlas_pixel(&sheet, 2, 1));
+
+        let mut target = Image::new(4, 4, black);
+        let options = SpriteOptions {
+            flip_vertical: true,
+            ..SpriteOptions::default()
+        };
+        sheet.draw_frame_with(&mut target, 1, 1, 1, options);
+        assert_eq!(target_pixel(&target, 1, 1), atlas_pixel(&sheet, 2, 1));
+        assert_eq!(target_pixel(&target, 2, 2), atlas_pixel(&sheet, 3, 0));
+    }
+
+    #[test]
+    fn frames_draw_tinted_and_clipped() {
+        let white = Color::new(1.0, 1.0, 1.0, 1.0);
+        let black = Color::new(0.0, 0.0, 0.0, 1.0);
+        let sheet = SpriteSheet::new(Image::new(2, 2, white), 2, 2);
+
+        let mut target = Image::new(3, 3, black);
+        target.set_clip(Some(Rect::new(0, 0, 2, 3)));
+        let options = SpriteOptions {
+            tint: Color::new(1.0, 0.0, 0.0, 1.0),
+            ..SpriteOptions::default()
+        };
+        sheet.draw_frame_with(&mut target, 0, 1, 1, options);
+        assert_eq!(target_pixel(&target, 1, 1), Color::new(1.0, 0.0, 0.0, 1.0).as_rgba());
+        assert_eq!(target_pixel(&target, 2, 1), black.as_rgba());
+        assert_eq!(target_pixel(&target, 0, 0), black.as_rgba());
+
+        // Missing frames draw nothing:
+        sheet.draw_frame(&mut target, 1, 0, 0);
+        assert_eq!(target_pixel(&target, 0, 0), black.as_rgba());
+    }
+
+    #[test]
+    fn looping_animations_wrap_around() {
+        let mut animation = Animation::new(4, 3, 2);
+        let mut frames = Vec::new();
+        for _ in 0..8 {
+            frames.push(animation.frame());
+            animation.advance(1);
+        }
+        assert_eq!(frames, vec![4, 4, 5, 5, 6, 6, 4, 4]);
+        assert!(!animation.finished());
+
+        animation.reset();
+        assert_eq!(animation.frame(), 4);
+    }
+
+    #[test]
+    fn one_shot_animations_stop_on_their_last_frame() {
+        let mut animation = Animation::new(4, 3, 2).once();
+        animation.advance(3);
+        assert_eq!(animation.frame(), 5);
+        assert!(!animation.finished());
+
+        animation.advance(1);
+        assert_eq!(animation.frame(), 6);
+        assert!(animation.finished());
+
+        animation.advance(100);
+        assert_eq!(animation.frame(), 6);
+        assert!(animation.finished());
+
+        animation.reset();
+        assert_eq!(animation.frame(), 4);
+        assert!(!animation.finished());
+    }
+}
@@ -1,22 +1,25 @@
 use romy_core::input::*;
+use romy_core::output::{Image, Rumble};
 use romy_core::runtime::*;
 use romy_core::*;
 
 use sdl2::audio::{AudioCallback, AudioSpecDesired};
 use sdl2::controller::Axis;
-use sdl2::controller::Button;
+use sdl2::controller::Button as SdlButton;
 use sdl2::controller::GameController;
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 use sdl2::keyboard::Scancode;
 use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
 use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 struct AudioQueue {
     samples: std::sync::Arc<std::sync::RwLock<std::collections::VecDeque<f32>>>,
+    volume: Arc<RwLock<f32>>,
 }
 
 impl AudioCallback for AudioQueue {
@@ -32,8 +35,33 @@ impl AudioCallback for AudioQueue {
             return;
         }
 
-        let slice: Vec<_> = lock.drain(..out.len()).collect();
-        out.copy_from_slice(&slice);
+        let volume = *self.volume.read().unwrap();
+        let length = out.len();
+        for (sample, queued) in out.iter_mut().zip(lock.drain(..length)) {
+            *sample = queued * volume;
+        }
+    }
+}
+
+/// A handle for checking on the audio queue of a running game from outside the runtime, clone one
+/// into RunOptions::audio_monitor before running and keep the other. It can be read from any
+/// thread.
+#[derive(Clone, Default)]
+pub struct AudioMonitor {
+    samples: Arc<RwLock<VecDeque<f32>>>,
+    device_buffer: Arc<AtomicUsize>,
+}
+
+impl AudioMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets how full the audio queue is right now. The queue is only locked long enough to read
+    /// its length, so this doesn't hold up the audio device.
+    pub fn status(&self) -> AudioQueueStatus {
+        let queued = self.samples.read().unwrap().len();
+        AudioQueueStatus::new(queued, self.device_buffer.load(Ordering::Relaxed))
     }
 }
 
@@ -45,6 +73,15 @@ impl ControllerMapper {
     fn new(sdl_controller: GameController) -> Self {
         Self { sdl_controller }
     }
+    fn rumble(&mut self, rumble: &Rumble) {
+        let low_frequency = (rumble.low_frequency() * f32::from(u16::MAX)) as u16;
+        let high_frequency = (rumble.high_frequency() * f32::from(u16::MAX)) as u16;
+
+        // Not all controllers support rumble, so errors are fine to ignore here:
+        let _ = self
+            .sdl_controller
+            .set_rumble(low_frequency, high_frequency, rumble.duration());
+    }
     fn map_axis(value: i16) -> f32 {
         if value > 0 {
             f32::from(value) / 32767.0
@@ -53,33 +90,142 @@ impl ControllerMapper {
             f32::from(value) / 32768.0
         }
     }
-    fn to_standard_controller(&self) -> Controller {
-        Controller::new(ControllerInit {
-            a: self.sdl_controller.button(Button::A),
-            b: self.sdl_controller.button(Button::A),
-            x: self.sdl_controller.button(Button::X),
-            y: self.sdl_controller.button(Button::Y),
-            left: self.sdl_controller.button(Button::DPadLeft),
-            right: self.sdl_controller.button(Button::DPadRight),
-            up: self.sdl_controller.button(Button::DPadUp),
-            down: self.sdl_controller.button(Button::DPadDown),
-            start: self.sdl_controller.button(Button::Start),
-            select: self.sdl_controller.button(Button::Back),
-            guide: self.sdl_controller.button(Button::Guide),
-            left_shoulder: self.sdl_controller.button(Button::LeftShoulder),
-            right_shoulder: self.sdl_controller.button(Button::RightShoulder),
-            left_stick: self.sdl_controller.button(Button::LeftStick),
-            right_stick: self.sdl_controller.button(Button::RightStick),
+    fn to_standard_controller(&self, remap: &ButtonRemap, inversion: &AxisInversion) -> Controller {
+        let button = |button| match physical_button(remap.physical(button)) {
+            Some(physical) => self.sdl_controller.button(physical),
+            None => false,
+        };
+
+        let mut init = ControllerInit {
+            a: button(Button::A),
+            b: button(Button::B),
+            x: button(Button::X),
+            y: button(Button::Y),
+            left: button(Button::Left),
+            right: button(Button::Right),
+            up: button(Button::Up),
+            down: button(Button::Down),
+            start: button(Button::Start),
+            select: button(Button::Select),
+            guide: button(Button::Guide),
+            left_shoulder: button(Button::LeftShoulder),
+            right_shoulder: button(Button::RightShoulder),
+            left_stick: button(Button::LeftStick),
+            right_stick: button(Button::RightStick),
             left_stick_x: Self::map_axis(self.sdl_controller.axis(Axis::LeftX)),
             left_stick_y: Self::map_axis(self.sdl_controller.axis(Axis::LeftY)),
             right_stick_x: Self::map_axis(self.sdl_controller.axis(Axis::RightX)),
             right_stick_y: Self::map_axis(self.sdl_controller.axis(Axis::RightY)),
-            left_trigger: Self::map_axis(self.sdl_controller.axis(Axis::TriggerLeft)),
-            right_trigger: Self::map_axis(self.sdl_controller.axis(Axis::TriggerRight)),
-        })
+            left_trigger: Self::map_axis(self.sdl_controller.axis(Axis::TriggerLeft)).max(0.0),
+            right_trigger: Self::map_axis(self.sdl_controller.axis(Axis::TriggerRight)).max(0.0),
+        };
+        inversion.apply(&mut init);
+        Controller::new(init)
+    }
+}
+
+/// Debugging changes to the step rate, made with alt and a key
+enum StepRateChange {
+    Slower,
+    Faster,
+    Reset,
+}
+
+/// Gets the step rate change for a key pressed with alt, if it is one
+fn step_rate_change(keycode: Keycode) -> Option<StepRateChange> {
+    match keycode {
+        Keycode::Comma => Some(StepRateChange::Slower),
+        Keycode::Period => Some(StepRateChange::Faster),
+        Keycode::Num0 => Some(StepRateChange::Reset),
+        _ => None,
+    }
+}
+
+/// Gets the time between steps in nanoseconds, from a step rate override if there is one
+///
+/// # Arguments
+/// * `info` - Info of the game
+/// * `step_rate` - Steps per second to use instead of the games own rate
+fn step_interval(info: &Info, step_rate: Option<u32>) -> u32 {
+    match step_rate {
+        Some(step_rate) => 1_000_000_000 / step_rate.max(1),
+        None => info.step_interval(),
     }
 }
 
+/// Draws a crosshair over the game pixel under the mouse. The mouse is mapped into the game with
+/// pointer_to_render() and back out again, so the crosshair only follows the mouse if the mapping
+/// is right.
+///
+/// # Arguments
+/// * `canvas` - The window canvas, after the frame has been copied to it
+/// * `mouse_x` - Horizontal mouse position in window coordinates
+/// * `mouse_y` - Vertical mouse position in window coordinates
+/// * `dest` - Where the frame was copied to on the canvas
+/// * `render_size` - Size of the image the game drew
+fn draw_pointer(
+    canvas: &mut sdl2::render::WindowCanvas,
+    mouse_x: i32,
+    mouse_y: i32,
+    dest: Rect,
+    render_size: (i32, i32),
+) -> Result<(), String> {
+    let viewport =
+        romy_core::output::Rect::new(dest.x(), dest.y(), dest.width() as i32, dest.height() as i32);
+    let window = canvas.window();
+    let position = pointer_to_render(
+        mouse_x as f32,
+        mouse_y as f32,
+        window.size(),
+        window.drawable_size(),
+        viewport,
+        render_size,
+    );
+    let (x, y) = match position {
+        Some(position) => position,
+        None => return Ok(()),
+    };
+
+    let (center_x, center_y) = render_pixel_center(x, y, viewport, render_size);
+    let (center_x, center_y) = (center_x as i32, center_y as i32);
+    let pixel_size = dest.width() as f32 / render_size.0.max(1) as f32;
+    let arm = (pixel_size * 4.0).max(8.0) as i32;
+
+    canvas.set_draw_color(Color::RGB(255, 0, 255));
+    canvas.draw_line((center_x - arm, center_y), (center_x + arm, center_y))?;
+    canvas.draw_line((center_x, center_y - arm), (center_x, center_y + arm))
+}
+
+/// Converts a physical button number, as used in ButtonRemap, to an SDL game controller button
+fn physical_button(physical: u32) -> Option<SdlButton> {
+    match physical {
+        0 => Some(SdlButton::A),
+        1 => Some(SdlButton::B),
+        2 => Some(SdlButton::X),
+        3 => Some(SdlButton::Y),
+        4 => Some(SdlButton::Back),
+        5 => Some(SdlButton::Guide),
+        6 => Some(SdlButton::Start),
+        7 => Some(SdlButton::LeftStick),
+        8 => Some(SdlButton::RightStick),
+        9 => Some(SdlButton::LeftShoulder),
+        10 => Some(SdlButton::RightShoulder),
+        11 => Some(SdlButton::DPadUp),
+        12 => Some(SdlButton::DPadDown),
+        13 => Some(SdlButton::DPadLeft),
+        14 => Some(SdlButton::DPadRight),
+        _ => None,
+    }
+}
+
+/// Gets the button table matching SDL's own game controller layout. Physical buttons are numbered
+/// like SDL_GameControllerButton: 0 a, 1 b, 2 x, 3 y, 4 back, 5 guide, 6 start, 7 left stick,
+/// 8 right stick, 9 left shoulder, 10 right shoulder, 11 dpad up, 12 dpad down, 13 dpad left and
+/// 14 dpad right.
+pub fn default_button_remap() -> ButtonRemap {
+    ButtonRemap::new([0, 1, 2, 3, 11, 12, 13, 14, 6, 4, 5, 9, 10, 7, 8])
+}
+
 fn convert_scan_code(scan_code: Scancode) -> Option<KeyCode> {
     match scan_code {
         Scancode::Num1 => Some(KeyCode::_1),
@@ -203,6 +349,210 @@ fn convert_key(
     None
 }
 
+/// Actions the runtime can take when the gamepad shortcut is held
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShortcutAction {
+    /// Do nothing, the shortcut is disabled
+    Nothing,
+    /// Close the runtime
+    Quit,
+    /// Pause or resume the game
+    Pause,
+}
+
+/// Options for changing how the SDL runtime looks and behaves
+pub struct RunOptions {
+    /// Color used to fill the space around the game when the window doesn't match its aspect
+    /// ratio
+    pub letterbox_color: romy_core::output::Color,
+    /// Image stretched over the whole window behind the game, shows in the letterbox margins
+    pub border: Option<Image>,
+    /// Mixer used to combine the voices a game renders, can be used to set per voice volumes
+    pub mixer: Mixer,
+    /// Compare each frame against the last one and only upload the area that changed, this saves
+    /// bandwidth for games that have mostly static screens
+    pub dirty_rects: bool,
+    /// Initial width of the window, in pixels
+    pub window_width: u32,
+    /// Initial height of the window, in pixels
+    pub window_height: u32,
+    /// Start in fullscreen mode, can still be toggled with alt+enter
+    pub fullscreen: bool,
+    /// Volume applied to everything the game plays, 0.0 - 1.0, can be changed with alt+minus and
+    /// alt+equals
+    pub master_volume: f32,
+    /// The most steps that will be run in a single frame when catching up after a stall, so a
+    /// slow game can't fall further and further behind
+    pub max_steps_per_frame: u32,
+    /// What to do with time that can't be caught up on within max_steps_per_frame
+    pub catch_up_policy: CatchUpPolicy,
+    /// How the time between frames is read, FramePacing::Smoothed evens out motion on systems
+    /// where vsync timing jitters
+    pub frame_pacing: FramePacing,
+    /// Print a warning when a frame takes longer than the games step interval
+    pub warn_slow_frames: bool,
+    /// Debugging aid that draws a crosshair over the game pixel under the mouse, for checking the
+    /// mouse is mapped into the game correctly with letterboxing and high DPI displays
+    pub show_pointer: bool,
+    /// Largest images a game is allowed to draw, frames with bigger images are not displayed
+    pub image_limits: ImageLimits,
+    /// What happens when the guide button, or start and select together, are held on a gamepad,
+    /// pauses by default
+    pub shortcut_action: ShortcutAction,
+    /// How long the gamepad shortcut needs to be held before it triggers, so a tap doesn't
+    /// trigger it by accident
+    pub shortcut_hold: Duration,
+    /// Number of samples the audio device asks for at a time, larger buffers are less likely to
+    /// underrun on slow machines, smaller ones lower the latency
+    pub audio_buffer_size: u16,
+    /// Split the keyboard between two players, one on WASD and one on the arrow keys, see
+    /// KeyBindings::left_hand() and KeyBindings::right_hand()
+    pub split_keyboard: bool,
+    /// Which keys press each button when the keyboard is used as a Nes controller. Not used with
+    /// split_keyboard, where each player has their own bindings.
+    pub key_bindings: KeyBindings,
+    /// Shares the audio queue with the caller, see AudioMonitor
+    pub audio_monitor: AudioMonitor,
+    /// Name of the audio device to play through, as listed by audio_devices(), the default device
+    /// is used when this is None or no device has the name
+    pub audio_device: Option<String>,
+    /// Pause the game while the window doesn't have focus
+    pub pause_on_focus_loss: bool,
+    /// Width to height ratio the window is kept at when resized, for example (4, 3), the initial
+    /// height is adjusted to match. None lets the window be resized freely, with the game
+    /// letterboxed inside it
+    pub aspect_ratio: Option<(u32, u32)>,
+    /// Fixed width and height reported to the game in DrawArguments, whatever the size of the
+    /// window, for example (256, 240). The runtime scales what the game draws up to fit the
+    /// window. None reports the size of the window.
+    pub internal_resolution: Option<(u32, u32)>,
+    /// Where time comes from for stepping, pausing and timing frames, real time by default
+    pub clock: Box<dyn Clock>,
+    /// Load games dropped onto the window in place of the running one, turn this off to stop the
+    /// game being swapped out, for kiosks for example
+    pub drop_to_load: bool,
+    /// Which physical gamepad button is read for each Controller button, see
+    /// default_button_remap() for how physical buttons are numbered
+    pub button_remap: ButtonRemap,
+    /// Which stick axes are negated before the game sees them
+    pub axis_inversion: AxisInversion,
+    /// Most memory a game can use before it is warned about or stopped, checked every frame
+    pub memory_limit: MemoryLimit,
+    /// Path the starting game was loaded from, alt+r reloads the game from here. Games dropped
+    /// onto the window replace it.
+    pub game_path: Option<String>,
+    /// Watch the games file and reload it whenever it changes, for rebuilding a game while it
+    /// runs. Reloading keeps the games state when the new build's memory layout looks the same.
+    pub hot_reload: bool,
+    /// Steps per second to run games at instead of the rate they ask for, for slowing a game down
+    /// to inspect it. Can be changed while running with alt+comma and alt+period, alt+0 goes back
+    /// to the games own rate. Alt+p pauses the game and alt+n runs a single step while paused.
+    pub step_rate: Option<u32>,
+    /// Called with every frame exactly as it is presented, after scaling and letterboxing, so
+    /// integration tests can check the whole draw path. Return false to stop the runtime. Only
+    /// available with the frame-capture feature, reading frames back is slow.
+    ///
+    /// ```ignore
+    /// let mut options = RunOptions::default();
+    /// options.on_frame = Some(Box::new(|frame: &Image| {
+    ///     assert_eq!(frame.pixels()[0], Color::new(0.0, 0.0, 0.0, 1.0).as_rgba());
+    ///     false
+    /// }));
+    /// run_with_options(Some(bundle), |_| None, options)?;
+    /// ```
+    #[cfg(feature = "frame-capture")]
+    pub on_frame: Option<Box<Fn(&Image) -> bool>>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            letterbox_color: romy_core::output::Color::new(0.0, 0.0, 0.0, 1.0),
+            border: None,
+            mixer: Mixer::new(),
+            dirty_rects: false,
+            window_width: 960,
+            window_height: 720,
+            fullscreen: false,
+            master_volume: 1.0,
+            max_steps_per_frame: 8,
+            catch_up_policy: CatchUpPolicy::DropTime,
+            frame_pacing: FramePacing::Exact,
+            warn_slow_frames: false,
+            show_pointer: false,
+            image_limits: ImageLimits::default(),
+            shortcut_action: ShortcutAction::Pause,
+            shortcut_hold: Duration::from_secs(1),
+            audio_buffer_size: 1024,
+            split_keyboard: false,
+            key_bindings: KeyBindings::default(),
+            audio_monitor: AudioMonitor::new(),
+            audio_device: None,
+            pause_on_focus_loss: false,
+            aspect_ratio: None,
+            internal_resolution: None,
+            clock: Box::new(SystemClock::new()),
+            drop_to_load: true,
+            button_remap: default_button_remap(),
+            axis_inversion: AxisInversion::default(),
+            memory_limit: MemoryLimit::default(),
+            game_path: None,
+            hot_reload: false,
+            step_rate: None,
+            #[cfg(feature = "frame-capture")]
+            on_frame: None,
+        }
+    }
+}
+
+/// Adjusts a window size to an aspect ratio, keeping whichever side changed the most from the
+/// previous size so dragging either edge works
+///
+/// # Arguments
+/// * `size` - The size the window was resized to
+/// * `previous` - The size of the window before it was resized
+/// * `aspect_ratio` - Width to height ratio to keep
+fn fit_aspect_ratio(
+    size: (u32, u32),
+    previous: (u32, u32),
+    aspect_ratio: (u32, u32),
+) -> (u32, u32) {
+    let (width, height) = (u64::from(size.0), u64::from(size.1));
+    let (ratio_width, ratio_height) = (u64::from(aspect_ratio.0), u64::from(aspect_ratio.1));
+    if ratio_width == 0 || ratio_height == 0 {
+        return size;
+    }
+
+    let width_change = (i64::from(size.0) - i64::from(previous.0)).abs();
+    let height_change = (i64::from(size.1) - i64::from(previous.1)).abs();
+    if width_change >= height_change {
+        let height = (width * ratio_height + ratio_width / 2) / ratio_width;
+        (size.0, height.clamp(1, u64::from(u32::MAX)) as u32)
+    } else {
+        let width = (height * ratio_width + ratio_height / 2) / ratio_height;
+        (width.clamp(1, u64::from(u32::MAX)) as u32, size.1)
+    }
+}
+
+/// Gets the names of the audio devices that can be played through, for RunOptions::audio_device
+pub fn audio_devices() -> Result<Vec<String>, String> {
+    let audio_subsystem = sdl2::init()?.audio()?;
+    let count = audio_subsystem.num_audio_playback_devices().unwrap_or(0);
+    (0..count)
+        .map(|index| audio_subsystem.audio_playback_device_name(index))
+        .collect()
+}
+
+fn convert_color(color: &romy_core::output::Color) -> Color {
+    let rgba = color.as_rgba();
+    Color::RGBA(
+        (rgba & 0xFF) as u8,
+        ((rgba >> 8) & 0xFF) as u8,
+        ((rgba >> 16) & 0xFF) as u8,
+        ((rgba >> 24) & 0xFF) as u8,
+    )
+}
+
 pub fn run_standalone(app: Box<Game>, info: Info) -> Result<(), String> {
     run(
         Some(RunBundle {
@@ -215,22 +565,91 @@ pub fn run_standalone(app: Box<Game>, info: Info) -> Result<(), String> {
 
 struct RomyGame {
     bundle: RunBundle,
-    start_time: Instant,
-    step: Duration,
-    steps: u128,
+    start_time: Duration,
+    // Game time added by stepping the game while it's paused, on top of the time since the start:
+    stepped_time: Duration,
+    scheduler: StepScheduler,
+    presence: PlayerPresence,
+    memory_warned: bool,
+    // Memory size straight after init, builds with a different size have a different layout:
+    initial_memory: Option<usize>,
 }
 
 impl RomyGame {
-    fn new(bundle: RunBundle) -> Self {
-        let step = Duration::from_nanos(u64::from(bundle.info.step_interval()));
+    fn new(mut bundle: RunBundle, options: &RunOptions) -> Self {
+        let scheduler = StepScheduler::new(
+            step_interval(&bundle.info, options.step_rate),
+            options.max_steps_per_frame,
+            options.catch_up_policy,
+        )
+        .with_frame_pacing(options.frame_pacing);
+        let initial_memory = bundle.game.memory_usage();
 
         Self {
             bundle,
-            start_time: Instant::now(),
-            step,
-            steps: 0,
+            start_time: options.clock.now(),
+            stepped_time: Duration::from_secs(0),
+            scheduler,
+            presence: PlayerPresence::new(),
+            memory_warned: false,
+            initial_memory,
         }
     }
+
+    /// Gets how long the game has been running for, not counting time it was paused
+    ///
+    /// # Arguments
+    /// * `now` - The current time, or the time the game was paused at
+    fn game_time(&self, now: Duration) -> Duration {
+        now.checked_sub(self.start_time).unwrap_or_default() + self.stepped_time
+    }
+
+    /// Swaps in a rebuilt version of the game, moving the memory of the running game across when
+    /// the new build's memory is laid out the same way: it starts out the same size, its static
+    /// data is the same and it steps at the same rate. Otherwise the new build starts fresh.
+    ///
+    /// # Arguments
+    /// * `bundle` - The newly loaded game
+    /// * `options` - Options for the runtime
+    fn reload(&mut self, mut bundle: RunBundle, options: &RunOptions) {
+        let mismatch = if bundle.game.memory_usage() != self.initial_memory {
+            Some("its memory size changed")
+        } else if bundle.game.memory_layout() != self.bundle.game.memory_layout() {
+            Some("its static data changed")
+        } else if bundle.info.step_interval() != self.bundle.info.step_interval() {
+            Some("its step rate changed")
+        } else {
+            None
+        };
+        let mismatch = mismatch.or_else(|| {
+            let restored = match self.bundle.game.save_memory() {
+                Some(memory) => bundle.game.load_memory(&memory),
+                None => false,
+            };
+            if restored {
+                None
+            } else {
+                Some("its memory couldn't be moved across")
+            }
+        });
+
+        let name = bundle.info.name().to_string();
+        match mismatch {
+            None => {
+                eprintln!("Romy: reloaded {}, keeping its state", name);
+                self.bundle = bundle;
+            }
+            Some(reason) => {
+                eprintln!("Romy: reloaded {}, {} so it restarted", name, reason);
+                *self = RomyGame::new(bundle, options);
+            }
+        }
+    }
+}
+
+/// Gets when a file was last changed, None if it can't be read
+fn modified_time(path: &str) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
 }
 
 /// Runs a RunBundle using SDL2
@@ -241,6 +660,25 @@ impl RomyGame {
 /// * `load_new` - Callback to get a new bundle from a file path, this will be called if a file is
 /// dragged onto the game window.
 pub fn run<F>(bundle: Option<RunBundle>, load_new: F) -> Result<(), String>
+where
+    F: Fn(&str) -> Option<RunBundle>,
+{
+    run_with_options(bundle, load_new, RunOptions::default())
+}
+
+/// Runs a RunBundle using SDL2, with options to change how the runtime behaves
+///
+/// # Arguments
+/// * `bundle` - Optional bundle to run, if none is supplied the sdl window will open and wait
+///   for a game do be dropped onto it.
+/// * `load_new` - Callback to get a new bundle from a file path, this will be called if a file is
+///   dragged onto the game window.
+/// * `options` - Options for the runtime
+pub fn run_with_options<F>(
+    bundle: Option<RunBundle>,
+    load_new: F,
+    options: RunOptions,
+) -> Result<(), String>
 where
     F: Fn(&str) -> Option<RunBundle>,
 {
@@ -249,7 +687,7 @@ where
     let mut game = match bundle {
         Some(bundle) => {
             title = format!("Romy: {}", bundle.info.name());
-            Some(RomyGame::new(bundle))
+            Some(RomyGame::new(bundle, &options))
         }
         None => None,
     };
@@ -257,14 +695,23 @@ where
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
 
-    let window = video_subsystem
-        .window(&title, 960, 720)
+    let mut window_size = (options.window_width, options.window_height);
+    if let Some(aspect_ratio) = options.aspect_ratio {
+        window_size = fit_aspect_ratio(window_size, (0, window_size.1), aspect_ratio);
+    }
+
+    let mut window = video_subsystem
+        .window(&title, window_size.0, window_size.1)
         .resizable()
         .allow_highdpi()
         .position_centered()
         .build()
         .map_err(|e| e.to_string())?;
 
+    if options.fullscreen {
+        window.set_fullscreen(sdl2::video::FullscreenType::Desktop)?;
+    }
+
     let mut canvas = window
         .into_canvas()
         .present_vsync()
@@ -276,28 +723,83 @@ where
         .create_texture_streaming(PixelFormatEnum::ABGR8888, 320, 240)
         .map_err(|e| e.to_string())?;
 
+    let border = match &options.border {
+        Some(border) => {
+            let mut border_texture = creator
+                .create_texture_streaming(
+                    PixelFormatEnum::ABGR8888,
+                    border.width() as u32,
+                    border.height() as u32,
+                )
+                .map_err(|e| e.to_string())?;
+            border_texture
+                .update(None, border.pixels8(), border.width() as usize * 4)
+                .map_err(|e| e.to_string())?;
+            Some(border_texture)
+        }
+        None => None,
+    };
+    let letterbox_color = convert_color(&options.letterbox_color);
+
     let audio_subsystem = sdl_context.audio().unwrap();
     let desired_spec = AudioSpecDesired {
-        freq: Some(44100),
+        freq: Some(RenderAudioArguments::SAMPLE_RATE),
         channels: Some(1),
-        samples: Some(1024),
+        samples: Some(options.audio_buffer_size.max(1)),
     };
+    let audio_buffer_size = usize::from(options.audio_buffer_size.max(1));
     let game_controller_subsystem = sdl_context.game_controller()?;
 
-    let samples = Arc::new(RwLock::new(VecDeque::new()));
+    let samples = options.audio_monitor.samples.clone();
+    samples.write().unwrap().clear();
+    options
+        .audio_monitor
+        .device_buffer
+        .store(audio_buffer_size, Ordering::Relaxed);
     let samples_clone = samples.clone();
+    let volume = Arc::new(RwLock::new(options.master_volume.clamp(0.0, 1.0)));
+    let volume_clone = volume.clone();
 
+    let audio_device = match &options.audio_device {
+        Some(name) => {
+            let count = audio_subsystem.num_audio_playback_devices().unwrap_or(0);
+            let found = (0..count).any(|index| {
+                audio_subsystem.audio_playback_device_name(index).ok().as_ref() == Some(name)
+            });
+            if !found {
+                eprintln!("Romy: audio device \"{}\" not found, using the default", name);
+            }
+            Some(name.as_str()).filter(|_| found)
+        }
+        None => None,
+    };
     let device = audio_subsystem
-        .open_playback(None, &desired_spec, |_| AudioQueue {
+        .open_playback(audio_device, &desired_spec, |_| AudioQueue {
             samples: samples_clone,
+            volume: volume_clone,
         })
         .unwrap();
     device.resume();
 
     let mut keyboard = Keyboard::default();
+    keyboard.set_bindings(options.key_bindings.clone());
     let mut controllers = Vec::new();
+    let mut devices_changed = false;
+    let mut last_render: Option<Image> = None;
+    let mut last_dest: Option<Rect> = None;
+    let mut shortcut_start: Option<Duration> = None;
+    let mut shortcut_fired = false;
+    let mut paused_at: Option<Duration> = None;
+    let mut focused = true;
+    let mut focus_paused = false;
+    let mut game_path = options.game_path.clone();
+    let mut game_modified = game_path.as_ref().and_then(|path| modified_time(path));
+    let mut last_watch = options.clock.now();
+    let mut options = options;
 
     'mainloop: loop {
+        let mut reload = false;
+
         for event in sdl_context.event_pump()?.poll_iter() {
             match event {
                 Event::KeyDown {
@@ -326,6 +828,48 @@ where
                                 .window_mut()
                                 .set_fullscreen(new_fullscreen_mode)
                                 .unwrap();
+                        } else if keycode == sdl2::keyboard::Keycode::Minus {
+                            let mut volume = volume.write().unwrap();
+                            *volume = (*volume - 0.1).max(0.0);
+                        } else if keycode == sdl2::keyboard::Keycode::Equals {
+                            let mut volume = volume.write().unwrap();
+                            *volume = (*volume + 0.1).min(1.0);
+                        } else if keycode == sdl2::keyboard::Keycode::R {
+                            reload = true;
+                        } else if keycode == sdl2::keyboard::Keycode::P {
+                            let now = options.clock.now();
+                            match paused_at.take() {
+                                Some(paused_at) => {
+                                    if let Some(game) = &mut game {
+                                        game.start_time += now - paused_at;
+                                    }
+                                }
+                                None => paused_at = Some(now),
+                            }
+                            focus_paused = false;
+                        } else if keycode == sdl2::keyboard::Keycode::N {
+                            // Adding a step of game time lets exactly one more step run, and
+                            // resuming later carries on from there without a jump:
+                            if let (Some(_), Some(game)) = (paused_at, &mut game) {
+                                game.stepped_time += game.scheduler.step();
+                            }
+                        } else if let (Some(rate_change), Some(game)) =
+                            (step_rate_change(keycode), &mut game)
+                        {
+                            let current = 1_000_000_000 / game.scheduler.step().as_nanos() as u32;
+                            options.step_rate = match rate_change {
+                                StepRateChange::Slower => Some((current / 2).max(1)),
+                                StepRateChange::Faster => Some(current.saturating_mul(2)),
+                                StepRateChange::Reset => None,
+                            };
+                            let elapsed =
+                                game.game_time(paused_at.unwrap_or_else(|| options.clock.now()));
+                            let interval = step_interval(&game.bundle.info, options.step_rate);
+                            game.scheduler.set_step_interval(interval, elapsed);
+                            eprintln!(
+                                "Romy: running at {} steps per second",
+                                1_000_000_000 / interval.max(1)
+                            );
                         }
                     } else {
                         let key = convert_key(scancode, keycode);
@@ -343,24 +887,71 @@ where
                         keyboard.key_up(scancode);
                     }
                 }
-                Event::DropFile { filename, .. } => {
+                Event::DropFile { filename, .. } if options.drop_to_load => {
                     if let Some(bundle) = load_new(&filename) {
-                        canvas
-                            .window_mut()
-                            .set_title(format!("Romy: {}", bundle.info.name()).as_str())
-                            .unwrap();
+                        title = format!("Romy: {}", bundle.info.name());
+                        canvas.window_mut().set_title(&title).unwrap();
 
-                        game = Some(RomyGame::new(bundle));
+                        game = Some(RomyGame::new(bundle, &options));
+                        last_render = None;
+                        last_dest = None;
+                        paused_at = None;
+                        game_modified = modified_time(&filename);
+                        game_path = Some(filename);
                     }
                 }
                 Event::ControllerDeviceAdded { which, .. } => {
                     if let Ok(c) = game_controller_subsystem.open(which) {
                         controllers.push(ControllerMapper::new(c));
+                        devices_changed = true;
                     }
                 }
                 Event::ControllerDeviceRemoved { which, .. } => {
                     controllers
                         .retain(|controller| controller.sdl_controller.instance_id() != which);
+                    devices_changed = true;
+                }
+                Event::Window {
+                    win_event: WindowEvent::Resized(width, height),
+                    ..
+                } => {
+                    let size = (width.max(1) as u32, height.max(1) as u32);
+                    let windowed =
+                        canvas.window().fullscreen_state() == sdl2::video::FullscreenType::Off;
+                    if let (Some(aspect_ratio), true) = (options.aspect_ratio, windowed) {
+                        let fitted = fit_aspect_ratio(size, window_size, aspect_ratio);
+                        if fitted != size {
+                            canvas
+                                .window_mut()
+                                .set_size(fitted.0, fitted.1)
+                                .map_err(|e| e.to_string())?;
+                        }
+                        window_size = fitted;
+                    } else if windowed {
+                        window_size = size;
+                    }
+                }
+                Event::Window {
+                    win_event: WindowEvent::FocusLost,
+                    ..
+                } => {
+                    focused = false;
+                    if options.pause_on_focus_loss && paused_at.is_none() {
+                        paused_at = Some(options.clock.now());
+                        focus_paused = true;
+                    }
+                }
+                Event::Window {
+                    win_event: WindowEvent::FocusGained,
+                    ..
+                } => {
+                    focused = true;
+                    if focus_paused {
+                        focus_paused = false;
+                        if let (Some(game), Some(paused_at)) = (&mut game, paused_at.take()) {
+                            game.start_time += options.clock.now() - paused_at;
+                        }
+                    }
                 }
                 Event::Quit { .. } => break 'mainloop,
                 _ => {}
@@ -368,51 +959,189 @@ where
         }
 
         let mut input = InputCollection::new();
-        input.add_input(InputDevice::Keyboard(keyboard.clone()));
+        if options.split_keyboard {
+            for half in keyboard.split(&[KeyBindings::left_hand(), KeyBindings::right_hand()]) {
+                input.add_input(InputDevice::Keyboard(half));
+            }
+        } else {
+            input.add_input(InputDevice::Keyboard(keyboard.clone()));
+        }
 
+        let mut shortcut_held = false;
         for controller in &controllers {
-            input.add_input(InputDevice::Controller(controller.to_standard_controller()));
+            let device_id = controller.sdl_controller.instance_id() as u32;
+            let controller =
+                controller.to_standard_controller(&options.button_remap, &options.axis_inversion);
+            shortcut_held |= controller.guide() || (controller.start() && controller.select());
+            input.add_input_with_id(InputDevice::Controller(controller), device_id);
+        }
+
+        if shortcut_held {
+            let now = options.clock.now();
+            let started = *shortcut_start.get_or_insert(now);
+            if !shortcut_fired && now - started >= options.shortcut_hold {
+                shortcut_fired = true;
+                match options.shortcut_action {
+                    ShortcutAction::Nothing => {}
+                    ShortcutAction::Quit => break 'mainloop,
+                    ShortcutAction::Pause => match paused_at.take() {
+                        Some(paused_at) => {
+                            // Move the start forward so the paused time doesn't need catching up:
+                            if let Some(game) = &mut game {
+                                game.start_time += now - paused_at;
+                            }
+                        }
+                        None => paused_at = Some(now),
+                    },
+                }
+            }
+        } else {
+            shortcut_start = None;
+            shortcut_fired = false;
         }
 
-        canvas.set_draw_color(Color::RGBA(0, 0, 0, 255));
+        canvas.set_draw_color(letterbox_color);
         canvas.clear();
 
+        if let Some(border) = &border {
+            canvas.copy(border, None, None)?;
+        }
+
+        if options.hot_reload && options.clock.now() - last_watch >= Duration::from_secs(1) {
+            last_watch = options.clock.now();
+            let modified = game_path.as_ref().and_then(|path| modified_time(path));
+            if modified.is_some() && modified != game_modified {
+                game_modified = modified;
+                reload = true;
+            }
+        }
+
+        if let (true, Some(path)) = (reload, &game_path) {
+            if let Some(bundle) = load_new(path) {
+                match &mut game {
+                    Some(current) => current.reload(bundle, &options),
+                    None => game = Some(RomyGame::new(bundle, &options)),
+                }
+                last_render = None;
+            }
+        }
+
+        if let Some(current) = &mut game {
+            let used = current.bundle.game.memory_usage();
+            if let Some(message) = used.and_then(|used| options.memory_limit.check(used)) {
+                match options.memory_limit.action {
+                    MemoryLimitAction::Warn if !current.memory_warned => {
+                        eprintln!("Romy: {}", message);
+                        current.memory_warned = true;
+                    }
+                    MemoryLimitAction::Warn => {}
+                    MemoryLimitAction::Halt => {
+                        eprintln!("Romy: stopping the game, {}", message);
+                        game = None;
+                    }
+                }
+            }
+        }
+
         if let Some(game) = &mut game {
+            let frame_start = options.clock.now();
+            let time_span = game.game_time(paused_at.unwrap_or(frame_start));
+
             let app = &mut game.bundle.game;
             let info = &game.bundle.info;
+            let steps = game.scheduler.update(time_span);
+            if options.warn_slow_frames && game.scheduler.dropped_steps() > 0 {
+                eprintln!(
+                    "Romy: running slowly, skipped {} steps",
+                    game.scheduler.dropped_steps()
+                );
+            }
+            let frame_steps = steps.end - steps.start;
+            for step_index in steps {
+                input.set_devices_changed(devices_changed);
+                devices_changed = false;
 
-            let time_span = Instant::now().duration_since(game.start_time);
-            let expected_steps = time_span.as_micros() / game.step.as_micros();
-            while game.steps < expected_steps {
-                app.step(&StepArguments::new(input.get_input_arguments(&info)));
-
-                let audio = app.render_audio(&RenderAudioArguments {});
+                app.step(
+                    &StepArguments::new(
+                        game.presence.update(input.get_input_arguments(&info)),
+                        step_index,
+                        info.step_interval(),
+                    )
+                    .with_focus(focused),
+                );
+                input.clear_key_events();
 
-                {
-                    let mut samples = samples.write().unwrap();
-                    let new_samples = audio.samples();
-                    for sample in new_samples {
-                        samples.push_back(*sample);
+                if let Some(rumble) = app.rumble() {
+                    for controller in &mut controllers {
+                        controller.rumble(&rumble);
                     }
+                }
 
-                    //TODO: Don't let the audio get more than 10 steps out, need better solution:
-                    if samples.len() > new_samples.len()*10 {
-                        samples.clear();
+                if step_index % u64::from(info.audio_steps()) == 0 {
+                    let queued = samples.read().unwrap().len();
+                    let arguments = render_audio_arguments(info, queued, audio_buffer_size);
+                    let audio = options.mixer.mix(&app.render_voices(&arguments));
+
+                    {
+                        let mut samples = samples.write().unwrap();
+                        let new_samples = audio.samples();
+                        for sample in new_samples {
+                            samples.push_back(*sample);
+                        }
+
+                        // Games that ignore the sample count can still drift, don't let their
+                        // audio get more than 10 chunks out:
+                        if samples.len() > new_samples.len()*10 {
+                            samples.clear();
+                        }
                     }
                 }
 
-                game.steps += 1;
             }
 
-            let step_offset = (time_span.as_micros() % game.step.as_micros()) as f32
-                / game.step.as_micros() as f32;
+            if frame_steps > 0 {
+                // The events have been seen by a step now:
+                keyboard.clear_events();
+
+                let new_title = match app.title() {
+                    Some(game_title) => format!("Romy: {}", game_title),
+                    None => format!("Romy: {}", info.name()),
+                };
+                if new_title != title {
+                    canvas.window_mut().set_title(&new_title).unwrap();
+                    title = new_title;
+                }
+            }
+
+            let step_offset = game.scheduler.step_offset(time_span);
 
             let (width, height) = canvas.output_size().unwrap();
-            let render = app.draw(&DrawArguments::new(
-                width as i32,
-                height as i32,
+            let (draw_width, draw_height) = options.internal_resolution.unwrap_or((width, height));
+            let layers = app.draw_layers(&DrawArguments::new(
+                draw_width as i32,
+                draw_height as i32,
                 step_offset,
+                1.0,
+                (time_span.as_micros() as f64 / 1_000_000.0) as f32,
             ));
+            if let Err(error) = options.image_limits.check_layers(&layers) {
+                eprintln!("Romy: not displaying frame, {}", error);
+                if !present(&mut canvas, &options)? {
+                    break 'mainloop;
+                }
+                continue;
+            }
+            if is_skipped_frame(&layers) {
+                // The texture still holds the last frame the game drew:
+                if let Some(dest) = last_dest {
+                    canvas.copy(&texture, None, dest)?;
+                }
+                if !present(&mut canvas, &options)? {
+                    break 'mainloop;
+                }
+                continue;
+            }
+            let render = composite(layers);
 
             let t = texture.query();
             if t.width != render.width() as u32 || t.height != render.height() as u32 {
@@ -423,16 +1152,38 @@ where
                         render.height() as u32,
                     )
                     .map_err(|e| e.to_string())?;
+                last_render = None;
             }
 
-            texture.with_lock(None, |buffer: &mut [u8], _: usize| {
-                let source = render.pixels8();
-                buffer.clone_from_slice(&source[..buffer.len()])
-            })?;
+            let changed = match &last_render {
+                Some(last_render) => render.changed_bounds(last_render),
+                None => Some(romy_core::output::Rect::new(0, 0, render.width(), render.height())),
+            };
+
+            if let Some(changed) = changed {
+                let lock_rect = Rect::new(
+                    changed.x(),
+                    changed.y(),
+                    changed.width() as u32,
+                    changed.height() as u32,
+                );
+                texture.with_lock(lock_rect, |buffer: &mut [u8], pitch: usize| {
+                    let source = render.pixels8();
+                    let source_pitch = render.width() as usize * 4;
+                    let row_length = changed.width() as usize * 4;
+                    for row in 0..changed.height() as usize {
+                        let from =
+                            (changed.y() as usize + row) * source_pitch + changed.x() as usize * 4;
+                        buffer[row * pitch..row * pitch + row_length]
+                            .copy_from_slice(&source[from..from + row_length]);
+                    }
+                })?;
+            }
 
-            let scale =
-                (width as f32 / render.width() as f32).min(height as f32 / render.height() as f32);
-            let new_width = (render.width() as f32 * scale) as u32;
+            // Games with non-square pixels are stretched horizontally to match:
+            let display_width = render.width() as f32 * info.pixel_aspect_ratio();
+            let scale = (width as f32 / display_width).min(height as f32 / render.height() as f32);
+            let new_width = (display_width * scale) as u32;
             let new_height = (render.height() as f32 * scale) as u32;
             let dest = Rect::new(
                 ((width - new_width) / 2) as i32,
@@ -442,10 +1193,53 @@ where
             );
 
             canvas.copy(&texture, None, dest)?;
+            last_dest = Some(dest);
+
+            if options.show_pointer {
+                let mouse = sdl_context.event_pump()?.mouse_state();
+                let render_size = (render.width(), render.height());
+                draw_pointer(&mut canvas, mouse.x(), mouse.y(), dest, render_size)?;
+            }
+
+            let frame_time = options.clock.now() - frame_start;
+            if options.warn_slow_frames && frame_time > game.scheduler.step() {
+                eprintln!(
+                    "Romy: slow frame, took {}us for {} steps, the step interval is {}us",
+                    frame_time.as_micros(),
+                    frame_steps,
+                    game.scheduler.step().as_micros()
+                );
+            }
+
+            if options.dirty_rects {
+                last_render = Some(render);
+            }
         }
 
-        canvas.present();
+        if !present(&mut canvas, &options)? {
+            break 'mainloop;
+        }
     }
 
     Ok(())
 }
+
+/// Shows the finished frame on the window. With the frame-capture feature the frame is read back
+/// first and handed to RunOptions::on_frame, returns false if it asked for the runtime to stop.
+#[cfg_attr(not(feature = "frame-capture"), allow(unused_variables))]
+fn present(canvas: &mut sdl2::render::WindowCanvas, options: &RunOptions) -> Result<bool, String> {
+    #[cfg(feature = "frame-capture")]
+    {
+        if let Some(on_frame) = &options.on_frame {
+            let (width, height) = canvas.output_size()?;
+            let pixels = canvas.read_pixels(None, PixelFormatEnum::ABGR8888)?;
+            let frame = Image::from_data(width as i32, height as i32, &pixels);
+            if !on_frame(&frame) {
+                return Ok(false);
+            }
+        }
+    }
+
+    canvas.present();
+    Ok(true)
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "frame-capture")]
+
+use romy_core::input::InputDeviceType;
+use romy_core::output::{Color, Image, Sound};
+use romy_core::runtime::{GameMutMap, RunBundle};
+use romy_core::{DrawArguments, Game, Info, RenderAudioArguments, StepArguments};
+use romy_sdl::{run_with_options, RunOptions};
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Draws nothing for its first few frames, like a game that is still loading, then a red pixel
+struct LateGame {
+    draws: Cell<u32>,
+}
+
+impl Game for LateGame {
+    fn step(&mut self, _arguments: &StepArguments) {}
+
+    fn draw(&self, _arguments: &DrawArguments) -> Image {
+        self.draws.set(self.draws.get() + 1);
+        if self.draws.get() <= 3 {
+            Image::new(0, 0, Color::new(0.0, 0.0, 0.0, 0.0))
+        } else {
+            Image::new(1, 1, Color::new(1.0, 0.0, 0.0, 1.0))
+        }
+    }
+
+    fn render_audio(&self, arguments: &RenderAudioArguments) -> Sound {
+        Sound::with_buffer_size(RenderAudioArguments::SAMPLE_RATE, arguments.sample_count())
+    }
+}
+
+#[test]
+fn empty_images_are_skipped_until_the_game_draws() {
+    std::env::set_var("SDL_VIDEODRIVER", "dummy");
+    std::env::set_var("SDL_AUDIODRIVER", "dummy");
+
+    let game = LateGame {
+        draws: Cell::new(0),
+    };
+    let info = Info::new("late", 60, 1, InputDeviceType::Nes);
+    let bundle = RunBundle::new(Box::new(GameMutMap::new(Box::new(game))), info);
+
+    let black = Color::new(0.0, 0.0, 0.0, 1.0).as_rgba();
+    let red = Color::new(1.0, 0.0, 0.0, 1.0).as_rgba();
+    let frames = Rc::new(Cell::new(0));
+    let frames_seen = frames.clone();
+    let mut options = RunOptions::default();
+    options.on_frame = Some(Box::new(move |frame: &Image| {
+        frames_seen.set(frames_seen.get() + 1);
+        let center = (frame.height() / 2 * frame.width() + frame.width() / 2) as usize;
+        if frames_seen.get() <= 3 {
+            assert_eq!(frame.pixels()[center], black);
+            true
+        } else {
+            assert_eq!(frame.pixels()[center], red);
+            false
+        }
+    }));
+
+    run_with_options(Some(bundle), |_| None, options).unwrap();
+    assert_eq!(frames.get(), 4);
+}
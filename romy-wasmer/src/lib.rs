@@ -1,30 +1,91 @@
 use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::c_void;
 use std::fs::File;
+use std::hash::Hasher;
 use std::io::prelude::*;
+use std::path::Path;
 
 use romy_core::output::*;
 use romy_core::runtime::*;
 use romy_core::*;
 use wasmer_runtime::memory::MemoryView;
-use wasmer_runtime::{imports, instantiate, Func, Instance, Memory};
+use wasmer_runtime::units::{Bytes, Pages};
+use wasmer_runtime::{func, imports, instantiate, Ctx, Func, Instance, Memory};
 
 struct RomyWasmer {
     instance: Instance,
     info: Info,
     memory: Vec<u8>,
+    // Hash of the memory as instantiated, before the game has run, which covers its static data:
+    layout: u64,
+    // Referenced from the instance context by the storage imports:
+    _storage: Box<Storage>,
 }
 
 impl RomyWasmer {
-    fn new(mut instance: Instance) -> Self {
-        let info: Info = Self::call_on_instance(&mut instance, "init", Option::<&i32>::None);
+    fn new(mut instance: Instance, options: LoadOptions) -> Result<Self, LoadError> {
+        let LoadOptions { arguments, storage } = options;
+        let layout = Self::hash_memory(instance.context().memory(0));
+
+        // Games built against another version encode their data differently:
+        let api_version = instance
+            .func::<(), i32>("romy_api_version")
+            .ok()
+            .and_then(|func| func.call().ok());
+        if api_version != Some(API_VERSION) {
+            return Err(LoadError::ApiVersion(api_version));
+        }
+
+        // The storage imports find the storage through the context data, it's boxed so the pointer
+        // stays valid when this struct moves:
+        let mut storage = Box::new(storage);
+        instance.context_mut().data = &mut *storage as *mut Storage as *mut c_void;
+
+        // Optional export, games don't have to provide it:
+        if instance.func::<u32, ()>("set_launch_arguments").is_ok() {
+            let location = Self::set(&mut instance, &arguments);
+            let func: Func<u32, ()> = instance.func("set_launch_arguments").unwrap();
+            let result = func.call(location as u32);
+            result.unwrap_or_else(|error| Self::crashed(&instance, error));
+            Self::free(&mut instance, location);
+        }
+
+        let init: Func<(), u32> = instance.func("init").unwrap();
+        let pointer = init
+            .call()
+            .unwrap_or_else(|error| Self::crashed(&instance, error));
+        if pointer == 0 {
+            let reason = Self::optional_string(&instance, "init_error");
+            return Err(LoadError::Init(
+                reason.unwrap_or_else(|| "no reason given".to_string()),
+            ));
+        }
+        let info = read_from_memory::<Info>(instance.context().memory(0), pointer);
+        Self::free(&mut instance, pointer as usize);
+        let info =
+            info.ok_or_else(|| LoadError::InvalidInfo("it couldn't be decoded".to_string()))?;
+
         let mut memory = Vec::new();
         Self::dump_memory(instance.context().memory(0), &mut memory);
-        Self {
+        Ok(Self {
             instance,
             info,
             memory,
-        }
+            layout,
+            _storage: storage,
+        })
     }
+
+    /// Calls an optional export that returns a pointer to a string owned by the game, None if the
+    /// game doesn't have the export, it returned null or the call trapped
+    fn optional_string(instance: &Instance, name: &str) -> Option<String> {
+        // Optional exports, games don't have to provide them:
+        let func = instance.func::<(), u32>(name).ok()?;
+        let pointer = func.call().ok().filter(|&pointer| pointer != 0)?;
+        read_from_memory::<String>(instance.context().memory(0), pointer)
+    }
+
     fn dump_memory(_memory: &Memory, _to: &mut Vec<u8>) {
         // Fork of wasmer at https://github.com/catt-io/wasmer will allow memory save/load
         // looking for a way to do this without republishing the wasmer crates.
@@ -34,6 +95,24 @@ impl RomyWasmer {
     fn restore_memory(_memory: &Memory, _data: &[u8]) {
         // memory.set(data).unwrap();
     }
+
+    /// Hashes the contents of a memory, two builds of a game that hash the same before running
+    /// have their static data in the same places
+    fn hash_memory(memory: &Memory) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let view: MemoryView<u8> = memory.view();
+        for cell in view.iter() {
+            hasher.write_u8(cell.get());
+        }
+        hasher.finish()
+    }
+
+    /// Copies out the whole of the instances default memory, for comparing or for moving into a
+    /// rebuilt game with load_memory()
+    fn memory_snapshot(&self) -> Vec<u8> {
+        let view: MemoryView<u8> = self.instance.context().memory(0).view();
+        view.iter().map(std::cell::Cell::get).collect()
+    }
     fn get<'a, T: serde::Deserialize<'a>>(instance: &mut Instance, pointer: usize) -> T {
         let view: MemoryView<u8> = instance.context_mut().memory(0).view();
         let slice: Vec<_> = view[pointer..(pointer + 8)]
@@ -56,7 +135,9 @@ impl RomyWasmer {
 
         let alloc: Func<i32, u32> = instance.func("allocate").unwrap();
 
-        let location = alloc.call(params.len() as i32).unwrap() as usize;
+        let location = alloc
+            .call(params.len() as i32)
+            .unwrap_or_else(|error| Self::crashed(instance, error)) as usize;
         let view: MemoryView<u8> = instance.context_mut().memory(0).view();
         let slice = &view[location..(location + params.len())];
         for i in 0..params.len() {
@@ -68,7 +149,17 @@ impl RomyWasmer {
 
     fn free(instance: &mut Instance, pointer: usize) {
         let deallocate: Func<u32, ()> = instance.func("deallocate").unwrap();
-        deallocate.call(pointer as u32).unwrap()
+        let result = deallocate.call(pointer as u32);
+        result.unwrap_or_else(|error| Self::crashed(instance, error))
+    }
+
+    /// Stops the runtime after a call into the game has trapped, with the games panic message if
+    /// it left one
+    fn crashed(instance: &Instance, error: impl std::fmt::Debug) -> ! {
+        match Self::optional_string(instance, "panic_message") {
+            Some(message) => panic!("game crashed: {}", message),
+            None => panic!("game crashed: {:?}", error),
+        }
     }
 
     fn call_on_instance<'a, T: serde::Deserialize<'a>>(
@@ -80,13 +171,15 @@ impl RomyWasmer {
             Some(arg) => {
                 let location = Self::set(instance, arg);
                 let func: Func<u32, u32> = instance.func(id).unwrap();
-                let result = func.call(location as u32).unwrap() as usize;
+                let result = func.call(location as u32);
+                let result = result.unwrap_or_else(|error| Self::crashed(instance, error));
                 Self::free(instance, location);
-                result
+                result as usize
             }
             None => {
                 let func: Func<(), u32> = instance.func(id).unwrap();
-                func.call().unwrap() as usize
+                let result = func.call();
+                result.unwrap_or_else(|error| Self::crashed(instance, error)) as usize
             }
         };
 
@@ -108,12 +201,14 @@ impl RomyWasmer {
             Some(arg) => {
                 let location = Self::set(instance, arg);
                 let func: Func<u32, ()> = instance.func(id).unwrap();
-                func.call(location as u32).unwrap();
+                let result = func.call(location as u32);
+                result.unwrap_or_else(|error| Self::crashed(instance, error));
                 Self::free(instance, location);
             }
             None => {
                 let func: Func<(), ()> = instance.func(id).unwrap();
-                func.call().unwrap();
+                let result = func.call();
+                result.unwrap_or_else(|error| Self::crashed(instance, error));
             }
         };
     }
@@ -133,21 +228,334 @@ impl GameMut for RomyWasmer {
     fn render_audio(&mut self, arguments: &RenderAudioArguments) -> Sound {
         self.call("render_audio", Some(arguments))
     }
+
+    fn draw_layers(&mut self, arguments: &DrawArguments) -> Vec<Layer> {
+        // Optional export, games don't have to provide it:
+        if self.instance.func::<u32, u32>("draw_layers").is_err() {
+            return vec![Layer::new(self.draw(arguments), 0, 0, BlendMode::Opaque)];
+        }
+        self.call("draw_layers", Some(arguments))
+    }
+
+    fn render_voices(&mut self, arguments: &RenderAudioArguments) -> Vec<Voice> {
+        // Optional export, games don't have to provide it:
+        if self.instance.func::<u32, u32>("render_voices").is_err() {
+            return vec![Voice::new("main", 1.0, self.render_audio(arguments))];
+        }
+        self.call("render_voices", Some(arguments))
+    }
+
+    fn rumble(&mut self) -> Option<Rumble> {
+        // Optional export, games don't have to provide it:
+        if self.instance.func::<(), u32>("rumble").is_err() {
+            return None;
+        }
+        self.call("rumble", Option::<&i32>::None)
+    }
+
+    fn title(&mut self) -> Option<String> {
+        // Optional export, games don't have to provide it:
+        if self.instance.func::<(), u32>("title").is_err() {
+            return None;
+        }
+        self.call("title", Option::<&i32>::None)
+    }
+
+    fn memory_usage(&mut self) -> Option<usize> {
+        let Bytes(bytes) = self.instance.context().memory(0).size().into();
+        Some(bytes)
+    }
+
+    fn save_memory(&mut self) -> Option<Vec<u8>> {
+        Some(self.memory_snapshot())
+    }
+
+    fn load_memory(&mut self, memory: &[u8]) -> bool {
+        const PAGE_SIZE: usize = 65536;
+
+        let instance_memory = self.instance.context().memory(0);
+        let Bytes(size) = instance_memory.size().into();
+        if memory.len() > size {
+            let pages = (memory.len() - size + PAGE_SIZE - 1) / PAGE_SIZE;
+            if instance_memory.grow(Pages(pages as u32)).is_err() {
+                return false;
+            }
+        }
+
+        let view: MemoryView<u8> = instance_memory.view();
+        for (cell, byte) in view.iter().zip(memory) {
+            cell.set(*byte);
+        }
+        true
+    }
+
+    fn memory_layout(&mut self) -> Option<u64> {
+        Some(self.layout)
+    }
 }
 
-/// Load up a file and return the Game and Info data as a RunBundle
-pub fn load(path: &str) -> Option<RunBundle> {
-    if let Ok(mut file) = File::open(path) {
-        let mut buffer = Vec::new();
-        if file.read_to_end(&mut buffer).is_ok() {
-            let import_object = imports! {};
-            if let Ok(instance) = instantiate(&buffer, &import_object) {
-                let wasm = RomyWasmer::new(instance);
-                let info = wasm.info.clone();
-                return Some(RunBundle::new(Box::new(wasm), info));
+/// Reads a value encoded with romy_core::serial::encode_with_size from a games memory, the game
+/// chooses the pointer so None is returned if it doesn't point at valid data
+fn read_from_memory<T: serde::de::DeserializeOwned>(memory: &Memory, pointer: u32) -> Option<T> {
+    let view: MemoryView<u8> = memory.view();
+    let start = pointer as usize;
+    let header: Vec<_> = view
+        .get(start..start.checked_add(8)?)?
+        .iter()
+        .map(std::cell::Cell::get)
+        .collect();
+    let size = (&header[..]).read_u64::<LittleEndian>().ok()? as usize;
+    let data: Vec<_> = view
+        .get(start..start.checked_add(8)?.checked_add(size)?)?
+        .iter()
+        .map(std::cell::Cell::get)
+        .collect();
+    serial::try_decode_with_size(&data)
+}
+
+/// Gets the storage for the game from the context data, None if the imports are called while the
+/// game is being instantiated, before RomyWasmer::new() has set it
+fn storage(ctx: &mut Ctx) -> Option<&mut Storage> {
+    if ctx.data.is_null() {
+        return None;
+    }
+    Some(unsafe { &mut *(ctx.data as *mut Storage) })
+}
+
+/// Import for games, gets the size of the data saved under a key, -1 if nothing is saved
+fn storage_size(ctx: &mut Ctx, key: u32) -> i32 {
+    let key: String = match read_from_memory(ctx.memory(0), key) {
+        Some(key) => key,
+        None => return -1,
+    };
+    match storage(ctx).and_then(|storage| storage.load(&key)) {
+        Some(data) => data.len() as i32,
+        None => -1,
+    }
+}
+
+/// Import for games, copies the data saved under a key into a buffer at least storage_size() long
+fn storage_read(ctx: &mut Ctx, key: u32, buffer: u32) {
+    let key: String = match read_from_memory(ctx.memory(0), key) {
+        Some(key) => key,
+        None => return,
+    };
+    if let Some(data) = storage(ctx).and_then(|storage| storage.load(&key)) {
+        let view: MemoryView<u8> = ctx.memory(0).view();
+        let start = buffer as usize;
+        if let Some(cells) = view.get(start..start.saturating_add(data.len())) {
+            for (cell, byte) in cells.iter().zip(&data) {
+                cell.set(*byte);
             }
         }
     }
+}
+
+/// Import for games, saves data under a key, returns 1 on success and 0 on failure
+fn storage_write(ctx: &mut Ctx, key: u32, data: u32) -> i32 {
+    let key: Option<String> = read_from_memory(ctx.memory(0), key);
+    let data: Option<Vec<u8>> = read_from_memory(ctx.memory(0), data);
+    if let (Some(key), Some(data), Some(storage)) = (key, data, storage(ctx)) {
+        match storage.save(&key, &data) {
+            Ok(()) => return 1,
+            Err(error) => eprintln!("Romy: couldn't save {}: {}", key, error),
+        }
+    }
+    0
+}
+
+/// Options for loading a game
+pub struct LoadOptions {
+    /// Launch arguments handed to the game before it initializes, games read these with
+    /// romy::launch_arguments()
+    pub arguments: Vec<u8>,
+    /// Where the game's saved data is kept
+    pub storage: Storage,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            arguments: Vec::new(),
+            storage: Storage::memory(),
+        }
+    }
+}
+
+/// Errors that can happen while loading a game
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file couldn't be read
+    Io(std::io::Error),
+    /// The data couldn't be compiled or instantiated as a WebAssembly module
+    Instantiate(String),
+    /// The game was built against another version of the interface between games and runtimes,
+    /// with the version it reported, see API_VERSION
+    ApiVersion(Option<i32>),
+    /// The game returned info from init() that can't be run, see Info::validate()
+    InvalidInfo(String),
+    /// The game failed to start, with the reason it gave
+    Init(String),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "couldn't read game: {}", error),
+            LoadError::Instantiate(error) => write!(f, "couldn't instantiate game: {}", error),
+            LoadError::ApiVersion(Some(version)) => write!(
+                f,
+                "game was built for API version {}, this runtime supports version {}",
+                version, API_VERSION
+            ),
+            LoadError::ApiVersion(None) => write!(f, "game doesn't export romy_api_version"),
+            LoadError::InvalidInfo(error) => write!(f, "game gave invalid info: {}", error),
+            LoadError::Init(reason) => write!(f, "game failed to start: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(error: std::io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+/// Load up a file and return the Game and Info data as a RunBundle
+pub fn load(path: &str) -> Result<RunBundle, LoadError> {
+    load_with_arguments(path, &[])
+}
+
+/// Load up a file and return the Game and Info data as a RunBundle, passing some launch arguments
+/// to the game
+///
+/// # Arguments
+/// * `path` - Path to the game file
+/// * `arguments` - Launch arguments handed to the game before it initializes, games read these
+/// with romy::launch_arguments()
+pub fn load_with_arguments(path: &str, arguments: &[u8]) -> Result<RunBundle, LoadError> {
+    let name = Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "game".to_string());
+    let options = LoadOptions {
+        arguments: arguments.to_vec(),
+        storage: Storage::for_game(&name),
+    };
+    load_with_options(path, options)
+}
+
+/// Load up a file and return the Game and Info data as a RunBundle
+///
+/// # Arguments
+/// * `path` - Path to the game file
+/// * `options` - Arguments and storage for the game
+pub fn load_with_options(path: &str, options: LoadOptions) -> Result<RunBundle, LoadError> {
+    let mut file = File::open(path)?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    load_from_bytes_with_options(&buffer, options)
+}
+
+/// Load a game from WebAssembly data already in memory and return the Game and Info data as a
+/// RunBundle, useful for games embedded in an executable with include_bytes!. Anything the game
+/// saves is kept in memory, use load_from_bytes_with_options() to keep it on disk.
+pub fn load_from_bytes(data: &[u8]) -> Result<RunBundle, LoadError> {
+    load_from_bytes_with_options(data, LoadOptions::default())
+}
+
+/// Load a game from WebAssembly data already in memory, passing some launch arguments to the
+/// game, see load_with_arguments()
+pub fn load_from_bytes_with_arguments(
+    data: &[u8],
+    arguments: &[u8],
+) -> Result<RunBundle, LoadError> {
+    let options = LoadOptions {
+        arguments: arguments.to_vec(),
+        ..LoadOptions::default()
+    };
+    load_from_bytes_with_options(data, options)
+}
+
+/// Load a game from WebAssembly data already in memory, see load_with_options()
+pub fn load_from_bytes_with_options(
+    data: &[u8],
+    options: LoadOptions,
+) -> Result<RunBundle, LoadError> {
+    let wasm = instantiate_game(data, options)?;
+    let info = wasm.info.clone();
+    Ok(RunBundle::new(Box::new(wasm), info))
+}
+
+fn instantiate_game(data: &[u8], options: LoadOptions) -> Result<RomyWasmer, LoadError> {
+    let import_object = imports! {
+        "env" => {
+            "storage_size" => func!(storage_size),
+            "storage_read" => func!(storage_read),
+            "storage_write" => func!(storage_write),
+        },
+    };
+    let instance = instantiate(data, &import_object)
+        .map_err(|error| LoadError::Instantiate(error.to_string()))?;
+    let wasm = RomyWasmer::new(instance, options)?;
+    wasm.info.validate().map_err(LoadError::InvalidInfo)?;
+    Ok(wasm)
+}
+
+/// Loads a game, steps it a number of times with nothing pressed and draws a single frame, for
+/// things like thumbnails in a game launcher. The game gets in memory storage, so it sees no
+/// saves and anything it saves is thrown away.
+///
+/// # Arguments
+/// * `path` - The game file to load
+/// * `steps` - How many steps to run before drawing, games often need a few to set up a scene
+/// * `width` - The width the game is asked to draw at
+/// * `height` - The height the game is asked to draw at
+pub fn render_thumbnail(
+    path: &str,
+    steps: u64,
+    width: i32,
+    height: i32,
+) -> Result<Image, LoadError> {
+    let bundle = load_with_options(path, LoadOptions::default())?;
+    let mut harness = TestHarness::new(bundle);
+    harness.step_many(steps);
+    Ok(harness.draw(width, height))
+}
+
+/// Checks that a game is deterministic, that is the same inputs always lead to the same game.
+///
+/// Two instances of the game are started from the same data, and so the same initial state, then
+/// both are stepped with a recorded stream of inputs. After every step the memory of the two
+/// instances and the images they draw are compared, returning false as soon as they differ.
+/// Games relying on anything outside of their inputs, like the time or uninitialized memory, will
+/// fail this check and won't replay correctly.
+///
+/// # Arguments
+/// * `data` - WebAssembly data of the game
+/// * `inputs` - The arguments for each step, in order
+pub fn verify_deterministic(data: &[u8], inputs: &[StepArguments]) -> Result<bool, LoadError> {
+    let mut first = instantiate_game(data, LoadOptions::default())?;
+    let mut second = instantiate_game(data, LoadOptions::default())?;
+
+    if first.memory_snapshot() != second.memory_snapshot() {
+        return Ok(false);
+    }
+
+    for input in inputs {
+        first.step(input);
+        second.step(input);
+        if first.memory_snapshot() != second.memory_snapshot() {
+            return Ok(false);
+        }
+
+        let arguments = DrawArguments::new(320, 240, 0.0, 1.0, input.elapsed_seconds());
+        if first.draw_layers(&arguments) != second.draw_layers(&arguments) {
+            return Ok(false);
+        }
+    }
 
-    None
+    Ok(true)
 }
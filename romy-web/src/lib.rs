@@ -4,7 +4,8 @@ use romy_core::input::*;
 use romy_core::output::*;
 use romy_core::runtime::*;
 use romy_core::*;
-use std::cell::RefCell;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
 use std::collections::VecDeque;
 use std::rc::Rc;
 use std::time::Duration;
@@ -12,8 +13,8 @@ use wasm_bindgen::prelude::*;
 use wasm_bindgen::Clamped;
 use wasm_bindgen::JsCast;
 use web_sys::{
-    AudioContext, AudioContextState, Blob, BlobPropertyBag, Event, Gamepad, GamepadButton, Request,
-    RequestInit, RequestMode, Response, Url, Window,
+    AudioContext, AudioContextState, Blob, BlobPropertyBag, Event, EventTarget, Gamepad,
+    GamepadButton, Request, RequestInit, RequestMode, Response, ScriptProcessorNode, Url, Window,
 };
 
 #[wasm_bindgen]
@@ -26,6 +27,15 @@ fn window() -> Window {
     web_sys::window().unwrap()
 }
 
+/// Clock that follows the pages performance timer
+struct PerformanceClock;
+
+impl Clock for PerformanceClock {
+    fn now(&self) -> Duration {
+        Duration::from_micros((window().performance().unwrap().now() * 1000.0) as u64)
+    }
+}
+
 struct ControllerMapper {
     gamepad: Gamepad,
 }
@@ -35,11 +45,28 @@ impl ControllerMapper {
         Self { gamepad }
     }
     fn get_button(&self, button: i32) -> bool {
+        // Remapped buttons can point past the end of the buttons the gamepad has:
         Reflect::get(self.gamepad.buttons().as_ref(), &button.into())
-            .unwrap()
-            .dyn_into::<GamepadButton>()
-            .unwrap()
-            .pressed()
+            .ok()
+            .and_then(|button| button.dyn_into::<GamepadButton>().ok())
+            .map_or(false, |button| button.pressed())
+    }
+    fn get_button_value(&self, button: i32) -> f32 {
+        // Gamepads without the triggers don't have buttons this far in:
+        let button = match Reflect::get(self.gamepad.buttons().as_ref(), &button.into())
+            .ok()
+            .and_then(|button| button.dyn_into::<GamepadButton>().ok())
+        {
+            Some(button) => button,
+            None => return 0.0,
+        };
+        // Browsers without pressure support report 0 for a pressed button:
+        let value = button.value() as f32;
+        if button.pressed() && value == 0.0 {
+            1.0
+        } else {
+            value.clamp(0.0, 1.0)
+        }
     }
     fn get_axes(&self, axes: i32) -> f32 {
         Reflect::get(self.gamepad.axes().as_ref(), &axes.into())
@@ -47,36 +74,73 @@ impl ControllerMapper {
             .as_f64()
             .unwrap() as f32
     }
-    fn build_standard_controller(&mut self) -> Controller {
+    fn rumble(&self, rumble: &Rumble) {
+        // Not all browsers support haptics, so look the actuator up dynamically:
+        let actuator = Reflect::get(self.gamepad.as_ref(), &"vibrationActuator".into());
+        if let Ok(actuator) = actuator {
+            if let Ok(play_effect) = Reflect::get(&actuator, &"playEffect".into()) {
+                if let Ok(play_effect) = play_effect.dyn_into::<Function>() {
+                    let parameters = Object::new();
+                    Reflect::set(
+                        parameters.as_ref(),
+                        &"duration".into(),
+                        &rumble.duration().into(),
+                    )
+                    .unwrap();
+                    Reflect::set(
+                        parameters.as_ref(),
+                        &"strongMagnitude".into(),
+                        &rumble.low_frequency().into(),
+                    )
+                    .unwrap();
+                    Reflect::set(
+                        parameters.as_ref(),
+                        &"weakMagnitude".into(),
+                        &rumble.high_frequency().into(),
+                    )
+                    .unwrap();
+                    let _ = play_effect.call2(&actuator, &"dual-rumble".into(), &parameters);
+                }
+            }
+        }
+    }
+    fn build_standard_controller(
+        &mut self,
+        remap: &ButtonRemap,
+        inversion: &AxisInversion,
+    ) -> Controller {
         let gamepads = window().navigator().get_gamepads().unwrap();
         self.gamepad = Reflect::get(gamepads.as_ref(), &self.gamepad.index().into())
             .unwrap()
             .dyn_into::<Gamepad>()
             .unwrap();
 
-        Controller::new(ControllerInit {
-            a: self.get_button(0),
-            b: self.get_button(1),
-            x: self.get_button(2),
-            y: self.get_button(3),
-            left: self.get_button(14),
-            right: self.get_button(15),
-            up: self.get_button(12),
-            down: self.get_button(13),
-            start: self.get_button(9),
-            select: self.get_button(8),
-            guide: self.get_button(16),
-            left_shoulder: self.get_button(4),
-            right_shoulder: self.get_button(5),
-            left_stick: self.get_button(10),
-            right_stick: self.get_button(11),
+        let button = |button| self.get_button(remap.physical(button) as i32);
+        let mut init = ControllerInit {
+            a: button(Button::A),
+            b: button(Button::B),
+            x: button(Button::X),
+            y: button(Button::Y),
+            left: button(Button::Left),
+            right: button(Button::Right),
+            up: button(Button::Up),
+            down: button(Button::Down),
+            start: button(Button::Start),
+            select: button(Button::Select),
+            guide: button(Button::Guide),
+            left_shoulder: button(Button::LeftShoulder),
+            right_shoulder: button(Button::RightShoulder),
+            left_stick: button(Button::LeftStick),
+            right_stick: button(Button::RightStick),
             left_stick_x: self.get_axes(0),
             left_stick_y: self.get_axes(1),
             right_stick_x: self.get_axes(2),
             right_stick_y: self.get_axes(3),
-            left_trigger: if self.get_button(6) { 1.0 } else { 0.0 },
-            right_trigger: if self.get_button(7) { 1.0 } else { 0.0 },
-        })
+            left_trigger: self.get_button_value(6),
+            right_trigger: self.get_button_value(7),
+        };
+        inversion.apply(&mut init);
+        Controller::new(init)
     }
 }
 
@@ -158,12 +222,24 @@ impl InstanceWrapper {
             scratch: Vec::new(),
         }
     }
+    fn memory_size(&self) -> usize {
+        self.memory()
+            .buffer()
+            .dyn_into::<ArrayBuffer>()
+            .map(|buffer| buffer.byte_length() as usize)
+            .unwrap_or(0)
+    }
     fn memory(&self) -> WebAssembly::Memory {
         Reflect::get(self.instance.exports().as_ref(), &"memory".into())
             .unwrap()
             .dyn_into::<WebAssembly::Memory>()
             .unwrap()
     }
+    fn has_function(&self, name: &str) -> bool {
+        Reflect::get(self.instance.exports().as_ref(), &name.into())
+            .map(|function| function.is_function())
+            .unwrap_or(false)
+    }
     fn function(&self, name: &str) -> Function {
         Reflect::get(self.instance.exports().as_ref(), &name.into())
             .unwrap()
@@ -211,9 +287,76 @@ impl InstanceWrapper {
         self.free(pointer);
         serial::decode::<T>(&self.scratch)
     }
+    /// Stops the runtime after a call into the game has thrown, logging the games panic message
+    /// if it left one
+    fn crashed(&self, error: JsValue) -> ! {
+        match self.optional_string("panic_message") {
+            Some(message) => {
+                log(&format!("Romy: game crashed: {}", message));
+                panic!("game crashed: {}", message);
+            }
+            None => {
+                log(&format!("Romy: game crashed: {:?}", error));
+                panic!("game crashed: {:?}", error);
+            }
+        }
+    }
+    /// Calls an optional export that returns a pointer to a string owned by the game, None if the
+    /// game doesn't have the export or it returned null
+    fn optional_string(&self, name: &str) -> Option<String> {
+        // Optional exports, games don't have to provide them:
+        if !self.has_function(name) {
+            return None;
+        }
+        let pointer = self
+            .function(name)
+            .call0(&JsValue::undefined())
+            .ok()
+            .and_then(|pointer| pointer.as_f64())
+            .unwrap_or(0.0) as u32;
+        if pointer == 0 {
+            return None;
+        }
+
+        let buffer = self.memory().buffer().dyn_into::<ArrayBuffer>().ok()?;
+        read_from_memory::<String>(&buffer, pointer)
+    }
+    /// Gets the version of the interface the game was built against, None if it doesn't say
+    fn api_version(&self) -> Option<i32> {
+        if !self.has_function("romy_api_version") {
+            return None;
+        }
+        self.function("romy_api_version")
+            .call0(&JsValue::undefined())
+            .ok()
+            .and_then(|version| version.as_f64())
+            .map(|version| version as i32)
+    }
+    /// Starts the game, giving the reason the game gave if it failed to start
+    fn init(&mut self) -> Result<Info, String> {
+        let pointer = self
+            .function("init")
+            .call0(&JsValue::undefined())
+            .unwrap_or_else(|error| self.crashed(error))
+            .as_f64()
+            .unwrap() as u32;
+        if pointer == 0 {
+            let reason = self.optional_string("init_error");
+            return Err(reason.unwrap_or_else(|| "no reason given".to_string()));
+        }
+
+        let buffer = self.memory().buffer().dyn_into::<ArrayBuffer>().unwrap();
+        let info = read_from_memory::<Info>(&buffer, pointer);
+        self.free(pointer);
+        info.ok_or_else(|| "the info it gave couldn't be decoded".to_string())
+    }
     fn call<'a, T: serde::Deserialize<'a>>(&'a mut self, name: &str) -> T {
         let func = self.function(name);
-        let pointer = func.call0(&JsValue::undefined()).unwrap().as_f64().unwrap() as u32;
+        let pointer = func
+            .call0(&JsValue::undefined())
+            .unwrap_or_else(|error| self.crashed(error))
+            .as_f64()
+            .unwrap() as u32;
         self.decode(pointer)
     }
     fn call_with_arg<'a, T: serde::Deserialize<'a>>(
@@ -228,7 +371,7 @@ impl InstanceWrapper {
                 &JsValue::undefined(),
                 &JsValue::from_f64(f64::from(arg_pointer)),
             )
-            .unwrap()
+            .unwrap_or_else(|error| self.crashed(error))
             .as_f64()
             .unwrap() as u32;
         self.free(arg_pointer);
@@ -241,7 +384,7 @@ impl InstanceWrapper {
                 &JsValue::undefined(),
                 &JsValue::from_f64(f64::from(arg_pointer)),
             )
-            .unwrap();
+            .unwrap_or_else(|error| self.crashed(error));
         self.free(arg_pointer);
     }
     fn save(&mut self) {
@@ -274,8 +417,12 @@ impl InstanceWrapper {
 struct RomyGame {
     instance: InstanceWrapper,
     info: Info,
-    start_time: f64,
-    steps: i32,
+    start_time: Duration,
+    scheduler: StepScheduler,
+    presence: PlayerPresence,
+    memory_warned: bool,
+    // Called by the instance, freed along with the game:
+    _storage: StorageImports,
 }
 
 impl GameMut for RomyGame {
@@ -292,44 +439,308 @@ impl GameMut for RomyGame {
     fn render_audio(&mut self, arguments: &RenderAudioArguments) -> Sound {
         self.instance.call_with_arg("render_audio", arguments)
     }
+
+    fn draw_layers(&mut self, arguments: &DrawArguments) -> Vec<Layer> {
+        // Optional export, games don't have to provide it:
+        if !self.instance.has_function("draw_layers") {
+            return vec![Layer::new(self.draw(arguments), 0, 0, BlendMode::Opaque)];
+        }
+        self.instance.call_with_arg("draw_layers", arguments)
+    }
+
+    fn render_voices(&mut self, arguments: &RenderAudioArguments) -> Vec<Voice> {
+        // Optional export, games don't have to provide it:
+        if !self.instance.has_function("render_voices") {
+            return vec![Voice::new("main", 1.0, self.render_audio(arguments))];
+        }
+        self.instance.call_with_arg("render_voices", arguments)
+    }
+
+    fn rumble(&mut self) -> Option<Rumble> {
+        // Optional export, games don't have to provide it:
+        if !self.instance.has_function("rumble") {
+            return None;
+        }
+        self.instance.call("rumble")
+    }
+
+    fn title(&mut self) -> Option<String> {
+        // Optional export, games don't have to provide it:
+        if !self.instance.has_function("title") {
+            return None;
+        }
+        self.instance.call("title")
+    }
+
+    fn memory_usage(&mut self) -> Option<usize> {
+        Some(self.instance.memory_size())
+    }
 }
 
 impl RomyGame {
-    fn new(instance: WebAssembly::Instance) -> Self {
+    fn new(
+        instance: WebAssembly::Instance,
+        storage: StorageImports,
+        options: &RunOptions,
+    ) -> Result<Self, String> {
+        *storage.instance.borrow_mut() = Some(instance.clone());
         let mut instance = InstanceWrapper::new(instance);
 
-        let info: Info = instance.call("init");
-        let window = window();
-        let start_time = window.performance().unwrap().now();
+        // Games built against another version encode their data differently:
+        match instance.api_version() {
+            Some(API_VERSION) => {}
+            Some(version) => {
+                return Err(format!(
+                    "it was built for API version {}, this runtime supports version {}",
+                    version, API_VERSION
+                ))
+            }
+            None => return Err("it doesn't export romy_api_version".to_string()),
+        }
+
+        // Optional export, games don't have to provide it:
+        if instance.has_function("set_launch_arguments") {
+            instance.call_with_arg_no_return("set_launch_arguments", &options.launch_arguments);
+        }
+
+        let info = instance.init()?;
+        let start_time = PerformanceClock.now();
         instance.save();
 
-        Self {
+        let scheduler = StepScheduler::new(
+            info.step_interval(),
+            options.max_steps_per_frame,
+            options.catch_up_policy,
+        )
+        .with_frame_pacing(options.frame_pacing);
+
+        Ok(Self {
             instance,
             info,
             start_time,
-            steps: 0,
-        }
+            presence: PlayerPresence::new(),
+            scheduler,
+            memory_warned: false,
+            _storage: storage,
+        })
     }
 }
 
-fn request_animation_frame(f: &Closure<FnMut()>) {
+fn request_animation_frame(f: &Closure<FnMut()>) -> i32 {
     window()
         .request_animation_frame(f.as_ref().unchecked_ref())
-        .unwrap();
+        .unwrap()
+}
+
+/// Follows two fingers on the canvas for the touchGestures option, adding up how far they pinched
+/// and panned until the next frame takes it
+#[derive(Default)]
+struct GestureTracker {
+    // Identifier and position in CSS pixels of the two touches, empty unless exactly two are down:
+    touches: Vec<(i32, f32, f32)>,
+    pinch: f32,
+    pan_x: f32,
+    pan_y: f32,
+}
+
+impl GestureTracker {
+    /// Updates the tracked touches from a touch event, returns true while two fingers are down so
+    /// the browser can be stopped from scrolling or zooming the page instead
+    ///
+    /// # Arguments
+    /// * `touches` - Every touch on the screen, from TouchEvent.touches
+    fn touch(&mut self, touches: &web_sys::TouchList) -> bool {
+        let current: Vec<_> = if touches.length() == 2 {
+            (0..2)
+                .filter_map(|index| touches.get(index))
+                .map(|touch| {
+                    let (x, y) = (touch.client_x() as f32, touch.client_y() as f32);
+                    (touch.identifier(), x, y)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        // Only movement of the same two fingers counts, not a finger being swapped for another:
+        if let ([a, b], [c, d]) = (&self.touches[..], &current[..]) {
+            if a.0 == c.0 && b.0 == d.0 {
+                let distance = |a: &(i32, f32, f32), b: &(i32, f32, f32)| {
+                    (b.1 - a.1).hypot(b.2 - a.2)
+                };
+                self.pinch += distance(c, d) - distance(a, b);
+                self.pan_x += (c.1 + d.1 - a.1 - b.1) / 2.0;
+                self.pan_y += (c.2 + d.2 - a.2 - b.2) / 2.0;
+            }
+        }
+
+        self.touches = current;
+        self.touches.len() == 2
+    }
+
+    /// Takes the movement since the last call, None if there aren't two fingers down and they
+    /// haven't moved
+    ///
+    /// # Arguments
+    /// * `scale` - Game pixels per CSS pixel horizontally and vertically, they differ for games
+    ///   with non-square pixels
+    fn take(&mut self, scale: (f32, f32)) -> Option<Gesture> {
+        let moved = self.pinch != 0.0 || self.pan_x != 0.0 || self.pan_y != 0.0;
+        if self.touches.len() != 2 && !moved {
+            return None;
+        }
+
+        let gesture = Gesture::new(
+            self.pinch * scale.1,
+            self.pan_x * scale.0,
+            self.pan_y * scale.1,
+        );
+        self.pinch = 0.0;
+        self.pan_x = 0.0;
+        self.pan_y = 0.0;
+        Some(gesture)
+    }
+}
+
+/// Draws a crosshair over the game pixel under the mouse for the showPointer option. The mouse is
+/// mapped into the game with pointer_to_render() and back out again, so the crosshair only follows
+/// the mouse if the mapping is right.
+///
+/// # Arguments
+/// * `context` - The visible canvas, after the frame has been drawn to it
+/// * `mouse` - Mouse position over the canvas, in CSS pixels
+/// * `css_size` - Size of the canvas, in CSS pixels
+/// * `backing_size` - Size of the canvas backing store, in physical pixels
+/// * `viewport` - Where the frame was drawn in the backing store
+/// * `render_size` - Size of the image the game drew
+fn draw_pointer(
+    context: &web_sys::CanvasRenderingContext2d,
+    mouse: (f32, f32),
+    css_size: (u32, u32),
+    backing_size: (u32, u32),
+    viewport: Rect,
+    render_size: (i32, i32),
+) {
+    let position = pointer_to_render(
+        mouse.0,
+        mouse.1,
+        css_size,
+        backing_size,
+        viewport,
+        render_size,
+    );
+    let (x, y) = match position {
+        Some(position) => position,
+        None => return,
+    };
+
+    let (center_x, center_y) = render_pixel_center(x, y, viewport, render_size);
+    let (center_x, center_y) = (f64::from(center_x), f64::from(center_y));
+    let pixel_size = viewport.width() as f32 / render_size.0.max(1) as f32;
+    let arm = f64::from((pixel_size * 4.0).max(8.0));
+
+    context.set_stroke_style(&"#ff00ff".into());
+    context.set_line_width(1.0);
+    context.begin_path();
+    context.move_to(center_x - arm, center_y);
+    context.line_to(center_x + arm, center_y);
+    context.move_to(center_x, center_y - arm);
+    context.line_to(center_x, center_y + arm);
+    context.stroke();
+}
+
+/// An event listener added by bind(), kept so it can be removed again when the game is stopped
+struct Listener {
+    target: EventTarget,
+    event: &'static str,
+    callback: Function,
+    _closure: Box<dyn Any>,
+}
+
+impl Listener {
+    /// Adds a listener to a target
+    ///
+    /// # Arguments
+    /// * `target` - The object to listen to
+    /// * `event` - The name of the event
+    /// * `closure` - Called with each event, kept alive until the listener is removed
+    fn add<T: ?Sized + 'static>(
+        target: &EventTarget,
+        event: &'static str,
+        closure: Closure<T>,
+    ) -> Result<Self, JsValue> {
+        let callback: Function = closure.as_ref().clone().unchecked_into();
+        target.add_event_listener_with_callback(event, &callback)?;
+        Ok(Self {
+            target: target.clone(),
+            event,
+            callback,
+            _closure: Box::new(closure),
+        })
+    }
+
+    fn remove(self) {
+        self.target
+            .remove_event_listener_with_callback(self.event, &self.callback)
+            .unwrap();
+    }
+}
+
+/// Called for every animation frame, it requests the next frame itself
+type FrameClosure = Closure<dyn FnMut()>;
+
+/// Called by the audio processor when it needs more samples
+type AudioClosure = Closure<dyn FnMut(web_sys::AudioProcessingEvent)>;
+
+/// Everything bind() sets running, held by the Romy handle so Romy::stop() can tear it down
+struct Running {
+    listeners: Vec<Listener>,
+    animation_closure: Rc<RefCell<Option<FrameClosure>>>,
+    animation_frame: Rc<Cell<i32>>,
+    audio: Rc<RefCell<Audio>>,
+    romy_game: Rc<RefCell<Option<RomyGame>>>,
+    /// The canvas bind() created and the element it was added to, None for supplied canvases
+    created_canvas: Option<(web_sys::HtmlElement, web_sys::HtmlCanvasElement)>,
+}
+
+impl Running {
+    fn stop(self) {
+        window()
+            .cancel_animation_frame(self.animation_frame.get())
+            .unwrap();
+        // The animation closure holds a reference to itself to request the next frame:
+        self.animation_closure.borrow_mut().take();
+        for listener in self.listeners {
+            listener.remove();
+        }
+        self.audio.borrow_mut().stop();
+        self.romy_game.borrow_mut().take();
+        if let Some((element, canvas)) = self.created_canvas {
+            element.remove_child(&canvas).unwrap();
+        }
+    }
 }
 
 struct Audio {
     started: bool,
     samples: Rc<RefCell<VecDeque<f32>>>,
+    volume: Rc<Cell<f32>>,
+    buffer_size: u32,
     audio_context: AudioContext,
+    processor: Option<(ScriptProcessorNode, AudioClosure)>,
+    stopped: bool,
 }
 
 impl Audio {
-    fn new(samples: Rc<RefCell<VecDeque<f32>>>) -> Self {
+    fn new(samples: Rc<RefCell<VecDeque<f32>>>, volume: Rc<Cell<f32>>, buffer_size: u32) -> Self {
         let mut audio = Audio {
             started: false,
             samples,
+            volume,
+            buffer_size,
             audio_context: AudioContext::new().unwrap(),
+            processor: None,
+            stopped: false,
         };
 
         audio.start();
@@ -337,7 +748,7 @@ impl Audio {
         audio
     }
     fn start(&mut self) {
-        if self.started {
+        if self.started || self.stopped {
             return;
         }
         self.audio_context.resume().unwrap();
@@ -348,15 +759,20 @@ impl Audio {
         {
             self.samples.borrow_mut().clear();
         }
-        let processor = self.audio_context.create_script_processor_with_buffer_size_and_number_of_input_channels_and_number_of_output_channels(1024, 2, 2).unwrap();
+        let processor = self.audio_context.create_script_processor_with_buffer_size_and_number_of_input_channels_and_number_of_output_channels(self.buffer_size, 2, 2).unwrap();
         let samples_inner = self.samples.clone();
+        let volume_inner = self.volume.clone();
         let closure = Closure::wrap(Box::new(move |event: web_sys::AudioProcessingEvent| {
             let output_buffer = event.output_buffer().unwrap();
             let mut samples = samples_inner.borrow_mut();
             if samples.len() < output_buffer.length() as usize {
                 return;
             }
-            let mut samples: Vec<_> = samples.drain(..output_buffer.length() as usize).collect();
+            let volume = volume_inner.get();
+            let mut samples: Vec<_> = samples
+                .drain(..output_buffer.length() as usize)
+                .map(|sample| sample * volume)
+                .collect();
             for channel in 0..output_buffer.number_of_channels() {
                 output_buffer
                     .copy_to_channel(&mut samples, channel as i32)
@@ -364,24 +780,222 @@ impl Audio {
             }
         }) as Box<dyn FnMut(_)>);
         processor.set_onaudioprocess(Some(closure.as_ref().unchecked_ref()));
-        closure.forget();
         let destination = self.audio_context.destination();
         processor.connect_with_audio_node(&destination).unwrap();
+        self.processor = Some((processor, closure));
+    }
+
+    /// Suspends the audio context while the page is hidden, so nothing keeps playing in the
+    /// background, and resumes it when the page is shown again
+    ///
+    /// # Arguments
+    /// * `hidden` - Is the page hidden
+    fn set_hidden(&mut self, hidden: bool) {
+        if !self.started || self.stopped {
+            return;
+        }
+        if hidden {
+            let _ = self.audio_context.suspend();
+        } else {
+            // Anything queued before hiding is stale now:
+            self.samples.borrow_mut().clear();
+            let _ = self.audio_context.resume();
+        }
+    }
+
+    /// Stops playing for good and releases the audio context
+    fn stop(&mut self) {
+        self.stopped = true;
+        if let Some((processor, _closure)) = self.processor.take() {
+            processor.set_onaudioprocess(None);
+            processor.disconnect().unwrap();
+        }
+        let _ = self.audio_context.close();
+    }
+}
+
+/// Gets the memory of an instance as it is right now, it can be replaced when the instance grows
+/// its memory or a snapshot is loaded so shouldn't be held on to
+fn instance_memory(instance: &WebAssembly::Instance) -> ArrayBuffer {
+    Reflect::get(instance.exports().as_ref(), &"memory".into())
+        .unwrap()
+        .dyn_into::<WebAssembly::Memory>()
+        .unwrap()
+        .buffer()
+        .dyn_into::<ArrayBuffer>()
+        .unwrap()
+}
+
+/// Checks a game started and the info it gave, logging why and giving None if it can't be run
+fn validated(game: Result<RomyGame, String>) -> Option<RomyGame> {
+    let game = match game {
+        Ok(game) => game,
+        Err(reason) => {
+            log(&format!("Romy: not running game, it failed to start: {}", reason));
+            return None;
+        }
+    };
+    match game.info.validate() {
+        Ok(()) => Some(game),
+        Err(error) => {
+            log(&format!("Romy: not running game, it gave invalid info: {}", error));
+            None
+        }
+    }
+}
+
+/// Reads a value encoded with romy_core::serial::encode_with_size from a games memory, the game
+/// chooses the pointer so None is returned if it doesn't point at valid data
+fn read_from_memory<T: serde::de::DeserializeOwned>(
+    buffer: &ArrayBuffer,
+    pointer: u32,
+) -> Option<T> {
+    let length = u64::from(buffer.byte_length());
+    if u64::from(pointer) + 8 > length {
+        return None;
+    }
+    let mut size_buffer: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0];
+    Uint8Array::new_with_byte_offset_and_length(buffer, pointer, 8).copy_to(&mut size_buffer);
+    let size = u64::from_le_bytes(size_buffer);
+    if u64::from(pointer) + 8 + size > length {
+        return None;
     }
+
+    let mut data = vec![0; size as usize + 8];
+    Uint8Array::new_with_byte_offset_and_length(buffer, pointer, size as u32 + 8)
+        .copy_to(&mut data);
+    serial::try_decode_with_size(&data)
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Loads some saved data from the browsers local storage, where it is kept as hex
+fn storage_load(key: &str) -> Option<Vec<u8>> {
+    let local_storage = window().local_storage().ok()??;
+    from_hex(&local_storage.get_item(key).ok()??)
+}
+
+/// The imports object given to games, storage is backed by the browsers local storage. The
+/// closures behind the imports are kept here rather than forgotten, so they are freed when the
+/// game using them is.
+struct StorageImports {
+    imports: Object,
+    // The instance the imports read from and write to, filled in once it has been created:
+    instance: Rc<RefCell<Option<WebAssembly::Instance>>>,
+    _closures: Vec<Box<dyn Any>>,
 }
 
-fn load_wasm(path: &str, romy_game: Rc<RefCell<Option<RomyGame>>>, streaming: bool) {
+/// Builds the imports given to games
+///
+/// # Arguments
+/// * `prefix` - Put in front of every key the game uses, so games don't see each others data
+fn storage_imports(prefix: String) -> StorageImports {
+    let instance = Rc::new(RefCell::new(None::<WebAssembly::Instance>));
+    let mut closures: Vec<Box<dyn Any>> = Vec::new();
+    let env = Object::new();
+
+    let instance_inner = instance.clone();
+    let prefix_inner = prefix.clone();
+    let closure = Closure::wrap(Box::new(move |key: u32| -> i32 {
+        // Not set until the game has been instantiated:
+        let memory = match instance_inner.borrow().as_ref() {
+            Some(instance) => instance_memory(instance),
+            None => return -1,
+        };
+        let key: Option<String> = read_from_memory(&memory, key);
+        key.filter(|key| Storage::check_key(key).is_ok())
+            .and_then(|key| storage_load(&format!("{}{}", prefix_inner, key)))
+            .map(|data| data.len() as i32)
+            .unwrap_or(-1)
+    }) as Box<dyn Fn(u32) -> i32>);
+    Reflect::set(env.as_ref(), &"storage_size".into(), closure.as_ref()).unwrap();
+    closures.push(Box::new(closure));
+
+    let instance_inner = instance.clone();
+    let prefix_inner = prefix.clone();
+    let closure = Closure::wrap(Box::new(move |key: u32, pointer: u32| {
+        let memory = match instance_inner.borrow().as_ref() {
+            Some(instance) => instance_memory(instance),
+            None => return,
+        };
+        let key: Option<String> = read_from_memory(&memory, key);
+        let key = key.filter(|key| Storage::check_key(key).is_ok());
+        if let Some(data) = key.and_then(|key| storage_load(&format!("{}{}", prefix_inner, key))) {
+            if u64::from(pointer) + data.len() as u64 <= u64::from(memory.byte_length()) {
+                let length = data.len() as u32;
+                let buffer = Uint8Array::new_with_byte_offset_and_length(&memory, pointer, length);
+                unsafe {
+                    buffer.set(&Uint8Array::view(&data), 0);
+                }
+            }
+        }
+    }) as Box<dyn Fn(u32, u32)>);
+    Reflect::set(env.as_ref(), &"storage_read".into(), closure.as_ref()).unwrap();
+    closures.push(Box::new(closure));
+
+    let instance_inner = instance.clone();
+    let closure = Closure::wrap(Box::new(move |key: u32, data: u32| -> i32 {
+        let memory = match instance_inner.borrow().as_ref() {
+            Some(instance) => instance_memory(instance),
+            None => return 0,
+        };
+        let key: Option<String> = read_from_memory(&memory, key);
+        let data: Option<Vec<u8>> = read_from_memory(&memory, data);
+        let local_storage = window().local_storage().ok().and_then(|storage| storage);
+        if let (Some(key), Some(data), Some(local_storage)) = (key, data, local_storage) {
+            if let Err(error) = Storage::check_key(&key) {
+                log(&format!("Romy: couldn't save {}: {}", key, error));
+                return 0;
+            }
+            if data.len() <= Storage::MAX_SIZE {
+                let key = format!("{}{}", prefix, key);
+                if local_storage.set_item(&key, &to_hex(&data)).is_ok() {
+                    return 1;
+                }
+            }
+        }
+        0
+    }) as Box<dyn Fn(u32, u32) -> i32>);
+    Reflect::set(env.as_ref(), &"storage_write".into(), closure.as_ref()).unwrap();
+    closures.push(Box::new(closure));
+
+    let imports = Object::new();
+    Reflect::set(imports.as_ref(), &"env".into(), env.as_ref()).unwrap();
+    StorageImports {
+        imports,
+        instance,
+        _closures: closures,
+    }
+}
+
+fn load_wasm(
+    path: &str,
+    options: Rc<RunOptions>,
+    romy_game: Rc<RefCell<Option<RomyGame>>>,
+    streaming: bool,
+) {
     let mut opts = RequestInit::new();
     opts.method("GET");
     opts.mode(RequestMode::Cors);
-    let imports = Object::new();
+    // Moved into the game once it loads, the closures waiting on the load are never freed:
+    let mut storage = Some(storage_imports(format!("romy:{}:", path)));
 
     let request = Request::new_with_str_and_init(&path, &opts).unwrap();
     let request_promise = window().fetch_with_request(&request);
 
     let romy_game_inner = romy_game.clone();
     if streaming {
-        let wasm_stream = WebAssembly::instantiate_streaming(&request_promise, &imports);
+        let imports = &storage.as_ref().unwrap().imports;
+        let wasm_stream = WebAssembly::instantiate_streaming(&request_promise, imports);
 
         //TODO: These future closures are a bit funky to deal with, there has to be a better way
         let wasm_stream_closure = std::rc::Rc::new(std::cell::RefCell::new(None));
@@ -392,7 +1006,8 @@ fn load_wasm(path: &str, romy_game: Rc<RefCell<Option<RomyGame>>>, streaming: bo
                 .dyn_into::<WebAssembly::Instance>()
                 .unwrap();
 
-            *romy_game_inner.borrow_mut() = Some(RomyGame::new(instance));
+            let storage = storage.take().unwrap();
+            *romy_game_inner.borrow_mut() = validated(RomyGame::new(instance, storage, &options));
             wasm_stream_closure_inner.borrow().as_ref().unwrap();
         }) as Box<FnMut(JsValue)>));
         wasm_stream.then(wasm_stream_closure.borrow().as_ref().unwrap());
@@ -412,11 +1027,15 @@ fn load_wasm(path: &str, romy_game: Rc<RefCell<Option<RomyGame>>>, streaming: bo
             let bytes_closure = std::rc::Rc::new(std::cell::RefCell::new(None));
             let bytes_closure_inner = bytes_closure.clone();
             let romy_game_inner = romy_game.clone();
+            let options = options.clone();
+            let mut storage = storage.take();
             *bytes_closure.borrow_mut() = Some(Closure::wrap(Box::new(move |obj: JsValue| {
+                let storage = storage.take().unwrap();
                 let array = obj.dyn_into::<ArrayBuffer>().unwrap();
                 let module = WebAssembly::Module::new(&array).unwrap();
-                let instance = WebAssembly::Instance::new(&module, &Object::new()).unwrap();
-                *romy_game_inner.borrow_mut() = Some(RomyGame::new(instance));
+                let instance = WebAssembly::Instance::new(&module, &storage.imports).unwrap();
+                *romy_game_inner.borrow_mut() =
+                    validated(RomyGame::new(instance, storage, &options));
 
                 bytes_closure_inner.borrow().as_ref().unwrap();
             })
@@ -429,41 +1048,360 @@ fn load_wasm(path: &str, romy_game: Rc<RefCell<Option<RomyGame>>>, streaming: bo
     }
 }
 
+/// Options for changing how the runtime looks and behaves, read from a plain JS object passed to
+/// bind()
+struct RunOptions {
+    /// CSS color used to fill the space around the game, from `letterboxColor`
+    letterbox_color: String,
+    /// URL of an image stretched behind the game that shows in the letterbox margins, from
+    /// `border`
+    border: Option<String>,
+    /// Volume applied to everything the game plays, 0.0 - 1.0, from `masterVolume`
+    master_volume: f32,
+    /// Mixer used to combine the voices a game renders, with gains for voices by name from
+    /// `voiceGains`
+    mixer: Mixer,
+    /// The most steps run in a single frame when catching up, from `maxStepsPerFrame`
+    max_steps_per_frame: u32,
+    /// What to do with time that can't be caught up on, from `catchUpPolicy` which can be
+    /// "dropTime" or "runSlow"
+    catch_up_policy: CatchUpPolicy,
+    /// How the time between frames is read, from `framePacing` which can be "exact" or
+    /// "smoothed"
+    frame_pacing: FramePacing,
+    /// Launch arguments handed to the game before it initializes, from `launchArguments`
+    launch_arguments: Vec<u8>,
+    /// Pause the game while the page doesn't have focus, from `pauseOnFocusLoss`. Hidden pages
+    /// are always paused.
+    pause_on_focus_loss: bool,
+    /// Ratio of physical to logical pixels the canvas is rendered at, from `pixelRatio`. None uses
+    /// the devicePixelRatio of the window.
+    pixel_ratio: Option<f64>,
+    /// Number of samples played per audio callback, from `audioBufferSize`. ScriptProcessorNode
+    /// only takes powers of two from 256 to 16384, so other values are rounded up to one.
+    audio_buffer_size: u32,
+    /// Split the keyboard between two players, one on WASD and one on the arrow keys, from
+    /// `splitKeyboard`
+    split_keyboard: bool,
+    /// Which keys press each button when the keyboard is used as a Nes controller, changed by
+    /// `keyBindings`
+    key_bindings: KeyBindings,
+    /// Load games dropped onto the page in place of the running one, from `dropToLoad`
+    drop_to_load: bool,
+    /// Pass two finger pinches and pans on the canvas to the game, from `touchGestures`
+    touch_gestures: bool,
+    /// Which gamepad button index is read for each Controller button, changed by `buttonMap`
+    button_remap: ButtonRemap,
+    /// Which stick axes are negated before the game sees them, from `invertAxes`
+    axis_inversion: AxisInversion,
+    /// Most memory a game can use, from `memoryLimit` in megabytes and `memoryLimitAction` which
+    /// can be "warn" or "halt"
+    memory_limit: MemoryLimit,
+    /// Debugging aid that draws a crosshair over the game pixel under the mouse, from
+    /// `showPointer`
+    show_pointer: bool,
+    /// An existing canvas to draw into instead of creating one, from `canvas`
+    canvas: Option<web_sys::HtmlCanvasElement>,
+}
+
+impl RunOptions {
+    fn from_object(options: Option<Object>) -> Self {
+        let mut result = Self {
+            letterbox_color: "black".to_string(),
+            border: None,
+            master_volume: 1.0,
+            mixer: Mixer::new(),
+            max_steps_per_frame: 8,
+            catch_up_policy: CatchUpPolicy::DropTime,
+            frame_pacing: FramePacing::Exact,
+            launch_arguments: Vec::new(),
+            pause_on_focus_loss: false,
+            pixel_ratio: None,
+            audio_buffer_size: 1024,
+            split_keyboard: false,
+            key_bindings: KeyBindings::default(),
+            drop_to_load: true,
+            touch_gestures: false,
+            button_remap: default_button_remap(),
+            axis_inversion: AxisInversion::default(),
+            memory_limit: MemoryLimit::default(),
+            show_pointer: false,
+            canvas: None,
+        };
+
+        if let Some(options) = options {
+            if let Some(letterbox_color) = option_string(&options, "letterboxColor") {
+                result.letterbox_color = letterbox_color;
+            }
+            result.border = option_string(&options, "border");
+            if let Some(master_volume) = option_number(&options, "masterVolume") {
+                result.master_volume = master_volume as f32;
+            }
+            let voice_gains = Reflect::get(options.as_ref(), &"voiceGains".into())
+                .ok()
+                .and_then(|value| value.dyn_into::<Object>().ok());
+            if let Some(voice_gains) = voice_gains {
+                for name in Object::keys(&voice_gains).iter().filter_map(|key| key.as_string()) {
+                    match option_number(&voice_gains, &name) {
+                        Some(gain) => result.mixer.set_gain(&name, gain.max(0.0) as f32),
+                        None => log(&format!("Romy: ignoring voiceGains {}, not a number", name)),
+                    }
+                }
+            }
+            if let Some(max_steps_per_frame) = option_number(&options, "maxStepsPerFrame") {
+                result.max_steps_per_frame = (max_steps_per_frame as u32).max(1);
+            }
+            match option_string(&options, "catchUpPolicy").as_deref() {
+                Some("dropTime") => result.catch_up_policy = CatchUpPolicy::DropTime,
+                Some("runSlow") => result.catch_up_policy = CatchUpPolicy::RunSlow,
+                _ => {}
+            }
+            match option_string(&options, "framePacing").as_deref() {
+                Some("exact") => result.frame_pacing = FramePacing::Exact,
+                Some("smoothed") => result.frame_pacing = FramePacing::Smoothed,
+                _ => {}
+            }
+            if let Some(launch_arguments) = option_string(&options, "launchArguments") {
+                result.launch_arguments = launch_arguments.into_bytes();
+            }
+            if let Some(pause_on_focus_loss) = option_bool(&options, "pauseOnFocusLoss") {
+                result.pause_on_focus_loss = pause_on_focus_loss;
+            }
+            result.pixel_ratio = option_number(&options, "pixelRatio");
+            if let Some(split_keyboard) = option_bool(&options, "splitKeyboard") {
+                result.split_keyboard = split_keyboard;
+            }
+            if let Some(key_bindings) = option_string(&options, "keyBindings") {
+                if result.split_keyboard {
+                    log("Romy: ignoring keyBindings option, splitKeyboard has its own bindings");
+                } else if let Err(error) = result.key_bindings.apply(&key_bindings) {
+                    log(&format!("Romy: ignoring keyBindings option, {}", error));
+                }
+            }
+            if let Some(drop_to_load) = option_bool(&options, "dropToLoad") {
+                result.drop_to_load = drop_to_load;
+            }
+            if let Some(touch_gestures) = option_bool(&options, "touchGestures") {
+                result.touch_gestures = touch_gestures;
+            }
+            if let Some(button_map) = option_string(&options, "buttonMap") {
+                if let Err(error) = result.button_remap.apply(&button_map) {
+                    log(&format!("Romy: ignoring buttonMap option, {}", error));
+                }
+            }
+            if let Some(invert_axes) = option_string(&options, "invertAxes") {
+                match AxisInversion::parse(&invert_axes) {
+                    Ok(axis_inversion) => result.axis_inversion = axis_inversion,
+                    Err(error) => log(&format!("Romy: ignoring invertAxes option, {}", error)),
+                }
+            }
+            if let Some(memory_limit) = option_number(&options, "memoryLimit") {
+                result.memory_limit.max_bytes = (memory_limit.max(0.0) * 1024.0 * 1024.0) as usize;
+            }
+            match option_string(&options, "memoryLimitAction").as_deref() {
+                Some("warn") => result.memory_limit.action = MemoryLimitAction::Warn,
+                Some("halt") => result.memory_limit.action = MemoryLimitAction::Halt,
+                _ => {}
+            }
+            if let Some(show_pointer) = option_bool(&options, "showPointer") {
+                result.show_pointer = show_pointer;
+            }
+            result.canvas = Reflect::get(options.as_ref(), &"canvas".into())
+                .ok()
+                .and_then(|value| value.dyn_into::<web_sys::HtmlCanvasElement>().ok());
+            if let Some(audio_buffer_size) = option_number(&options, "audioBufferSize") {
+                result.audio_buffer_size = (audio_buffer_size.max(0.0) as u32)
+                    .next_power_of_two()
+                    .clamp(256, 16384);
+            }
+        }
+
+        result
+    }
+}
+
+fn option_string(options: &Object, name: &str) -> Option<String> {
+    Reflect::get(options.as_ref(), &name.into())
+        .ok()
+        .and_then(|value| value.as_string())
+}
+
+fn option_number(options: &Object, name: &str) -> Option<f64> {
+    Reflect::get(options.as_ref(), &name.into())
+        .ok()
+        .and_then(|value| value.as_f64())
+}
+
+fn option_bool(options: &Object, name: &str) -> Option<bool> {
+    Reflect::get(options.as_ref(), &name.into())
+        .ok()
+        .and_then(|value| value.as_bool())
+}
+
+/// Gets the button table matching the browsers standard gamepad layout, where physical buttons are
+/// the indices of Gamepad.buttons
+fn default_button_remap() -> ButtonRemap {
+    ButtonRemap::new([0, 1, 2, 3, 12, 13, 14, 15, 9, 8, 16, 4, 5, 10, 11])
+}
+
+/// A handle to a running game, returned from bind()
+#[wasm_bindgen]
+pub struct Romy {
+    volume: Rc<Cell<f32>>,
+    samples: Rc<RefCell<VecDeque<f32>>>,
+    audio_buffer_size: u32,
+    button_remap: Rc<Cell<ButtonRemap>>,
+    running: RefCell<Option<Running>>,
+}
+
+#[wasm_bindgen]
+impl Romy {
+    /// Sets the volume applied to everything the game plays
+    ///
+    /// # Arguments
+    /// * `volume` - The new volume, 0.0 - 1.0
+    pub fn set_master_volume(&self, volume: f32) {
+        self.volume.set(volume.clamp(0.0, 1.0));
+    }
+
+    /// Gets the volume applied to everything the game plays
+    pub fn master_volume(&self) -> f32 {
+        self.volume.get()
+    }
+
+    /// Gets the number of samples waiting to be played, for seeing if the audio is keeping up
+    pub fn queued_audio_samples(&self) -> u32 {
+        self.audio_status().queued_samples() as u32
+    }
+
+    /// Gets how long the samples waiting to be played take to play, in seconds, roughly how far
+    /// behind the game the audio is
+    pub fn audio_latency(&self) -> f64 {
+        self.audio_status().latency().as_micros() as f64 / 1_000_000.0
+    }
+
+    /// Remaps gamepad buttons while the game is running, written the same way as the
+    /// `buttonMap` option. Returns false and changes nothing if the map has a mistake.
+    ///
+    /// # Arguments
+    /// * `button_map` - button=index pairs separated by commas, for example "a=1, b=0"
+    pub fn set_button_map(&self, button_map: &str) -> bool {
+        let mut remap = self.button_remap.get();
+        match remap.apply(button_map) {
+            Ok(()) => {
+                self.button_remap.set(remap);
+                true
+            }
+            Err(error) => {
+                log(&format!("Romy: ignoring button map, {}", error));
+                false
+            }
+        }
+    }
+
+    /// Stops the game for good, cancelling its animation frames, removing the event listeners
+    /// bind() added and closing its audio. A canvas created by bind() is removed from the page.
+    /// Does nothing if the game has already been stopped.
+    pub fn stop(&self) {
+        if let Some(running) = self.running.borrow_mut().take() {
+            running.stop();
+        }
+    }
+}
+
+impl Romy {
+    fn audio_status(&self) -> AudioQueueStatus {
+        AudioQueueStatus::new(self.samples.borrow().len(), self.audio_buffer_size as usize)
+    }
+}
+
 #[wasm_bindgen]
 pub fn bind(
     element: &web_sys::HtmlElement,
     args: Option<String>,
     streaming: Option<bool>,
-) -> Result<(), JsValue> {
+    options: Option<Object>,
+) -> Result<Romy, JsValue> {
     let window = window();
     let document = window.document().unwrap();
     let element = element.clone();
+    let options = Rc::new(RunOptions::from_object(options));
+
+    let style = element.style();
+    style.set_property("background-color", &options.letterbox_color)?;
+    if let Some(border) = &options.border {
+        style.set_property("background-image", &format!("url(\"{}\")", border))?;
+        style.set_property("background-size", "100% 100%")?;
+    }
+
+    // A canvas supplied by the page keeps its own size and style, the game is fitted inside it:
+    let owns_canvas = options.canvas.is_none();
+    let canvas = match &options.canvas {
+        Some(canvas) => canvas.clone(),
+        None => {
+            let canvas = document.create_element("canvas")?;
+            let canvas: web_sys::HtmlCanvasElement = canvas
+                .dyn_into::<web_sys::HtmlCanvasElement>()
+                .map_err(|_| ())
+                .unwrap();
+            element.append_child(&canvas)?;
+            canvas
+        }
+    };
 
-    let canvas = document.create_element("canvas")?;
-    let canvas: web_sys::HtmlCanvasElement = canvas
+    let context = canvas
+        .get_context("2d")
+        .unwrap()
+        .unwrap()
+        .dyn_into::<web_sys::CanvasRenderingContext2d>()
+        .unwrap();
+
+    // Frames are put into this offscreen canvas at the games resolution, then scaled up into the
+    // visible one so it can match the displays pixel density:
+    let buffer = document
+        .create_element("canvas")?
         .dyn_into::<web_sys::HtmlCanvasElement>()
         .map_err(|_| ())
         .unwrap();
-    element.append_child(&canvas)?;
-
-    let context = canvas
+    let buffer_context = buffer
         .get_context("2d")
         .unwrap()
         .unwrap()
         .dyn_into::<web_sys::CanvasRenderingContext2d>()
         .unwrap();
+    let pixel_ratio = options.pixel_ratio;
+    let audio_buffer_size = options.audio_buffer_size as usize;
 
     let samples = Rc::new(RefCell::new(VecDeque::new()));
-    let audio = Rc::new(RefCell::new(Audio::new(samples.clone())));
+    let volume = Rc::new(Cell::new(options.master_volume.clamp(0.0, 1.0)));
+    let audio = Rc::new(RefCell::new(Audio::new(
+        samples.clone(),
+        volume.clone(),
+        options.audio_buffer_size,
+    )));
 
     let romy_game = Rc::new(RefCell::new(None));
 
     if let Some(args) = args {
-        load_wasm(&args, romy_game.clone(), streaming.unwrap_or(true));
+        load_wasm(
+            &args,
+            options.clone(),
+            romy_game.clone(),
+            streaming.unwrap_or(true),
+        );
     }
 
-    let keyboard = Rc::new(RefCell::new(Keyboard::default()));
+    let mut keyboard = Keyboard::default();
+    keyboard.set_bindings(options.key_bindings.clone());
+    let keyboard = Rc::new(RefCell::new(keyboard));
     let controllers = Rc::new(RefCell::new(Vec::new()));
+    let devices_changed = Rc::new(Cell::new(false));
+    let mixer = options.mixer.clone();
+    let image_limits = ImageLimits::default();
+
+    let mut listeners = Vec::new();
+    let window_target: &EventTarget = window.as_ref();
+    let element_target: &EventTarget = element.as_ref();
 
     let audio_inner = audio.clone();
     let keyboard_inner = keyboard.clone();
@@ -474,8 +1412,7 @@ pub fn bind(
         }
         audio_inner.borrow_mut().start();
     }) as Box<dyn FnMut(_)>);
-    window.add_event_listener_with_callback("keydown", closure.as_ref().unchecked_ref())?;
-    closure.forget();
+    listeners.push(Listener::add(window_target, "keydown", closure)?);
 
     let audio_inner = audio.clone();
     let keyboard_inner = keyboard.clone();
@@ -486,32 +1423,37 @@ pub fn bind(
         }
         audio_inner.borrow_mut().start();
     }) as Box<dyn FnMut(_)>);
-    window.add_event_listener_with_callback("keyup", closure.as_ref().unchecked_ref())?;
-    closure.forget();
+    listeners.push(Listener::add(window_target, "keyup", closure)?);
 
     let controllers_inner = controllers.clone();
+    let devices_changed_inner = devices_changed.clone();
     let closure = Closure::wrap(Box::new(move |event: web_sys::GamepadEvent| {
         let mut controllers = controllers_inner.borrow_mut();
         controllers.push(ControllerMapper::new(event.gamepad().unwrap()));
+        devices_changed_inner.set(true);
     }) as Box<dyn FnMut(_)>);
-    window
-        .add_event_listener_with_callback("gamepadconnected", closure.as_ref().unchecked_ref())?;
-    closure.forget();
+    listeners.push(Listener::add(window_target, "gamepadconnected", closure)?);
 
     let controllers_inner = controllers.clone();
+    let devices_changed_inner = devices_changed.clone();
     let closure = Closure::wrap(Box::new(move |event: web_sys::GamepadEvent| {
         let mut controllers = controllers_inner.borrow_mut();
         controllers.retain(|controller| controller.gamepad.id() != event.gamepad().unwrap().id());
+        devices_changed_inner.set(true);
     }) as Box<dyn FnMut(_)>);
-    window.add_event_listener_with_callback(
-        "gamepaddisconnected",
-        closure.as_ref().unchecked_ref(),
-    )?;
-    closure.forget();
+    listeners.push(Listener::add(window_target, "gamepaddisconnected", closure)?);
 
     let romy_game_inner = romy_game.clone();
     let audio_inner = audio.clone();
+    let options_inner = options.clone();
     let closure = Closure::wrap(Box::new(move |event: web_sys::DragEvent| {
+        if !options_inner.drop_to_load {
+            // Still stop the browser navigating to the dropped file:
+            let event: &Event = event.as_ref();
+            event.prevent_default();
+            return;
+        }
+
         audio_inner.borrow_mut().start();
 
         let file = event
@@ -528,118 +1470,381 @@ pub fn bind(
         let data = web_sys::Blob::new_with_blob_sequence_and_options(&data, &props).unwrap();
         let url = Url::create_object_url_with_blob(&data).unwrap();
 
-        load_wasm(&url, romy_game_inner.clone(), streaming.unwrap_or(true));
+        load_wasm(
+            &url,
+            options_inner.clone(),
+            romy_game_inner.clone(),
+            streaming.unwrap_or(true),
+        );
 
         let event: &Event = event.as_ref();
         event.prevent_default();
     }) as Box<dyn FnMut(_)>);
-    element.add_event_listener_with_callback("drop", closure.as_ref().unchecked_ref())?;
-    closure.forget();
+    listeners.push(Listener::add(element_target, "drop", closure)?);
 
     let closure = Closure::wrap(Box::new(move |event: web_sys::DragEvent| {
         let event: &Event = event.as_ref();
         event.prevent_default();
     }) as Box<dyn FnMut(_)>);
-    element.add_event_listener_with_callback("dragover", closure.as_ref().unchecked_ref())?;
-    closure.forget();
+    listeners.push(Listener::add(element_target, "dragover", closure)?);
+
+    // Where the mouse is over the canvas, in CSS pixels, only tracked for showPointer:
+    let mouse = Rc::new(Cell::new(None));
+    if options.show_pointer {
+        let canvas_target: &EventTarget = canvas.as_ref();
+
+        let mouse_inner = mouse.clone();
+        let closure = Closure::wrap(Box::new(move |event: web_sys::MouseEvent| {
+            mouse_inner.set(Some((event.offset_x(), event.offset_y())));
+        }) as Box<dyn FnMut(_)>);
+        listeners.push(Listener::add(canvas_target, "mousemove", closure)?);
+
+        let mouse_inner = mouse.clone();
+        let closure = Closure::wrap(Box::new(move |_event: web_sys::MouseEvent| {
+            mouse_inner.set(None);
+        }) as Box<dyn FnMut(_)>);
+        listeners.push(Listener::add(canvas_target, "mouseleave", closure)?);
+    }
+
+    // Two finger gestures on the canvas, only tracked for touchGestures:
+    let gestures = Rc::new(RefCell::new(GestureTracker::default()));
+    // Game pixels per CSS pixel of the last frame drawn, for scaling gestures into the game:
+    let render_scale = Rc::new(Cell::new((1.0, 1.0)));
+    if options.touch_gestures {
+        let canvas_target: &EventTarget = canvas.as_ref();
+        for event_type in &["touchstart", "touchmove", "touchend", "touchcancel"] {
+            let gestures_inner = gestures.clone();
+            let closure = Closure::wrap(Box::new(move |event: web_sys::TouchEvent| {
+                // Single touches are left alone so they still work as clicks:
+                if gestures_inner.borrow_mut().touch(&event.touches()) {
+                    let event: &Event = event.as_ref();
+                    event.prevent_default();
+                }
+            }) as Box<dyn FnMut(_)>);
+            listeners.push(Listener::add(canvas_target, event_type, closure)?);
+        }
+    }
+
+    let audio_inner = audio.clone();
+    let document_inner = document.clone();
+    let closure = Closure::wrap(Box::new(move |_event: Event| {
+        audio_inner.borrow_mut().set_hidden(document_inner.hidden());
+    }) as Box<dyn FnMut(_)>);
+    listeners.push(Listener::add(document.as_ref(), "visibilitychange", closure)?);
 
     let animation_closure = std::rc::Rc::new(std::cell::RefCell::new(None));
     let animation_closure_inner = animation_closure.clone();
+    let animation_frame = Rc::new(Cell::new(0));
+    let animation_frame_inner = animation_frame.clone();
+    let element_handle = element.clone();
+    let canvas_handle = canvas.clone();
+    let mouse_inner = mouse.clone();
+    let gestures_inner = gestures.clone();
+    let render_scale_inner = render_scale.clone();
     let samples_inner = samples.clone();
     let romy_game_inner = romy_game.clone();
     let keyboard_inner = keyboard.clone();
     let controllers_inner = controllers.clone();
+    let devices_changed_inner = devices_changed.clone();
+    let button_remap = Rc::new(Cell::new(options.button_remap));
+    let button_remap_inner = button_remap.clone();
+    let mut title = None;
+    let pause_on_focus_loss = options.pause_on_focus_loss;
+    let split_keyboard = options.split_keyboard;
+    let touch_gestures = options.touch_gestures;
+    let memory_limit = options.memory_limit;
+    let axis_inversion = options.axis_inversion;
+    let mut paused_at: Option<Duration> = None;
     *animation_closure.borrow_mut() = Some(Closure::wrap(Box::new(move || {
         let mut input = InputCollection::new();
-        input.add_input(InputDevice::Keyboard(keyboard_inner.borrow().clone()));
+        if split_keyboard {
+            let halves = [KeyBindings::left_hand(), KeyBindings::right_hand()];
+            for half in keyboard_inner.borrow().split(&halves) {
+                input.add_input(InputDevice::Keyboard(half));
+            }
+        } else {
+            input.add_input(InputDevice::Keyboard(keyboard_inner.borrow().clone()));
+        }
 
         let mut controllers = controllers_inner.borrow_mut();
         for controller in controllers.iter_mut() {
-            input.add_input(InputDevice::Controller(
-                controller.build_standard_controller(),
-            ));
+            let device_id = controller.gamepad.index();
+            let controller =
+                controller.build_standard_controller(&button_remap_inner.get(), &axis_inversion);
+            input.add_input_with_id(InputDevice::Controller(controller), device_id);
         }
 
         let mut r = romy_game_inner.borrow_mut();
         if let Some(ref mut romy_game) = *r {
-            let now = crate::window().performance().unwrap().now();
-            let time_span = Duration::from_millis((now - romy_game.start_time) as u64);
-            let step = Duration::from_nanos(u64::from(romy_game.info.step_interval()));
-            let expected_steps = (time_span.as_micros() / step.as_micros()) as i32;
-            while romy_game.steps < expected_steps {
-                romy_game.step(&StepArguments::new(
-                    input.get_input_arguments(&romy_game.info),
-                ));
+            let used = romy_game.memory_usage();
+            if let Some(message) = used.and_then(|used| memory_limit.check(used)) {
+                match memory_limit.action {
+                    MemoryLimitAction::Warn if !romy_game.memory_warned => {
+                        log(&format!("Romy: {}", message));
+                        romy_game.memory_warned = true;
+                    }
+                    MemoryLimitAction::Warn => {}
+                    MemoryLimitAction::Halt => {
+                        log(&format!("Romy: stopping the game, {}", message));
+                        *r = None;
+                    }
+                }
+            }
+        }
 
-                let audio = romy_game.render_audio(&RenderAudioArguments {});
+        let document = crate::window().document().unwrap();
+        let hidden = document.hidden();
+        let focused = !hidden && document.has_focus().unwrap_or(true);
+        if let Some(ref mut romy_game) = *r {
+            let now = PerformanceClock.now();
+
+            // Hidden pages always pause, there is nothing to see or hear:
+            let paused = hidden || (pause_on_focus_loss && !focused);
+            match paused_at {
+                None if paused => paused_at = Some(now),
+                Some(paused_since) if !paused => {
+                    // Move the start forward so the paused time doesn't need catching up:
+                    romy_game.start_time += now - paused_since;
+                    paused_at = None;
+                }
+                _ => {}
+            }
+        }
 
-                {
-                    let mut samples = samples_inner.borrow_mut();
-                    let new_samples = audio.samples();
-                    for sample in new_samples {
-                        samples.push_back(*sample);
+        // Skip stepping and drawing entirely while hidden:
+        let running = if hidden { None } else { r.as_mut() };
+        if let Some(romy_game) = running {
+            let now = PerformanceClock.now();
+
+            let time_span = paused_at
+                .unwrap_or(now)
+                .checked_sub(romy_game.start_time)
+                .unwrap_or_default();
+            let steps = romy_game.scheduler.update(time_span);
+            let frame_steps = steps.end - steps.start;
+            let mut gesture = if touch_gestures && frame_steps > 0 {
+                gestures_inner.borrow_mut().take(render_scale_inner.get())
+            } else {
+                None
+            };
+            for step_index in steps {
+                input.set_devices_changed(devices_changed_inner.replace(false));
+
+                let info = &romy_game.info;
+                let input_arguments = input.get_input_arguments(info).with_gesture(gesture);
+                let arguments = StepArguments::new(
+                    romy_game.presence.update(input_arguments),
+                    step_index,
+                    info.step_interval(),
+                )
+                .with_focus(focused);
+                romy_game.step(&arguments);
+                input.clear_key_events();
+                // Only the first step sees the movement, later ones see the fingers held still:
+                gesture = gesture.map(|_| Gesture::default());
+
+                if let Some(rumble) = romy_game.rumble() {
+                    for controller in controllers.iter() {
+                        controller.rumble(&rumble);
                     }
+                }
 
-                    //TODO: Don't let the audio get more than 10 steps out, need better solution:
-                    if samples.len() > new_samples.len()*10 {
-                        samples.clear();
+                if step_index % u64::from(romy_game.info.audio_steps()) == 0 {
+                    let queued = samples_inner.borrow().len();
+                    let arguments =
+                        render_audio_arguments(&romy_game.info, queued, audio_buffer_size);
+                    let audio = mixer.mix(&romy_game.render_voices(&arguments));
+
+                    {
+                        let mut samples = samples_inner.borrow_mut();
+                        let new_samples = audio.samples();
+                        for sample in new_samples {
+                            samples.push_back(*sample);
+                        }
+
+                        // Games that ignore the sample count can still drift, don't let their
+                        // audio get more than 10 chunks out:
+                        if samples.len() > new_samples.len()*10 {
+                            samples.clear();
+                        }
                     }
                 }
 
-                romy_game.steps += 1;
             }
 
-            let step_offset =
-                (time_span.as_micros() % step.as_micros()) as f32 / step.as_micros() as f32;
+            if frame_steps > 0 {
+                // The events have been seen by a step now:
+                keyboard_inner.borrow_mut().clear_events();
 
-            let mut image = romy_game.draw(&DrawArguments::new(320, 240, step_offset));
-
-            let render_width = image.width();
-            let render_height = image.height();
-            canvas.set_width(render_width as u32);
-            canvas.set_height(render_height as u32);
+                if let Some(new_title) = romy_game.title() {
+                    if title.as_ref() != Some(&new_title) {
+                        let document = crate::window().document().unwrap();
+                        document.set_title(&new_title);
+                        title = Some(new_title);
+                    }
+                }
+            }
 
-            let image = web_sys::ImageData::new_with_u8_clamped_array(
-                Clamped(image.pixels8_mut()),
-                render_width as u32,
-            )
-            .unwrap();
-            context.put_image_data(&image, 0.0, 0.0).unwrap();
-            let width = element.offset_width();
-            let height = element.offset_height();
+            let step_offset = romy_game.scheduler.step_offset(time_span);
 
-            let scale =
-                (width as f32 / render_width as f32).min(height as f32 / render_height as f32);
-            let new_width = (render_width as f32 * scale) as i32;
-            let new_height = (render_height as f32 * scale) as i32;
+            let scale_factor = pixel_ratio.unwrap_or_else(|| crate::window().device_pixel_ratio());
+            let scale_factor = scale_factor.max(1.0) as f32;
+            let layers = romy_game.draw_layers(&DrawArguments::new(
+                320,
+                240,
+                step_offset,
+                scale_factor,
+                (time_span.as_micros() as f64 / 1_000_000.0) as f32,
+            ));
+            if let Err(error) = image_limits.check_layers(&layers) {
+                log(&format!("Romy: not displaying frame, {}", error));
+            } else if is_skipped_frame(&layers) {
+                // Leave the last frame the game drew on the canvas
+            } else {
+                let mut image = composite(layers);
+
+                let render_width = image.width();
+                let render_height = image.height();
+                buffer.set_width(render_width as u32);
+                buffer.set_height(render_height as u32);
+
+                let image = web_sys::ImageData::new_with_u8_clamped_array(
+                    Clamped(image.pixels8_mut()),
+                    render_width as u32,
+                )
+                .unwrap();
+                buffer_context.put_image_data(&image, 0.0, 0.0).unwrap();
+                let (width, height) = if owns_canvas {
+                    (element.offset_width(), element.offset_height())
+                } else {
+                    (canvas.client_width(), canvas.client_height())
+                };
+
+                // Games with non-square pixels are stretched horizontally to match:
+                let display_width = render_width as f32 * romy_game.info.pixel_aspect_ratio();
+                let scale =
+                    (width as f32 / display_width).min(height as f32 / render_height as f32);
+                let new_width = (display_width * scale) as i32;
+                let new_height = (render_height as f32 * scale) as i32;
+                render_scale_inner.set((
+                    render_width as f32 / new_width.max(1) as f32,
+                    render_height as f32 / new_height.max(1) as f32,
+                ));
 
-            let padding_left = ((width - new_width) / 2) as i32;
-            let padding_top = ((height - new_height) / 2) as i32;
+                let padding_left = ((width - new_width) / 2) as i32;
+                let padding_top = ((height - new_height) / 2) as i32;
+
+                // The backing store is sized in physical pixels and CSS sizes the canvas in
+                // logical ones, so nothing gets resampled by the browser on high DPI displays:
+                let physical = |size: i32| (size as f32 * scale_factor).round() as u32;
+                let (backing_width, backing_height) = if owns_canvas {
+                    (physical(new_width).max(1), physical(new_height).max(1))
+                } else {
+                    (physical(width).max(1), physical(height).max(1))
+                };
+                if canvas.width() != backing_width || canvas.height() != backing_height {
+                    canvas.set_width(backing_width);
+                    canvas.set_height(backing_height);
+                }
 
-            canvas
-                .set_attribute(
-                    "style",
-                    format!(
-                        "width: {}px;
-                        height: {}px;
-                        position: relative;
-                        left: {}px;
-                        top: {}px; 
-                        image-rendering: -moz-crisp-edges;
-                        image-rendering: -webkit-crisp-edges;
-                        image-rendering: pixelated;
-                        image-rendering: crisp-edges;",
-                        new_width, new_height, padding_left, padding_top
+                // Supplied canvases keep their size, so the game is drawn centered inside them:
+                let (draw_x, draw_y) = if owns_canvas {
+                    (0.0, 0.0)
+                } else {
+                    context.clear_rect(
+                        0.0,
+                        0.0,
+                        f64::from(backing_width),
+                        f64::from(backing_height),
+                    );
+                    (
+                        f64::from(physical(padding_left)),
+                        f64::from(physical(padding_top)),
                     )
-                    .as_str(),
-                )
-                .unwrap();
+                };
+                context.set_image_smoothing_enabled(false);
+                context
+                    .draw_image_with_html_canvas_element_and_dw_and_dh(
+                        &buffer,
+                        draw_x,
+                        draw_y,
+                        f64::from(physical(new_width)),
+                        f64::from(physical(new_height)),
+                    )
+                    .unwrap();
+
+                if let Some((mouse_x, mouse_y)) = mouse_inner.get() {
+                    let css_size = if owns_canvas {
+                        (new_width, new_height)
+                    } else {
+                        (width, height)
+                    };
+                    let viewport = Rect::new(
+                        draw_x as i32,
+                        draw_y as i32,
+                        physical(new_width) as i32,
+                        physical(new_height) as i32,
+                    );
+                    draw_pointer(
+                        &context,
+                        (mouse_x as f32, mouse_y as f32),
+                        (css_size.0 as u32, css_size.1 as u32),
+                        (backing_width, backing_height),
+                        viewport,
+                        (render_width, render_height),
+                    );
+                }
+
+                if owns_canvas {
+                    canvas
+                        .set_attribute(
+                            "style",
+                            format!(
+                                "width: {}px;
+                                height: {}px;
+                                position: relative;
+                                left: {}px;
+                                top: {}px; 
+                                image-rendering: -moz-crisp-edges;
+                                image-rendering: -webkit-crisp-edges;
+                                image-rendering: pixelated;
+                                image-rendering: crisp-edges;",
+                                new_width, new_height, padding_left, padding_top
+                            )
+                            .as_str(),
+                        )
+                        .unwrap();
+                }
+            }
         }
 
-        request_animation_frame(animation_closure_inner.borrow().as_ref().unwrap());
+        animation_frame_inner.set(request_animation_frame(
+            animation_closure_inner.borrow().as_ref().unwrap(),
+        ));
     }) as Box<FnMut()>));
 
-    request_animation_frame(animation_closure.borrow().as_ref().unwrap());
+    animation_frame.set(request_animation_frame(
+        animation_closure.borrow().as_ref().unwrap(),
+    ));
 
-    Ok(())
+    let created_canvas = if owns_canvas {
+        Some((element_handle, canvas_handle))
+    } else {
+        None
+    };
+
+    Ok(Romy {
+        volume,
+        samples,
+        audio_buffer_size: options.audio_buffer_size,
+        button_remap,
+        running: RefCell::new(Some(Running {
+            listeners,
+            animation_closure,
+            animation_frame,
+            audio,
+            romy_game,
+            created_canvas,
+        })),
+    })
 }
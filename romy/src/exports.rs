@@ -7,7 +7,7 @@ use std::collections::HashMap;
 /// Exports the api version, in the case of breaking api changes the runtime should be able to adapt
 #[no_mangle]
 extern "C" fn romy_api_version() -> i32 {
-    1
+    romy_core::API_VERSION
 }
 
 /// Allocate some WASM accessible memory for use by the runtime
@@ -22,7 +22,8 @@ extern "C" fn allocate(size: i32) -> *mut u8 {
     game.allocate(size)
 }
 
-/// Deallocate memory previously allocated by allocate()
+/// Deallocate memory previously allocated by allocate(), unknown or already freed pointers are
+/// ignored
 ///
 /// # Arguments
 /// * `pointer` - The number of bytes to allocate.
@@ -32,6 +33,17 @@ extern "C" fn deallocate(pointer: *const u8) {
     game.deallocate(pointer)
 }
 
+/// Hands the game its launch arguments, optional for the runtime to call, but if it does it must
+/// be before init()
+///
+/// # Arguments
+/// * `pointer` - A pointer to a Vec<u8> encoded via romy_core::serial::encode_with_size
+#[no_mangle]
+extern "C" fn set_launch_arguments(pointer: *const u8) {
+    let game = unsafe { &mut ROOT };
+    game.set_launch_arguments(pointer)
+}
+
 /// Steps the game forward
 ///
 /// # Arguments
@@ -56,6 +68,19 @@ extern "C" fn draw(pointer: *const u8) -> *const u8 {
     game.draw(pointer)
 }
 
+/// Renders a stack of layers for the runtime to composite
+///
+/// # Arguments
+/// * `pointer` - A pointer to a romy::DrawArguments structure encoded via 
+/// romy_core::serial::encode_with_size
+/// 
+/// Returns a Vec<romy::Layer> encoded with romy_core::serial::encode_with_size
+#[no_mangle]
+extern "C" fn draw_layers(pointer: *const u8) -> *const u8 {
+    let game = unsafe { &mut ROOT };
+    game.draw_layers(pointer)
+}
+
 /// Renders a steps worth of audio of the game
 ///
 /// # Arguments
@@ -69,6 +94,85 @@ extern "C" fn render_audio(pointer: *const u8) -> *const u8 {
     game.render_audio(pointer)
 }
 
+/// Renders a steps worth of audio of the game as separate voices
+///
+/// # Arguments
+/// * `pointer` - A pointer to a romy::RenderAudioArguments structure encoded via 
+/// romy_core::serial::encode_with_size
+/// 
+/// Returns a Vec<romy::Voice> encoded with romy_core::serial::encode_with_size
+#[no_mangle]
+extern "C" fn render_voices(pointer: *const u8) -> *const u8 {
+    let game = unsafe { &mut ROOT };
+    game.render_voices(pointer)
+}
+
+/// Asks the game if the controllers should rumble
+///
+/// Returns a Option<romy::Rumble> encoded with romy_core::serial::encode_with_size
+#[no_mangle]
+extern "C" fn rumble() -> *const u8 {
+    let game = unsafe { &mut ROOT };
+    game.rumble()
+}
+
+/// Asks the game for a title to show in place of its name
+///
+/// Returns a Option<String> encoded with romy_core::serial::encode_with_size
+#[no_mangle]
+extern "C" fn title() -> *const u8 {
+    let game = unsafe { &mut ROOT };
+    game.title()
+}
+
+/// Gets the message of the panic that stopped the game, for the runtime to call after a trap
+///
+/// Returns a String encoded with romy_core::serial::encode_with_size, or null if the game hasn't
+/// panicked. The memory stays owned by the game, it must not be deallocated.
+#[no_mangle]
+extern "C" fn panic_message() -> *const u8 {
+    match unsafe { &PANIC_MESSAGE } {
+        Some(message) => message.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+/// The last panic message, already encoded so handing it over after a trap doesn't allocate
+static mut PANIC_MESSAGE: Option<Vec<u8>> = None;
+
+/// Gets why the game failed to start, for the runtime to call when init() returns null
+///
+/// Returns a String encoded with romy_core::serial::encode_with_size, or null if the game started.
+/// The memory stays owned by the game, it must not be deallocated.
+#[no_mangle]
+extern "C" fn init_error() -> *const u8 {
+    match unsafe { &INIT_ERROR } {
+        Some(error) => error.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+/// Why the game failed to start, see connect_result()
+static mut INIT_ERROR: Option<Vec<u8>> = None;
+
+/// Keeps the reason the game failed to start for init_error()
+pub fn set_init_error(reason: &str) {
+    unsafe {
+        INIT_ERROR = Some(encode_with_size(&reason));
+    }
+}
+
+/// Replaces the panic hook with one that keeps the message for panic_message(), a panic in a
+/// game ends in a trap and the runtime has no other way of finding out what went wrong
+pub fn capture_panics() {
+    std::panic::set_hook(Box::new(|info| {
+        let message = encode_with_size(&info.to_string());
+        unsafe {
+            PANIC_MESSAGE = Some(message);
+        }
+    }));
+}
+
 lazy_static! {
     static ref DATA: MutStatic<MemoryAllocator> = { MutStatic::from(MemoryAllocator::new()) };
 }
@@ -111,17 +215,27 @@ impl MemoryAllocator {
         result
     }
     fn deallocate(&mut self, pointer: *const u8) {
-        let id = pointer as usize;
-        self.external_memory.remove(&self.id_map[&id]).unwrap();
-        self.id_map.remove(&id).unwrap();
+        // Pointers come from the host, so quietly ignore any that were never allocated or have
+        // already been freed rather than bringing the whole game down:
+        if let Some(id) = self.id_map.remove(&(pointer as usize)) {
+            self.external_memory.remove(&id);
+        }
     }
 }
 
-pub static mut ROOT: Root = Root { game: None };
+pub static mut ROOT: Root = Root {
+    game: None,
+    launch_arguments: Vec::new(),
+    images: None,
+    sounds: None,
+};
 
 /// Used as a connection from exported functions to a Game
 pub struct Root {
     game: Option<Box<Game>>,
+    launch_arguments: Vec<u8>,
+    images: Option<ImagePool>,
+    sounds: Option<SoundPool>,
 }
 
 impl Root {
@@ -129,6 +243,21 @@ impl Root {
     pub fn connect(&mut self, game: Box<super::Game>) {
         self.game = Some(game);
     }
+    /// The arguments given by the runtime, empty if it didn't supply any
+    pub fn launch_arguments(&self) -> &[u8] {
+        &self.launch_arguments
+    }
+    /// Pool that images are recycled into once they have been handed to the host
+    pub fn images(&mut self) -> &mut ImagePool {
+        self.images.get_or_insert_with(ImagePool::new)
+    }
+    /// Pool that sounds are recycled into once they have been handed to the host
+    pub fn sounds(&mut self) -> &mut SoundPool {
+        self.sounds.get_or_insert_with(SoundPool::new)
+    }
+    fn set_launch_arguments(&mut self, pointer: *const u8) {
+        self.launch_arguments = unsafe { decode_with_size_ptr(pointer) };
+    }
     fn step(&mut self, pointer: *const u8) {
         let step_input: StepArguments = unsafe { decode_with_size_ptr(pointer) };
 
@@ -137,26 +266,92 @@ impl Root {
             return;
         }
 
-        panic!();
+        panic!("no game is connected, see romy_main!()");
     }
     fn draw(&mut self, pointer: *const u8) -> *const u8 {
         let draw_input: DrawArguments = unsafe { decode_with_size_ptr(pointer) };
 
         if let Some(app) = &mut self.game {
             let image = app.draw(&draw_input);
-            return move_ownership_to_host(image);
+            let pointer = move_ownership_to_host(&image);
+            self.images().recycle(image);
+            return pointer;
+        }
+
+        panic!("no game is connected, see romy_main!()");
+    }
+    fn draw_layers(&mut self, pointer: *const u8) -> *const u8 {
+        let draw_input: DrawArguments = unsafe { decode_with_size_ptr(pointer) };
+
+        if let Some(app) = &mut self.game {
+            let layers = app.draw_layers(&draw_input);
+            let pointer = move_ownership_to_host(&layers);
+            for layer in layers {
+                self.images().recycle(layer.into_image());
+            }
+            return pointer;
         }
 
-        panic!();
+        panic!("no game is connected, see romy_main!()");
     }
     fn render_audio(&mut self, pointer: *const u8) -> *const u8 {
         let render_audio_input: RenderAudioArguments = unsafe { decode_with_size_ptr(pointer) };
 
         if let Some(app) = &mut self.game {
             let sound = app.render_audio(&render_audio_input);
-            return move_ownership_to_host(sound);
+            let pointer = move_ownership_to_host(&sound);
+            self.sounds().recycle(sound);
+            return pointer;
+        }
+
+        panic!("no game is connected, see romy_main!()");
+    }
+    fn render_voices(&mut self, pointer: *const u8) -> *const u8 {
+        let render_audio_input: RenderAudioArguments = unsafe { decode_with_size_ptr(pointer) };
+
+        if let Some(app) = &mut self.game {
+            let voices = app.render_voices(&render_audio_input);
+            let pointer = move_ownership_to_host(&voices);
+            for voice in voices {
+                self.sounds().recycle(voice.into_sound());
+            }
+            return pointer;
+        }
+
+        panic!("no game is connected, see romy_main!()");
+    }
+    fn rumble(&mut self) -> *const u8 {
+        if let Some(app) = &mut self.game {
+            return move_ownership_to_host(app.rumble());
         }
 
-        panic!();
+        panic!("no game is connected, see romy_main!()");
+    }
+    fn title(&mut self) -> *const u8 {
+        if let Some(app) = &mut self.game {
+            return move_ownership_to_host(app.title());
+        }
+
+        panic!("no game is connected, see romy_main!()");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_and_freed_pointers_are_ignored() {
+        let mut allocator = MemoryAllocator::new();
+        let pointer = allocator.allocate(16);
+        let kept = allocator.allocate(16);
+
+        allocator.deallocate(pointer);
+        allocator.deallocate(pointer);
+        allocator.deallocate(std::ptr::null());
+        allocator.deallocate(&0u8 as *const u8);
+
+        assert_eq!(allocator.external_memory.len(), 1);
+        assert_eq!(allocator.id_map.get(&(kept as usize)), Some(&1));
     }
 }
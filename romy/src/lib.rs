@@ -1,11 +1,40 @@
 mod exports;
-pub use romy_core::input::InputDeviceType;
-pub use romy_core::output::{Color, Image, Sound};
-pub use romy_core::{DrawArguments, Game, Info, RenderAudioArguments, StepArguments};
+pub mod storage;
+pub use romy_core::input::{
+    Button, ButtonSet, InputDeviceType, Key, KeyBindings, KeyCode, KeyEvent, Keyboard,
+    TRIGGER_THRESHOLD,
+};
+pub use romy_core::output::{
+    BlendMode, Color, Envelope, Image, ImagePool, Layer, Rect, Rumble, Sound, SoundPlayer,
+    SoundPool, Voice,
+};
+pub use romy_core::math;
+pub use romy_core::random::Rng;
+pub use romy_core::{
+    DrawArguments, Game, Info, PlayerStatus, RenderAudioArguments, StepArguments,
+};
 
 #[cfg(feature = "romy-engine")]
 pub use romy_engine as engine;
 
+/// Loads an Image from a .png, so an image embedded in a game with include_bytes!() can be
+/// loaded with Image::from_png_bytes()
+#[cfg(feature = "romy-engine")]
+pub trait ImageFromPng: Sized {
+    /// Decodes a .png, returning why it couldn't be decoded if it isn't a valid image
+    ///
+    /// # Arguments
+    /// * `data` - the contents of the .png file
+    fn from_png_bytes(data: &[u8]) -> Result<Self, String>;
+}
+
+#[cfg(feature = "romy-engine")]
+impl ImageFromPng for Image {
+    fn from_png_bytes(data: &[u8]) -> Result<Self, String> {
+        engine::try_decode_png(data)
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub use romy_sdl::run_standalone;
 
@@ -20,6 +49,7 @@ macro_rules! romy_main {
         #[cfg(target_arch = "wasm32")]
         #[no_mangle]
         pub unsafe extern "C" fn init() -> *mut u8 {
+            $crate::capture_panics();
             connect(Box::new($y), $x)
         }
         #[cfg(not(target_arch = "wasm32"))]
@@ -29,9 +59,111 @@ macro_rules! romy_main {
     };
 }
 
+/// Sets up the main() function for each build target, for games whose creation can fail. The
+/// second argument gives a Result, the error can be anything that can be displayed and is
+/// reported by the runtime as the reason the game failed to start.
+///
+/// ```ignore
+/// romy_try_main!(Info::new("Game", 60, 1, InputDeviceType::Nes), Game::load());
+/// ```
+#[macro_export]
+macro_rules! romy_try_main {
+    ($x:expr, $y:expr) => {
+        #[cfg(target_arch = "wasm32")]
+        fn main() -> Result<(), String> {
+            Ok(())
+        }
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub unsafe extern "C" fn init() -> *mut u8 {
+            $crate::capture_panics();
+            let game = $y
+                .map(|game| Box::new(game) as Box<dyn Game>)
+                .map_err(|error| error.to_string());
+            connect_result(game, $x)
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        fn main() -> Result<(), String> {
+            let game = $y.map_err(|error| format!("failed to start: {}", error))?;
+            run_standalone(Box::new(game), $x)
+        }
+    };
+}
+
+/// Gets the arguments the game was launched with, this lets a single game behave differently
+/// depending on how it is started, with a chosen level or random seed for example. Call it while
+/// constructing the game, the arguments are set before that happens.
+///
+/// What the bytes mean is up to the game, the romy command line passes anything given after `--`
+/// as a UTF-8 string of space separated arguments, so `romy game.wasm -- --seed 42` gives
+/// `--seed 42`. When running standalone the process arguments are used in the same way.
+pub fn launch_arguments() -> Vec<u8> {
+    if cfg!(target_arch = "wasm32") {
+        let romy = unsafe { &exports::ROOT };
+        romy.launch_arguments().to_vec()
+    } else {
+        let arguments: Vec<String> = std::env::args().skip(1).collect();
+        arguments.join(" ").into_bytes()
+    }
+}
+
+/// Gets an image to draw into, prefer this to Image::new() in Game::draw(). Once an image returned
+/// from draw has been handed to the runtime it is recycled, so after the first couple of frames
+/// this reuses memory instead of allocating a new image every frame. See ImagePool.
+///
+/// # Arguments
+/// * `width` - the number of horizontal pixels
+/// * `height` - the number of vertical pixels.
+/// * `color` - the color of all pixels in the image.
+pub fn image(width: i32, height: i32, color: Color) -> Image {
+    let romy = unsafe { &mut exports::ROOT };
+    romy.images().take(width, height, color)
+}
+
+/// Gets a silent sound to render audio into, prefer this to Sound::with_buffer_size() in
+/// Game::render_audio(). Like image(), sounds are recycled once they have been handed to the
+/// runtime, so this reuses memory instead of allocating every step. See SoundPool.
+///
+/// # Arguments
+/// * `sample_rate` - the number of samples per second
+/// * `sample_count` - the number of samples
+pub fn sound(sample_rate: i32, sample_count: i32) -> Sound {
+    let romy = unsafe { &mut exports::ROOT };
+    romy.sounds().take(sample_rate, sample_count)
+}
+
+/// Gets a copy of a sound to return from Game::render_audio(), prefer this to cloning the sound.
+/// See sound().
+///
+/// # Arguments
+/// * `source` - the sound to copy
+pub fn sound_copy(source: &Sound) -> Sound {
+    let romy = unsafe { &mut exports::ROOT };
+    romy.sounds().take_copy(source)
+}
+
+/// Keeps the message of any panic so the runtime can report it, called by romy_main!() before the
+/// game is created
+#[doc(hidden)]
+pub fn capture_panics() {
+    exports::capture_panics();
+}
+
 /// Connects a Game to the Wasm erxports
 pub fn connect(game: Box<Game>, info: Info) -> *mut u8 {
     let romy = unsafe { &mut exports::ROOT };
     romy.connect(game);
     exports::move_ownership_to_host(info)
 }
+
+/// Connects a Game that might have failed to be created to the Wasm exports. On failure null is
+/// returned from init() and the runtime reports the reason instead of running the game.
+pub fn connect_result(game: Result<Box<dyn Game>, String>, info: Info) -> *mut u8 {
+    match game {
+        Ok(game) => connect(game, info),
+        Err(reason) => {
+            exports::set_init_error(&reason);
+            std::ptr::null_mut()
+        }
+    }
+}
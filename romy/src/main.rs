@@ -1,8 +1,183 @@
 //#![windows_subsystem = "windows"]
 
-use clap::{App, Arg};
-use romy_wasmer::load;
-use romy_sdl::run;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use romy_wasmer::load_with_arguments;
+use romy_core::input::{AxisInversion, ButtonRemap, KeyBindings};
+use romy_core::runtime::{FramePacing, MemoryLimitAction, RunBundle, TestHarness};
+use romy_sdl::{audio_devices, default_button_remap, run_with_options, RunOptions};
+use std::time::{Duration, Instant};
+
+/// Reads a positive whole number argument, warning and returning None if it's not valid
+fn positive_arg(matches: &ArgMatches, name: &str) -> Option<u32> {
+    let value = matches.value_of(name)?;
+    match value.parse::<u32>() {
+        Ok(value) if value > 0 => Some(value),
+        _ => {
+            eprintln!("Ignoring invalid --{} value: {}", name, value);
+            None
+        }
+    }
+}
+
+/// Reads an argument made of two positive whole numbers, like an aspect ratio written as
+/// width:height, warning and returning None if it's not valid
+fn pair_arg(matches: &ArgMatches, name: &str, separator: char) -> Option<(u32, u32)> {
+    let value = matches.value_of(name)?;
+    let mut parts = value.splitn(2, separator).map(str::parse::<u32>);
+    match (parts.next(), parts.next()) {
+        (Some(Ok(width)), Some(Ok(height))) if width > 0 && height > 0 => Some((width, height)),
+        _ => {
+            eprintln!("Ignoring invalid --{} value: {}", name, value);
+            None
+        }
+    }
+}
+
+/// Reads a gamepad button map from the file named by an argument, warning and returning None if it
+/// can't be read or has a mistake
+fn button_remap_arg(matches: &ArgMatches, name: &str) -> Option<ButtonRemap> {
+    let path = matches.value_of(name)?;
+    let config = match std::fs::read_to_string(path) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Ignoring --{} file {}: {}", name, path, error);
+            return None;
+        }
+    };
+
+    let mut remap = default_button_remap();
+    match remap.apply(&config) {
+        Ok(()) => Some(remap),
+        Err(error) => {
+            eprintln!("Ignoring --{} file {}: {}", name, path, error);
+            None
+        }
+    }
+}
+
+/// Reads keyboard bindings from the file named by an argument, warning and returning None if it
+/// can't be read or has a mistake
+fn key_bindings_arg(matches: &ArgMatches, name: &str) -> Option<KeyBindings> {
+    let path = matches.value_of(name)?;
+    let config = match std::fs::read_to_string(path) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Ignoring --{} file {}: {}", name, path, error);
+            return None;
+        }
+    };
+
+    let mut bindings = KeyBindings::default();
+    match bindings.apply(&config) {
+        Ok(()) => Some(bindings),
+        Err(error) => {
+            eprintln!("Ignoring --{} file {}: {}", name, path, error);
+            None
+        }
+    }
+}
+
+/// Loads a game, printing why if it fails
+fn load_or_report(path: &str, arguments: &[u8]) -> Option<RunBundle> {
+    match load_with_arguments(path, arguments) {
+        Ok(bundle) => Some(bundle),
+        Err(error) => {
+            eprintln!("Failed to load {}: {}", path, error);
+            None
+        }
+    }
+}
+
+/// Prints the info of a game without running it
+fn print_info(path: &str) {
+    let bundle = match load_or_report(path, &[]) {
+        Some(bundle) => bundle,
+        None => std::process::exit(1),
+    };
+    let info = &bundle.info;
+
+    println!("name: {}", info.name());
+    println!(
+        "step interval: {}ns ({:.2} steps per second)",
+        info.step_interval(),
+        1_000_000_000.0 / f64::from(info.step_interval())
+    );
+    println!("audio steps: {}", info.audio_steps());
+    println!("players: {}", info.number_of_players());
+    for player in 0..info.number_of_players() {
+        if let Some(input) = info.player_input(player) {
+            println!("  player {}: {:?}", player + 1, input);
+        }
+    }
+}
+
+/// Converts a duration to fractional seconds
+fn seconds(duration: Duration) -> f64 {
+    duration.as_nanos() as f64 / 1_000_000_000.0
+}
+
+/// Runs a game as fast as possible without a window or audio, then prints timings as key=value
+/// lines so they can be tracked by scripts
+///
+/// # Arguments
+/// * `path` - The game file to load
+/// * `steps` - How many times to step, draw and render audio
+/// * `width` - The width passed to each draw
+/// * `height` - The height passed to each draw
+fn bench(path: &str, steps: u32, width: i32, height: i32) {
+    let load_start = Instant::now();
+    let bundle = match load_or_report(path, &[]) {
+        Some(bundle) => bundle,
+        None => std::process::exit(1),
+    };
+    let load_time = load_start.elapsed();
+
+    let mut harness = TestHarness::new(bundle);
+    let mut step_time = Duration::default();
+    let mut draw_time = Duration::default();
+    let mut audio_time = Duration::default();
+
+    let start = Instant::now();
+    for _ in 0..steps {
+        let call_start = Instant::now();
+        harness.step();
+        step_time += call_start.elapsed();
+
+        let call_start = Instant::now();
+        harness.draw(width, height);
+        draw_time += call_start.elapsed();
+
+        let call_start = Instant::now();
+        harness.render_audio();
+        audio_time += call_start.elapsed();
+    }
+    let total_time = start.elapsed();
+
+    let average_micros = |time: Duration| seconds(time) * 1_000_000.0 / f64::from(steps);
+    println!("load_seconds={:.6}", seconds(load_time));
+    println!("steps={}", steps);
+    println!("draw_size={}x{}", width, height);
+    println!("total_seconds={:.6}", seconds(total_time));
+    println!("steps_per_second={:.2}", f64::from(steps) / seconds(total_time));
+    println!("step_average_us={:.3}", average_micros(step_time));
+    println!("draw_average_us={:.3}", average_micros(draw_time));
+    println!("render_audio_average_us={:.3}", average_micros(audio_time));
+}
+
+/// Prints the names of the audio devices a game can be played through
+fn print_audio_devices() {
+    match audio_devices() {
+        Ok(devices) => {
+            for device in devices {
+                println!("{}", device);
+            }
+        }
+        Err(error) => {
+            eprintln!("Romy: couldn't list audio devices, {}", error);
+            std::process::exit(1);
+        }
+    }
+}
 
 fn main() {
     let matches = App::new("romy")
@@ -13,11 +188,240 @@ fn main() {
                 .index(1)
                 .required(false),
         )
+        .arg(
+            Arg::with_name("width")
+                .help("initial width of the window")
+                .long("width")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("height")
+                .help("initial height of the window")
+                .long("height")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .help("initial size of the window as a multiple of 320x240")
+                .long("scale")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("aspect")
+                .help("keep the window at an aspect ratio when resizing, for example 4:3")
+                .long("aspect")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("resolution")
+                .help("fixed size the game draws at and is scaled up from, for example 256x240")
+                .long("resolution")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-drop")
+                .help("ignore games dropped onto the window instead of loading them")
+                .long("no-drop"),
+        )
+        .arg(
+            Arg::with_name("fullscreen")
+                .help("start in fullscreen mode")
+                .long("fullscreen"),
+        )
+        .arg(
+            Arg::with_name("audio-buffer")
+                .help("number of audio samples requested at a time, raise this if sound crackles")
+                .long("audio-buffer")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("audio-device")
+                .help("name of the audio device to play through, see the audio-devices command")
+                .long("audio-device")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("button-map")
+                .help("file of button=number lines that remap gamepad buttons, for example a=1")
+                .long("button-map")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("key-bindings")
+                .help("file of button=keys lines that rebind the keyboard, for example a=k x")
+                .long("key-bindings")
+                .takes_value(true)
+                .conflicts_with("split-keyboard"),
+        )
+        .arg(
+            Arg::with_name("invert-axes")
+                .help("stick axes to invert, for example left_stick_y,right_stick_y")
+                .long("invert-axes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("memory-limit")
+                .help("megabytes of memory a game can use before a warning is printed")
+                .long("memory-limit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("memory-limit-halt")
+                .help("stop games that go over the memory limit instead of warning")
+                .long("memory-limit-halt"),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .help("reload the game whenever its file changes, keeping its state if possible")
+                .long("watch"),
+        )
+        .arg(
+            Arg::with_name("step-rate")
+                .help("steps per second to run the game at instead of its own rate, for debugging")
+                .long("step-rate")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("split-keyboard")
+                .help("share the keyboard between two players, one on WASD and one on the arrows")
+                .long("split-keyboard"),
+        )
+        .arg(
+            Arg::with_name("smooth-frames")
+                .help("even out the time between frames, for smoother motion when vsync jitters")
+                .long("smooth-frames"),
+        )
+        .arg(
+            Arg::with_name("show-pointer")
+                .help("debugging aid, draws a crosshair where the mouse lands in the game")
+                .long("show-pointer"),
+        )
+        .arg(
+            Arg::with_name("arguments")
+                .help("launch arguments passed on to the game, given after --")
+                .multiple(true)
+                .last(true),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("prints information about a game without running it")
+                .arg(
+                    Arg::with_name("input")
+                        .help("the game file to load")
+                        .index(1)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("audio-devices")
+                .about("prints the names of the audio devices usable with --audio-device"),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("runs a game as fast as possible without a window and prints timings")
+                .arg(
+                    Arg::with_name("input")
+                        .help("the game file to load")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("steps")
+                        .help("number of times to step, draw and render audio, 1000 by default")
+                        .long("steps")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("width")
+                        .help("width to draw at, 320 by default")
+                        .long("width")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("height")
+                        .help("height to draw at, 240 by default")
+                        .long("height")
+                        .takes_value(true),
+                ),
+        )
         .get_matches();
 
-    if let Some(path) = matches.value_of("input") {
-        run(load(&path), |path| load(path)).unwrap();
-    } else {
-        run(None, |path| load(path)).unwrap();
+    if let Some(matches) = matches.subcommand_matches("info") {
+        print_info(matches.value_of("input").unwrap());
+        return;
+    }
+
+    if matches.subcommand_matches("audio-devices").is_some() {
+        print_audio_devices();
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("bench") {
+        bench(
+            matches.value_of("input").unwrap(),
+            positive_arg(matches, "steps").unwrap_or(1000),
+            positive_arg(matches, "width").unwrap_or(320) as i32,
+            positive_arg(matches, "height").unwrap_or(240) as i32,
+        );
+        return;
+    }
+
+    let mut options = RunOptions::default();
+    if let Some(scale) = positive_arg(&matches, "scale") {
+        options.window_width = scale.saturating_mul(320);
+        options.window_height = scale.saturating_mul(240);
+    }
+    if let Some(width) = positive_arg(&matches, "width") {
+        options.window_width = width;
+    }
+    if let Some(height) = positive_arg(&matches, "height") {
+        options.window_height = height;
+    }
+    options.aspect_ratio = pair_arg(&matches, "aspect", ':');
+    options.internal_resolution = pair_arg(&matches, "resolution", 'x');
+    options.fullscreen = matches.is_present("fullscreen");
+    options.drop_to_load = !matches.is_present("no-drop");
+    if let Some(audio_buffer) = positive_arg(&matches, "audio-buffer") {
+        options.audio_buffer_size = audio_buffer.min(u32::from(u16::MAX)) as u16;
+    }
+    options.audio_device = matches.value_of("audio-device").map(str::to_string);
+    if let Some(button_remap) = button_remap_arg(&matches, "button-map") {
+        options.button_remap = button_remap;
+    }
+    if let Some(key_bindings) = key_bindings_arg(&matches, "key-bindings") {
+        options.key_bindings = key_bindings;
+    }
+    if let Some(invert_axes) = matches.value_of("invert-axes") {
+        match AxisInversion::parse(invert_axes) {
+            Ok(axis_inversion) => options.axis_inversion = axis_inversion,
+            Err(error) => eprintln!("Ignoring invalid --invert-axes value: {}", error),
+        }
+    }
+    if let Some(memory_limit) = positive_arg(&matches, "memory-limit") {
+        options.memory_limit.max_bytes = (memory_limit as usize).saturating_mul(1024 * 1024);
+    }
+    if matches.is_present("memory-limit-halt") {
+        options.memory_limit.action = MemoryLimitAction::Halt;
+    }
+
+    let arguments = match matches.values_of("arguments") {
+        Some(values) => values.collect::<Vec<_>>().join(" ").into_bytes(),
+        None => Vec::new(),
+    };
+
+    options.game_path = matches.value_of("input").map(str::to_string);
+    options.hot_reload = matches.is_present("watch");
+    options.show_pointer = matches.is_present("show-pointer");
+    options.step_rate = positive_arg(&matches, "step-rate");
+    options.split_keyboard = matches.is_present("split-keyboard");
+    if matches.is_present("smooth-frames") {
+        options.frame_pacing = FramePacing::Smoothed;
     }
+
+    let bundle = match matches.value_of("input") {
+        Some(path) => load_or_report(path, &arguments),
+        None => None,
+    };
+
+    run_with_options(bundle, |path| load_or_report(path, &arguments), options).unwrap();
 }
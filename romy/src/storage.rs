@@ -0,0 +1,76 @@
+//! Saving and loading small amounts of data that last between runs of a game, like high scores or
+//! progress. Data is stored by the runtime, on disk for native runtimes and in the browsers local
+//! storage on the web.
+
+#[cfg(target_arch = "wasm32")]
+use romy_core::serial::encode_with_size;
+
+#[cfg(target_arch = "wasm32")]
+extern "C" {
+    fn storage_size(key: *const u8) -> i32;
+    fn storage_read(key: *const u8, buffer: *mut u8);
+    fn storage_write(key: *const u8, data: *const u8) -> i32;
+}
+
+/// Saves some data under a key, replacing anything already saved there. Returns false if the
+/// runtime couldn't save it.
+///
+/// # Arguments
+/// * `key` - The key to save under, 1 - 64 letters, numbers, dashes and underscores, other keys
+///   aren't saved
+/// * `data` - The data to save, at most 1MiB
+#[cfg(target_arch = "wasm32")]
+pub fn save(key: &str, data: &[u8]) -> bool {
+    let key = encode_with_size(&key);
+    let data = encode_with_size(&data);
+    unsafe { storage_write(key.as_ptr(), data.as_ptr()) != 0 }
+}
+
+/// Loads the data saved under a key, None if nothing has been saved
+///
+/// # Arguments
+/// * `key` - The key the data was saved under
+#[cfg(target_arch = "wasm32")]
+pub fn load(key: &str) -> Option<Vec<u8>> {
+    let key = encode_with_size(&key);
+    let size = unsafe { storage_size(key.as_ptr()) };
+    if size < 0 {
+        return None;
+    }
+
+    let mut buffer = vec![0; size as usize];
+    unsafe { storage_read(key.as_ptr(), buffer.as_mut_ptr()) };
+    Some(buffer)
+}
+
+/// Saves some data under a key, replacing anything already saved there. Returns false if the
+/// runtime couldn't save it.
+///
+/// # Arguments
+/// * `key` - The key to save under, 1 - 64 letters, numbers, dashes and underscores, other keys
+///   aren't saved
+/// * `data` - The data to save, at most 1MiB
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save(key: &str, data: &[u8]) -> bool {
+    native_storage().save(key, data).is_ok()
+}
+
+/// Loads the data saved under a key, None if nothing has been saved
+///
+/// # Arguments
+/// * `key` - The key the data was saved under
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load(key: &str) -> Option<Vec<u8>> {
+    native_storage().load(key)
+}
+
+/// Storage for games running standalone, named after the executable like a game run through the
+/// romy command line is named after its file
+#[cfg(not(target_arch = "wasm32"))]
+fn native_storage() -> romy_core::runtime::Storage {
+    let name = std::env::current_exe()
+        .ok()
+        .and_then(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "game".to_string());
+    romy_core::runtime::Storage::for_game(&name)
+}